@@ -4,22 +4,58 @@
 
 //! Nostr Database Helper
 
-use std::cmp::Ordering;
-use std::collections::{BTreeSet, HashMap, HashSet};
-use std::iter;
-use std::ops::Deref;
+use std::borrow::Borrow;
+use std::cell::{Cell, RefCell};
+use std::cmp::{Ordering, Reverse};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Bound, Deref, DerefMut, RangeInclusive};
+use std::str::FromStr;
+use std::sync::atomic::{self, AtomicUsize};
 use std::sync::Arc;
+use std::time::Duration;
+use std::{iter, mem, slice};
 
+use im::{ordset, OrdMap, OrdSet, Vector};
+use nostr::event::TagsIndexes;
 use nostr::nips::nip01::Coordinate;
-use nostr::{Alphabet, Event, EventId, Filter, Kind, PublicKey, SingleLetterTag, Timestamp};
-use tokio::sync::RwLock;
+use nostr::types::time::Instant;
+use nostr::{
+    Alphabet, Event, EventId, Filter, JsonUtil, Kind, PublicKey, SingleLetterTag, Timestamp, Url,
+};
+#[cfg(feature = "parking_lot")]
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use thiserror::Error;
+#[cfg(not(feature = "parking_lot"))]
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use crate::tree::{BTreeCappedSet, Capacity, InsertResult, OverCapacityPolicy};
 use crate::Order;
 
+/// Sort key of the indexed events (see [`event_sort_key`])
+type SortKey = (Reverse<Timestamp>, EventId);
+
+/// Events sorted like the query results (newest first)
+///
+/// Persistent set: cloning it is `O(1)` and the nodes are shared until modified (see [`DatabaseHelper::snapshot`]).
+type EventSet = OrdSet<DatabaseEvent>;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct DatabaseEvent {
     event: Arc<Event>,
+    key: SortKey,
+}
+
+impl DatabaseEvent {
+    #[inline]
+    fn new(event: Event) -> Self {
+        Self {
+            key: (Reverse(event.created_at), event.id),
+            event: Arc::new(event),
+        }
+    }
 }
 
 impl PartialOrd for DatabaseEvent {
@@ -30,10 +66,44 @@ impl PartialOrd for DatabaseEvent {
 }
 
 impl Ord for DatabaseEvent {
+    /// Newest events first. If the timestamps are the same, the event with the lowest ID comes first (NIP-01).
     #[inline]
     fn cmp(&self, other: &Self) -> Ordering {
-        self.event.cmp(&other.event).reverse()
+        self.key.cmp(&other.key)
+    }
+}
+
+impl Borrow<SortKey> for DatabaseEvent {
+    /// Allow to look up the sets of events by time window (see [`sort_key_window`])
+    #[inline]
+    fn borrow(&self) -> &SortKey {
+        &self.key
+    }
+}
+
+/// Range of the sort keys of the events created between `since` and `until` (both included)
+///
+/// Return `None` if the window is empty.
+fn sort_key_window(
+    since: Option<Timestamp>,
+    until: Option<Timestamp>,
+) -> Option<(Bound<SortKey>, Bound<SortKey>)> {
+    if let (Some(since), Some(until)) = (since, until) {
+        if since > until {
+            return None;
+        }
     }
+
+    // Newest first: the window starts at `until`
+    let start: Bound<SortKey> = match until {
+        Some(until) => Bound::Included((Reverse(until), EventId::all_zeros())),
+        None => Bound::Unbounded,
+    };
+    let end: Bound<SortKey> = match since {
+        Some(since) => Bound::Included((Reverse(since), EventId::owned([u8::MAX; EventId::LEN]))),
+        None => Bound::Unbounded,
+    };
+    Some((start, end))
 }
 
 impl Deref for DatabaseEvent {
@@ -51,6 +121,80 @@ struct QueryByAuthorParams {
     until: Option<Timestamp>,
 }
 
+struct QueryByAuthorsParams {
+    authors: HashSet<PublicKey>,
+    since: Option<Timestamp>,
+    until: Option<Timestamp>,
+}
+
+/// Lazy union of sets of events, keeping the order (newest first)
+///
+/// Events in more sets are returned once.
+struct MergeIter<'a> {
+    iters: Vec<ordset::RangedIter<'a, DatabaseEvent>>,
+    heap: BinaryHeap<Reverse<(&'a DatabaseEvent, usize)>>,
+}
+
+impl<'a> MergeIter<'a> {
+    #[inline]
+    fn new<I>(sets: I) -> Self
+    where
+        I: IntoIterator<Item = &'a EventSet>,
+    {
+        Self::with_window(sets, None, None)
+    }
+
+    /// Merge only the events created between `since` and `until` (both included)
+    ///
+    /// Each set is cut to the window with a range lookup (`O(log n)`), so the events outside it are never visited.
+    fn with_window<I>(sets: I, since: Option<Timestamp>, until: Option<Timestamp>) -> Self
+    where
+        I: IntoIterator<Item = &'a EventSet>,
+    {
+        let iters: Vec<ordset::RangedIter<'a, DatabaseEvent>> = match sort_key_window(since, until)
+        {
+            Some(window) => sets.into_iter().map(|set| set.range(window)).collect(),
+            None => Vec::new(),
+        };
+        let mut merge = Self {
+            heap: BinaryHeap::with_capacity(iters.len()),
+            iters,
+        };
+
+        for index in 0..merge.iters.len() {
+            merge.advance(index);
+        }
+
+        merge
+    }
+
+    fn advance(&mut self, index: usize) {
+        if let Some(ev) = self.iters[index].next() {
+            self.heap.push(Reverse((ev, index)));
+        }
+    }
+}
+
+impl<'a> Iterator for MergeIter<'a> {
+    type Item = &'a DatabaseEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((ev, index)) = self.heap.pop()?;
+        self.advance(index);
+
+        // Skip the same event in the other sets
+        while let Some(Reverse((other, other_index))) = self.heap.peek().copied() {
+            if other.id != ev.id {
+                break;
+            }
+            self.heap.pop();
+            self.advance(other_index);
+        }
+
+        Some(ev)
+    }
+}
+
 struct QueryByKindAndAuthorParams {
     kind: Kind,
     author: PublicKey,
@@ -93,11 +237,30 @@ impl QueryByParamReplaceable {
 
 enum QueryPattern {
     Author(QueryByAuthorParams),
+    Authors(QueryByAuthorsParams),
     KindAuthor(QueryByKindAndAuthorParams),
     ParamReplaceable(QueryByParamReplaceable),
+    Ids(Box<Filter>),
+    Tags(Box<Filter>),
+    Search(Box<Filter>),
     Generic(Box<Filter>),
 }
 
+impl QueryPattern {
+    fn index(&self) -> QueryIndex {
+        match self {
+            Self::Author(..) => QueryIndex::Author,
+            Self::Authors(..) => QueryIndex::Authors,
+            Self::KindAuthor(..) => QueryIndex::KindAuthor,
+            Self::ParamReplaceable(..) => QueryIndex::ParamReplaceable,
+            Self::Ids(..) => QueryIndex::Ids,
+            Self::Tags(..) => QueryIndex::Tags,
+            Self::Search(..) => QueryIndex::Search,
+            Self::Generic(..) => QueryIndex::Scan,
+        }
+    }
+}
+
 impl From<Filter> for QueryPattern {
     fn from(filter: Filter) -> Self {
         let (kinds_len, first_kind): (usize, Option<Kind>) = filter
@@ -132,6 +295,11 @@ impl From<Filter> for QueryPattern {
                 since: filter.since,
                 until: filter.until,
             }),
+            (0, None, 2.., Some(_), 0, 0, None, None) => Self::Authors(QueryByAuthorsParams {
+                authors: filter.authors.unwrap_or_default(),
+                since: filter.since,
+                until: filter.until,
+            }),
             (1, Some(kind), 1, Some(author), 0, 0, None, None) => {
                 Self::KindAuthor(QueryByKindAndAuthorParams {
                     kind,
@@ -151,11 +319,532 @@ impl From<Filter> for QueryPattern {
                     until: filter.until,
                 })
             }
+            _ if filter.search.is_some() => Self::Search(Box::new(filter)),
+            _ if ids_len > 0 => Self::Ids(Box::new(filter)),
+            _ if generic_tags_len > 0 => Self::Tags(Box::new(filter)),
             _ => Self::Generic(Box::new(filter)),
         }
     }
 }
 
+/// Split content in lowercase words, used by the search index (NIP-50)
+fn tokenize(content: &str) -> BTreeSet<String> {
+    content
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// Hash of the content, used by the content hash index
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Sort key of [Event], in the same order of the [`DatabaseHelper::query`] results
+///
+/// Newest events first, then the lowest ID. Useful to merge the results with the ones of other sources.
+#[inline]
+pub fn event_sort_key(event: &Event) -> impl Ord {
+    (Reverse(event.created_at), event.id)
+}
+
+/// Check if [Event] match [Filter], with the same semantics of [`DatabaseHelper::query`]
+///
+/// Useful to route incoming events to the active subscriptions, without touching the indexes.
+/// Same of [`Filter::match_event`], except for the search (NIP-50): all the words of the search string
+/// must be in the content (see [`DatabaseHelper`] search), and for the present but empty sets: they match
+/// nothing (see [`validate_filter`]).
+pub fn event_matches(event: &Event, filter: &Filter) -> bool {
+    match &filter.search {
+        Some(search) => {
+            let words: BTreeSet<String> = tokenize(search);
+            let content: BTreeSet<String> = tokenize(&event.content);
+            words.is_subset(&content)
+                && check_sets(filter).is_ok()
+                && filter.clone().remove_search().match_event(event)
+        }
+        None => check_sets(filter).is_ok() && filter.match_event(event),
+    }
+}
+
+/// Check that [Filter] can match some event
+///
+/// Detect the filters that are obviously empty, without looking at the indexes:
+/// contradictory time window, zero `limit` and present but empty sets (they match nothing).
+/// Useful to reject the bad subscriptions before querying.
+pub fn validate_filter(filter: &Filter) -> Result<(), FilterError> {
+    if let (Some(since), Some(until)) = (filter.since, filter.until) {
+        if since > until {
+            return Err(FilterError::InvalidTimeWindow);
+        }
+    }
+
+    if filter.limit == Some(0) {
+        return Err(FilterError::ZeroLimit);
+    }
+
+    check_sets(filter)
+}
+
+/// Check that [Filter] has no present but empty set
+fn check_sets(filter: &Filter) -> Result<(), FilterError> {
+    if filter.ids.as_ref().is_some_and(|ids| ids.is_empty()) {
+        return Err(FilterError::EmptyIds);
+    }
+
+    if filter
+        .authors
+        .as_ref()
+        .is_some_and(|authors| authors.is_empty())
+    {
+        return Err(FilterError::EmptyAuthors);
+    }
+
+    if filter.kinds.as_ref().is_some_and(|kinds| kinds.is_empty()) {
+        return Err(FilterError::EmptyKinds);
+    }
+
+    for (tag, values) in filter.generic_tags.iter() {
+        if values.is_empty() {
+            return Err(FilterError::EmptyTagValues(*tag));
+        }
+    }
+
+    Ok(())
+}
+
+/// Result of the events that are never indexed (expired or ephemeral)
+fn unindexed_result(event: &Event) -> Option<DatabaseEventResult> {
+    if event.is_expired() {
+        Some(DatabaseEventResult::default())
+    } else if event.is_ephemeral() {
+        Some(DatabaseEventResult {
+            status: EventStatus::Ephemeral,
+            ..Default::default()
+        })
+    } else {
+        None
+    }
+}
+
+fn index_result(
+    event: &Event,
+    to_store: bool,
+    replaced: bool,
+    to_discard: HashSet<EventId>,
+) -> DatabaseEventResult {
+    let (status, replaced): (EventStatus, Option<Coordinate>) = match (to_store, replaced) {
+        (true, true) => {
+            let identifier: &str = event.identifier().unwrap_or_default();
+            (
+                EventStatus::Replaced,
+                Some(Coordinate::new(event.kind(), event.author()).identifier(identifier)),
+            )
+        }
+        (true, false) => (EventStatus::Saved, None),
+        (false, _) => (EventStatus::Rejected, None),
+    };
+
+    DatabaseEventResult {
+        to_store,
+        to_discard,
+        status,
+        replaced,
+        reason: None,
+    }
+}
+
+/// Check if the values of `tag` are normalized (`e` and `p` tags: event IDs and public keys)
+#[inline]
+fn is_normalized_tag(tag: &SingleLetterTag) -> bool {
+    *tag == SingleLetterTag::lowercase(Alphabet::E)
+        || *tag == SingleLetterTag::lowercase(Alphabet::P)
+}
+
+/// Lowercase the value if it's hex
+fn normalize_tag_value(value: &str) -> String {
+    if value.chars().all(|c| c.is_ascii_hexdigit()) {
+        value.to_ascii_lowercase()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Lowercase the hex values of `e` and `p` tags (event IDs and public keys)
+fn normalize_tag_values(mut filter: Filter) -> Filter {
+    for (tag, values) in filter.generic_tags.iter_mut() {
+        if is_normalized_tag(tag) {
+            *values = values
+                .iter()
+                .map(|value| normalize_tag_value(value))
+                .collect();
+        }
+    }
+    filter
+}
+
+/// Check if the `new` replaceable event must replace the `existing` one
+///
+/// The newest event wins. If the timestamps are the same, the event with the lowest ID is kept (NIP-01).
+#[inline]
+fn has_precedence(new: &Event, existing: &Event) -> bool {
+    match new.created_at.cmp(&existing.created_at) {
+        Ordering::Greater => true,
+        Ordering::Equal => new.id < existing.id,
+        Ordering::Less => false,
+    }
+}
+
+/// Lazy query results
+///
+/// Holds a snapshot of the helper and resolves the matching events while iterating, a page at a time
+/// (newest first), cloning each one only when yielded. In ascending order the whole result set is resolved
+/// at the first call: the oldest events of a filter with a `limit` are known only after the newest ones.
+#[derive(Debug, Clone)]
+pub struct QueryIter {
+    snapshot: Arc<InternalDatabaseHelper>,
+    /// Filters not exhausted yet, with the remaining `limit`
+    filters: Vec<Filter>,
+    order: Order,
+    /// Sort key of the last resolved event
+    last: Option<SortKey>,
+    buffer: VecDeque<DatabaseEvent>,
+    report: Option<QueryReport>,
+}
+
+/// Report of a lazy query to the observer, sent when exhausted
+#[derive(Debug, Clone)]
+struct QueryReport {
+    observer: Arc<dyn DatabaseHelperObserver>,
+    filters: Vec<Filter>,
+    matched: usize,
+    elapsed: Duration,
+}
+
+impl QueryIter {
+    fn new(snapshot: Arc<InternalDatabaseHelper>, filters: Vec<Filter>, order: Order) -> Self {
+        Self {
+            snapshot,
+            filters,
+            order,
+            last: None,
+            buffer: VecDeque::new(),
+            report: None,
+        }
+    }
+
+    fn resolve(&mut self) {
+        let snapshot: Arc<InternalDatabaseHelper> = Arc::clone(&self.snapshot);
+        let filters: Vec<Filter> = match self.order {
+            Order::Asc => {
+                let events: VecDeque<DatabaseEvent> =
+                    match snapshot.internal_query(mem::take(&mut self.filters)) {
+                        InternalQueryResult::All => snapshot.events.iter().rev().cloned().collect(),
+                        InternalQueryResult::Set(set) => set.into_iter().rev().cloned().collect(),
+                    };
+                self.buffer = events;
+                return;
+            }
+            Order::Desc => mem::take(&mut self.filters),
+        };
+
+        // Take the next events of every filter, after the last resolved one
+        let last: Option<SortKey> = self.last;
+        let mut chunks: Vec<(Filter, Vec<&DatabaseEvent>, bool)> =
+            Vec::with_capacity(filters.len());
+        let mut cutoff: Option<SortKey> = None;
+        for filter in filters.into_iter() {
+            let mut query: Filter = filter.clone();
+            let remaining: usize = query.limit.take().unwrap_or(usize::MAX);
+            let take: usize = remaining.min(QUERY_ITER_PAGE);
+            if let Some((Reverse(created_at), _)) = last {
+                query.until = Some(
+                    query
+                        .until
+                        .map_or(created_at, |until| until.min(created_at)),
+                );
+            }
+
+            let chunk: Vec<&DatabaseEvent> = snapshot
+                .internal_query_by_filter(query)
+                .skip_while(|ev| last.is_some_and(|last| ev.key <= last))
+                .take(take)
+                .collect();
+
+            // The filter has more events after the chunk: the page can't go past its last one
+            let more: bool = chunk.len() == take && remaining > take;
+            if more {
+                if let Some(ev) = chunk.last() {
+                    cutoff = Some(cutoff.map_or(ev.key, |cutoff| cutoff.min(ev.key)));
+                }
+            }
+            chunks.push((filter, chunk, more));
+        }
+
+        let page: BTreeSet<&DatabaseEvent> = chunks
+            .iter()
+            .flat_map(|(_, chunk, _)| chunk.iter().copied())
+            .filter(|ev| cutoff.map_or(true, |cutoff| ev.key <= cutoff))
+            .collect();
+        if let Some(ev) = page.last() {
+            self.last = Some(ev.key);
+        }
+        self.buffer.extend(page.into_iter().cloned());
+
+        // Keep the filters with events still to resolve
+        for (mut filter, chunk, more) in chunks.into_iter() {
+            let resolved: usize =
+                chunk.partition_point(|ev| cutoff.map_or(true, |cutoff| ev.key <= cutoff));
+            if more || resolved < chunk.len() {
+                if let Some(limit) = &mut filter.limit {
+                    *limit -= resolved;
+                }
+                self.filters.push(filter);
+            }
+        }
+    }
+}
+
+impl Iterator for QueryIter {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() && !self.filters.is_empty() {
+            let start: Instant = Instant::now();
+            self.resolve();
+            if let Some(report) = &mut self.report {
+                report.elapsed += start.elapsed();
+            }
+        }
+
+        match self.buffer.pop_front() {
+            Some(ev) => {
+                if let Some(report) = &mut self.report {
+                    report.matched += 1;
+                }
+                Some(ev.deref().clone())
+            }
+            None => {
+                if let Some(report) = self.report.take() {
+                    report
+                        .observer
+                        .on_query(&report.filters, report.matched, report.elapsed);
+                }
+                None
+            }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.filters.is_empty() {
+            (self.buffer.len(), Some(self.buffer.len()))
+        } else {
+            (self.buffer.len(), Some(self.snapshot.events.len()))
+        }
+    }
+}
+
+/// Database helper error
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Error)]
+pub enum Error {
+    /// An index references an event that isn't stored
+    #[error("dangling index entry for event {0}")]
+    DanglingEvent(EventId),
+    /// A stored event is missing from the ID index
+    #[error("event {0} not in the ID index")]
+    MissingEvent(EventId),
+    /// Invalid query page cursor
+    #[error("invalid cursor")]
+    InvalidCursor,
+    /// Truncated or malformed snapshot
+    #[error("invalid snapshot")]
+    InvalidSnapshot,
+    /// Snapshot written by an unsupported format version
+    #[error("unsupported snapshot version: {0}")]
+    UnsupportedSnapshotVersion(u8),
+    /// Query deadline reached before completion
+    #[error("query timeout")]
+    QueryTimeout,
+    /// Filter by a tag that isn't indexed (see [`DatabaseHelper::set_indexed_tags`])
+    #[error("tag {0} not indexed")]
+    TagNotIndexed(SingleLetterTag),
+    /// Histogram with more than [`MAX_HISTOGRAM_BUCKETS`] buckets
+    #[error("too many histogram buckets (max {})", MAX_HISTOGRAM_BUCKETS)]
+    TooManyBuckets,
+}
+
+/// Filter that can't match any event (see [`validate_filter`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Error)]
+pub enum FilterError {
+    /// `since` is after `until`
+    #[error("since is after until")]
+    InvalidTimeWindow,
+    /// `limit` is `0`
+    #[error("limit is zero")]
+    ZeroLimit,
+    /// Present but empty `ids`
+    #[error("empty ids")]
+    EmptyIds,
+    /// Present but empty `authors`
+    #[error("empty authors")]
+    EmptyAuthors,
+    /// Present but empty `kinds`
+    #[error("empty kinds")]
+    EmptyKinds,
+    /// Tag without values
+    #[error("empty values for tag {0}")]
+    EmptyTagValues(SingleLetterTag),
+}
+
+/// Number of events resolved at once by [QueryIter]
+const QUERY_ITER_PAGE: usize = 256;
+
+/// Number of visited candidates between two checks of the query deadline
+const DEADLINE_CHECK_INTERVAL: usize = 256;
+
+/// Visit hook of the queries that never stops the iteration
+#[inline]
+fn visit_all() -> bool {
+    true
+}
+
+/// Max number of buckets of [`DatabaseHelper::histogram`]
+pub const MAX_HISTOGRAM_BUCKETS: usize = 100_000;
+
+/// Current version of the snapshot format
+const SNAPSHOT_VERSION: u8 = 1;
+
+fn write_len(buf: &mut Vec<u8>, len: usize) {
+    buf.extend((len as u64).to_be_bytes());
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_len(buf, bytes.len());
+    buf.extend(bytes);
+}
+
+struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if n > self.bytes.len() {
+            return Err(Error::InvalidSnapshot);
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        let mut buf: [u8; 8] = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(buf))
+    }
+
+    fn len(&mut self) -> Result<usize, Error> {
+        usize::try_from(self.u64()?).map_err(|_| Error::InvalidSnapshot)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], Error> {
+        let len: usize = self.len()?;
+        self.take(len)
+    }
+
+    fn str(&mut self) -> Result<&'a str, Error> {
+        std::str::from_utf8(self.bytes()?).map_err(|_| Error::InvalidSnapshot)
+    }
+
+    fn event_id(&mut self) -> Result<EventId, Error> {
+        EventId::from_slice(self.take(EventId::LEN)?).map_err(|_| Error::InvalidSnapshot)
+    }
+
+    fn public_key(&mut self) -> Result<PublicKey, Error> {
+        PublicKey::from_slice(self.take(PublicKey::LEN)?).map_err(|_| Error::InvalidSnapshot)
+    }
+}
+
+/// Sort key of the query results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum OrderBy {
+    /// Timestamp claimed by the event (default)
+    #[default]
+    CreatedAt,
+    /// Order in which the events were indexed
+    ReceivedAt,
+}
+
+/// Query page cursor
+///
+/// Point to the last event of a page, so the next one starts right after it, also if other events have the same timestamp.
+/// Can be persisted as string (`<created_at>:<event ID>`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cursor {
+    created_at: Timestamp,
+    id: EventId,
+}
+
+impl Cursor {
+    /// Check if [Event] comes after the cursor, in [`Order::Desc`] order
+    #[inline]
+    fn is_before(&self, event: &Event) -> bool {
+        match event.created_at.cmp(&self.created_at) {
+            Ordering::Less => true,
+            Ordering::Equal => event.id > self.id,
+            Ordering::Greater => false,
+        }
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.created_at, self.id)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (created_at, id) = s.split_once(':').ok_or(Error::InvalidCursor)?;
+        Ok(Self {
+            created_at: Timestamp::from_str(created_at).map_err(|_| Error::InvalidCursor)?,
+            id: EventId::from_hex(id).map_err(|_| Error::InvalidCursor)?,
+        })
+    }
+}
+
+/// Indexed event status
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum EventStatus {
+    /// New event
+    Saved,
+    /// Event already indexed
+    Duplicate,
+    /// New event that replaced an older replaceable or parameterized replaceable event
+    Replaced,
+    /// Event not indexed (i.e. deleted, expired or older than the current replaceable event)
+    #[default]
+    Rejected,
+    /// Event with invalid ID or signature, rejected by a verifying helper
+    Invalid,
+    /// Ephemeral event: never indexed, but still to be delivered to the live subscribers
+    Ephemeral,
+    /// Event created too far in the future (see [`DatabaseHelper::set_max_future_skew`])
+    FutureTimestamp,
+    /// Event rejected by the admission policy (see [`DatabaseHelper::with_policy`])
+    Blocked,
+}
+
 /// Database Event Result
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct DatabaseEventResult {
@@ -163,11 +852,174 @@ pub struct DatabaseEventResult {
     pub to_store: bool,
     /// List of events that should be removed from database
     pub to_discard: HashSet<EventId>,
+    /// Event status
+    pub status: EventStatus,
+    /// Coordinate of the replaced event, if [`EventStatus::Replaced`]
+    ///
+    /// The identifier is empty for replaceable events.
+    pub replaced: Option<Coordinate>,
+    /// Reason of the rejection, if [`EventStatus::Blocked`]
+    pub reason: Option<String>,
+}
+
+/// Event removed from the helper while indexing another one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DiscardedEvent {
+    /// Event ID
+    pub id: EventId,
+    /// Kind
+    pub kind: Kind,
+    /// Author
+    pub author: PublicKey,
+    /// Creation timestamp
+    pub created_at: Timestamp,
+}
+
+impl From<&Event> for DiscardedEvent {
+    fn from(event: &Event) -> Self {
+        Self {
+            id: event.id,
+            kind: event.kind,
+            author: event.pubkey,
+            created_at: event.created_at,
+        }
+    }
+}
+
+/// [DatabaseEventResult] with the details of the discarded events
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DetailedEventResult {
+    /// Result
+    pub result: DatabaseEventResult,
+    /// Details of the discarded events known by the helper (see [`DatabaseEventResult::to_discard`])
+    pub discarded: Vec<DiscardedEvent>,
+}
+
+impl From<DatabaseEventResult> for DetailedEventResult {
+    fn from(result: DatabaseEventResult) -> Self {
+        Self {
+            result,
+            discarded: Vec::new(),
+        }
+    }
+}
+
+/// Database helper statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct DatabaseHelperStats {
+    /// Number of indexed events
+    pub events: usize,
+    /// Number of distinct authors
+    pub authors: usize,
+    /// Number of distinct kinds
+    pub kinds: usize,
+    /// Number of parameterized replaceable coordinates
+    pub param_replaceable: usize,
+    /// Number of distinct single-letter tag values
+    pub tag_values: usize,
+    /// Total number of entries across all the indexes
+    pub index_entries: usize,
+    /// Number of deleted event IDs
+    pub deleted_ids: usize,
+    /// Number of deleted coordinates
+    pub deleted_coordinates: usize,
+    /// Time spent updating every index
+    #[cfg(feature = "index-timing")]
+    pub index_timings: IndexTimings,
+}
+
+/// Cumulative time spent updating every index while indexing the events, since the helper was created or cleared
+#[cfg(feature = "index-timing")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct IndexTimings {
+    /// Events sorted by `created_at`
+    pub created_at: Duration,
+    /// Events by ID and by author
+    pub author: Duration,
+    /// Events by kind (with author) and parameterized replaceable events
+    pub kind: Duration,
+    /// Single-letter tags
+    pub tags: Duration,
+    /// Search and content hash
+    pub content: Duration,
+}
+
+/// Measure the time between the laps
+#[cfg(feature = "index-timing")]
+struct Stopwatch(Instant);
+
+#[cfg(feature = "index-timing")]
+impl Stopwatch {
+    #[inline]
+    fn start() -> Self {
+        Self(Instant::now())
+    }
+
+    /// Get the time since the previous lap and start a new one
+    #[inline]
+    fn lap(&mut self) -> Duration {
+        let now: Instant = Instant::now();
+        let elapsed: Duration = now - self.0;
+        self.0 = now;
+        elapsed
+    }
+}
+
+/// Index used to look up the candidates of a query (see [`QueryExplain`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueryIndex {
+    /// Events of a single author
+    Author,
+    /// Events of many authors, merged
+    Authors,
+    /// Events of an author with a kind
+    KindAuthor,
+    /// Current version of a parameterized replaceable event
+    ParamReplaceable,
+    /// Events by ID
+    Ids,
+    /// Smallest between the authors and the single-letter tags indexes
+    Tags,
+    /// Search index (NIP-50)
+    Search,
+    /// No index: all the events are scanned
+    Scan,
+}
+
+/// Query explanation (see [`DatabaseHelper::explain`])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryExplain {
+    /// Index used to look up the candidates
+    pub index: QueryIndex,
+    /// Number of events matching every constraint of the filter alone
+    ///
+    /// Constraints are named as the filter fields (`ids`, `authors`, `kinds`, `#<tag>` and `search`).
+    pub constraints: Vec<(String, usize)>,
+    /// Number of events matching all the constraints so far, in the order of `constraints`
+    pub candidates: Vec<usize>,
+    /// Number of matching events, before applying the `limit`
+    pub matched: usize,
+    /// Number of returned events, after applying the `limit`
+    pub returned: usize,
 }
 
 enum InternalQueryResult<'a> {
     All,
-    Set(BTreeSet<&'a DatabaseEvent>),
+    /// Matching events, sorted and without duplicates
+    Set(Vec<&'a DatabaseEvent>),
+}
+
+enum Evaluation {
+    /// Final result (duplicate, deleted or expired event)
+    Done(DatabaseEventResult),
+    /// Checks passed: the changes to apply
+    Checked {
+        should_insert: bool,
+        replaced: bool,
+        to_discard: HashSet<EventId>,
+        pending_deletions: Vec<(EventId, PublicKey)>,
+        deleted_coordinates: Vec<(Coordinate, Timestamp)>,
+    },
 }
 
 /// Database helper
@@ -176,12 +1028,60 @@ struct InternalDatabaseHelper {
     /// Sorted events
     events: BTreeCappedSet<DatabaseEvent>,
     /// Events by ID
-    ids: HashMap<EventId, DatabaseEvent>,
-    author_index: HashMap<PublicKey, BTreeSet<DatabaseEvent>>,
-    kind_author_index: HashMap<(Kind, PublicKey), BTreeSet<DatabaseEvent>>,
-    param_replaceable_index: HashMap<(Kind, PublicKey, String), DatabaseEvent>,
-    deleted_ids: HashSet<EventId>,
-    deleted_coordinates: HashMap<Coordinate, Timestamp>,
+    ids: im::HashMap<EventId, DatabaseEvent>,
+    author_index: im::HashMap<PublicKey, EventSet>,
+    kind_author_index: im::HashMap<(Kind, PublicKey), EventSet>,
+    /// Authors of every kind with at least one event in `kind_author_index`, ordered by kind for the range lookups
+    kind_authors: OrdMap<Kind, OrdSet<PublicKey>>,
+    param_replaceable_index: im::HashMap<(Kind, PublicKey, String), DatabaseEvent>,
+    /// Number of events of every kind (only the kinds with at least one event)
+    kind_counts: HashMap<Kind, usize>,
+    #[cfg(feature = "index-timing")]
+    index_timings: IndexTimings,
+    /// Tags index
+    ///
+    /// Only single-letter tags are indexed, since they are the only ones that can be matched by filters (NIP-01).
+    tag_index: HashMap<SingleLetterTag, im::HashMap<String, EventSet>>,
+    /// Content words index (NIP-50)
+    search_index: im::HashMap<String, EventSet>,
+    /// Content hash index, if enabled
+    content_hash_index: Option<im::HashMap<u64, EventSet>>,
+    /// Letters of the indexed tags (`None` means all of them)
+    indexed_tags: Option<HashSet<Alphabet>>,
+    /// Events with an expiration tag, by expiration (NIP-40)
+    expiration_index: OrdMap<Timestamp, HashSet<EventId>>,
+    /// Tombstones of the deleted events, so they are rejected if delivered again
+    ///
+    /// The deleted events are removed from all the indexes and never indexed again:
+    /// the queries don't have to check them.
+    deleted_ids: im::HashSet<EventId>,
+    /// Deletion order of the `deleted_ids`, oldest first
+    deleted_ids_order: Vector<EventId>,
+    deleted_coordinates: im::HashMap<Coordinate, Timestamp>,
+    /// Deletion requests received before the target event
+    pending_deletions: im::HashSet<(EventId, PublicKey)>,
+    /// Receiving order of the `pending_deletions`, oldest first
+    pending_deletions_order: Vector<(EventId, PublicKey)>,
+    /// Relays where the events were seen
+    source_index: im::HashMap<EventId, HashSet<Url>>,
+    /// Indexing order of the events
+    received_index: im::HashMap<EventId, u64>,
+    /// Events by indexing order (reverse of `received_index`)
+    received_order: OrdMap<u64, EventId>,
+    /// Value of the next `received_index` entry
+    next_received: u64,
+    /// Lowercase the hex values of `e` and `p` tags of the filters and of `tag_index`
+    normalize_tags: bool,
+    /// Reject the events created after `now + max_future_skew`
+    max_future_skew: Option<Duration>,
+    /// Reject the events created before this timestamp
+    min_created_at: Option<Timestamp>,
+    /// Max number of `deleted_ids` (and of `pending_deletions`) to keep
+    max_deleted_ids: Option<usize>,
+    /// Newest events, if enabled (see `recent_capacity`)
+    recent: BTreeCappedSet<DatabaseEvent>,
+    /// Max number of `recent` events to keep (`None` disables them)
+    recent_capacity: Option<usize>,
 }
 
 impl InternalDatabaseHelper {
@@ -221,23 +1121,55 @@ impl InternalDatabaseHelper {
             .filter(move |event| self.internal_index_event(event, &now).to_store)
     }
 
-    fn internal_index_event(&mut self, event: &Event, now: &Timestamp) -> DatabaseEventResult {
+    /// Run the duplicate, deletion, expiration and replaceable checks, without modifying the helper
+    fn evaluate_event(&self, event: &Event, now: &Timestamp) -> Evaluation {
         // Check if was already added
         if self.ids.contains_key(&event.id) {
-            return DatabaseEventResult::default();
+            return Evaluation::Done(DatabaseEventResult {
+                status: EventStatus::Duplicate,
+                ..Default::default()
+            });
         }
 
         // Check if was deleted or is expired
-        if self.deleted_ids.contains(&event.id) || event.is_expired_at(now) {
+        if self.deleted_ids.contains(&event.id)
+            || self.pending_deletions.contains(&(event.id, event.pubkey))
+            || event.is_expired_at(now)
+        {
             let mut to_discard: HashSet<EventId> = HashSet::with_capacity(1);
             to_discard.insert(event.id);
-            return DatabaseEventResult {
+            return Evaluation::Done(DatabaseEventResult {
                 to_store: false,
                 to_discard,
-            };
+                status: EventStatus::Rejected,
+                replaced: None,
+                reason: None,
+            });
+        }
+
+        // Check if was created before the floor
+        if let Some(min_created_at) = self.min_created_at {
+            if event.created_at < min_created_at {
+                return Evaluation::Done(DatabaseEventResult {
+                    status: EventStatus::Rejected,
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Check if was created too far in the future
+        if let Some(skew) = self.max_future_skew {
+            if event.created_at > *now + skew {
+                return Evaluation::Done(DatabaseEventResult {
+                    status: EventStatus::FutureTimestamp,
+                    ..Default::default()
+                });
+            }
         }
 
         let mut to_discard: HashSet<EventId> = HashSet::new();
+        let mut pending_deletions: Vec<(EventId, PublicKey)> = Vec::new();
+        let mut deleted_coordinates: Vec<(Coordinate, Timestamp)> = Vec::new();
 
         // Compose others fields
         let author: PublicKey = event.author();
@@ -247,43 +1179,51 @@ impl InternalDatabaseHelper {
         let mut should_insert: bool = true;
 
         if kind.is_replaceable() {
-            let params: QueryByKindAndAuthorParams = QueryByKindAndAuthorParams::new(kind, author);
-            for ev in self.internal_query_by_kind_and_author(params) {
-                if ev.created_at > created_at || ev.id == event.id {
-                    should_insert = false;
-                } else {
-                    to_discard.insert(ev.id);
+            // Check if coordinate was deleted (the versions up to the deletion)
+            if self.has_coordinate_been_deleted(&Coordinate::new(kind, author), &created_at) {
+                should_insert = false;
+            } else {
+                let params: QueryByKindAndAuthorParams =
+                    QueryByKindAndAuthorParams::new(kind, author);
+                for ev in self.internal_query_by_kind_and_author(params, &visit_all) {
+                    if has_precedence(event, ev) {
+                        to_discard.insert(ev.id);
+                    } else {
+                        should_insert = false;
+                    }
                 }
             }
         } else if kind.is_parameterized_replaceable() {
-            match event.identifier() {
-                Some(identifier) => {
-                    let coordinate: Coordinate =
-                        Coordinate::new(kind, author).identifier(identifier);
+            // A missing `d` tag is treated as an empty identifier
+            let identifier: &str = event.identifier().unwrap_or_default();
+            let coordinate: Coordinate = Coordinate::new(kind, author).identifier(identifier);
 
-                    // Check if coordinate was deleted
-                    if self.has_coordinate_been_deleted(&coordinate, now) {
-                        should_insert = false;
+            // Check if coordinate was deleted (the versions up to the deletion)
+            if self.has_coordinate_been_deleted(&coordinate, &created_at) {
+                should_insert = false;
+            } else {
+                let params: QueryByParamReplaceable =
+                    QueryByParamReplaceable::new(kind, author, identifier.to_string());
+                if let Some(ev) = self.internal_query_param_replaceable(params) {
+                    if has_precedence(event, ev) {
+                        to_discard.insert(ev.id);
                     } else {
-                        let params: QueryByParamReplaceable =
-                            QueryByParamReplaceable::new(kind, author, identifier.to_string());
-                        if let Some(ev) = self.internal_query_param_replaceable(params) {
-                            if ev.created_at > created_at || ev.id == event.id {
-                                should_insert = false;
-                            } else {
-                                to_discard.insert(ev.id);
-                            }
-                        }
+                        should_insert = false;
                     }
                 }
-                None => should_insert = false,
             }
         } else if kind == Kind::EventDeletion {
             // Check `e` tags
             for id in event.event_ids() {
-                if let Some(ev) = self.ids.get(id) {
-                    if ev.pubkey == author && ev.created_at <= created_at {
-                        to_discard.insert(ev.id);
+                match self.ids.get(id) {
+                    Some(ev) => {
+                        if ev.pubkey == author && ev.created_at <= created_at {
+                            to_discard.insert(ev.id);
+                        }
+                    }
+                    // Target event not received yet: remember the deletion request
+                    None => {
+                        pending_deletions.push((*id, author));
                     }
                 }
             }
@@ -292,19 +1232,13 @@ impl InternalDatabaseHelper {
             for coordinate in event.coordinates() {
                 if coordinate.public_key == author {
                     // Save deleted coordinate at certain timestamp
-                    self.deleted_coordinates
-                        .entry(coordinate.clone())
-                        .and_modify(|t| {
-                            // Update only if newer
-                            if created_at > *t {
-                                *t = created_at
-                            }
-                        })
-                        .or_insert(created_at);
+                    deleted_coordinates.push((coordinate.clone(), created_at));
 
                     // Not check if ev.pubkey match the author because assume that query
-                    // returned only the events owned by author
-                    if !coordinate.identifier.is_empty() {
+                    // returned only the events owned by author.
+                    // A parameterized coordinate without identifier targets only the events
+                    // with an empty (or missing) `d` tag, like the check done at indexing time.
+                    if coordinate.kind.is_parameterized_replaceable() {
                         let mut params: QueryByParamReplaceable = QueryByParamReplaceable::new(
                             coordinate.kind,
                             coordinate.public_key,
@@ -318,740 +1252,9153 @@ impl InternalDatabaseHelper {
                         let mut params: QueryByKindAndAuthorParams =
                             QueryByKindAndAuthorParams::new(coordinate.kind, coordinate.public_key);
                         params.until = Some(created_at);
-                        to_discard
-                            .extend(self.internal_query_by_kind_and_author(params).map(|e| e.id));
+                        to_discard.extend(
+                            self.internal_query_by_kind_and_author(params, &visit_all)
+                                .map(|e| e.id),
+                        );
                     }
                 }
             }
         }
 
+        let replaced: bool = (kind.is_replaceable() || kind.is_parameterized_replaceable())
+            && !to_discard.is_empty();
+
+        Evaluation::Checked {
+            should_insert,
+            replaced,
+            to_discard,
+            pending_deletions,
+            deleted_coordinates,
+        }
+    }
+
+    #[inline]
+    fn internal_index_event(&mut self, event: &Event, now: &Timestamp) -> DatabaseEventResult {
+        self.internal_index_event_detailed(event, now).result
+    }
+
+    fn internal_index_event_detailed(
+        &mut self,
+        event: &Event,
+        now: &Timestamp,
+    ) -> DetailedEventResult {
+        let (should_insert, replaced, mut to_discard) = match self.evaluate_event(event, now) {
+            Evaluation::Done(res) => return res.into(),
+            Evaluation::Checked {
+                should_insert,
+                replaced,
+                to_discard,
+                pending_deletions,
+                deleted_coordinates,
+            } => {
+                for (id, author) in pending_deletions.into_iter() {
+                    self.insert_pending_deletion(id, author);
+                }
+                for (coordinate, created_at) in deleted_coordinates.into_iter() {
+                    self.deleted_coordinates
+                        .entry(coordinate)
+                        .and_modify(|t| {
+                            // Update only if newer
+                            if created_at > *t {
+                                *t = created_at
+                            }
+                        })
+                        .or_insert(created_at);
+                }
+                (should_insert, replaced, to_discard)
+            }
+        };
+
         // Remove events
-        self.discard_events(&to_discard);
+        let mut discarded: Vec<DiscardedEvent> = self.discard_events(&to_discard);
 
         // Insert event
+        let mut to_store: bool = false;
+
         if should_insert {
-            let e: DatabaseEvent = DatabaseEvent {
-                event: Arc::new(event.clone()),
-            }; // TODO: avoid clone?
+            let e: DatabaseEvent = DatabaseEvent::new(event.clone()); // TODO: avoid clone?
 
-            let InsertResult { inserted, pop } = self.events.insert(e.clone());
+            #[cfg(feature = "index-timing")]
+            let mut stopwatch = Stopwatch::start();
 
-            if inserted {
-                self.ids.insert(e.id, e.clone());
-                self.author_index
-                    .entry(author)
-                    .or_default()
-                    .insert(e.clone());
+            let InsertResult { inserted, pop } = self.events.insert(e.clone());
 
-                if kind.is_parameterized_replaceable() {
-                    if let Some(identifier) = e.identifier() {
-                        self.param_replaceable_index
-                            .insert((kind, author, identifier.to_string()), e.clone());
-                    }
-                }
+            #[cfg(feature = "index-timing")]
+            {
+                self.index_timings.created_at += stopwatch.lap();
+            }
 
-                if kind.is_replaceable() {
-                    let mut set = BTreeSet::new();
-                    set.insert(e);
-                    self.kind_author_index.insert((kind, author), set);
-                } else {
-                    self.kind_author_index
-                        .entry((kind, author))
-                        .or_default()
-                        .insert(e);
-                }
+            if inserted {
+                to_store = true;
+                self.insert_in_indexes(e);
             } else {
+                // Older than all the events of a full helper
                 to_discard.insert(e.id);
+                discarded.push(DiscardedEvent::from(event));
             }
 
+            // Oldest event evicted
             if let Some(event) = pop {
                 to_discard.insert(event.id);
+                discarded.push(DiscardedEvent::from(event.deref()));
                 self.discard_event(event);
             }
         }
 
-        DatabaseEventResult {
-            to_store: should_insert,
-            to_discard,
+        DetailedEventResult {
+            result: index_result(event, to_store, replaced, to_discard),
+            discarded,
         }
     }
 
-    fn discard_events(&mut self, ids: &HashSet<EventId>) {
-        if !ids.is_empty() {
-            for id in ids.iter() {
-                if let Some(ev) = self.ids.remove(id) {
-                    self.events.remove(&ev);
+    /// Compute the result of indexing an event, without modifying the helper
+    pub fn evaluate(&self, event: &Event) -> DatabaseEventResult {
+        // Same checks of `index_event`
+        if let Some(res) = unindexed_result(event) {
+            return res;
+        }
 
-                    if let Some(set) = self.author_index.get_mut(&ev.pubkey) {
-                        set.remove(&ev);
-                    }
+        let now: Timestamp = Timestamp::now();
+        let (should_insert, replaced, mut to_discard) = match self.evaluate_event(event, &now) {
+            Evaluation::Done(res) => return res,
+            Evaluation::Checked {
+                should_insert,
+                replaced,
+                to_discard,
+                ..
+            } => (should_insert, replaced, to_discard),
+        };
 
-                    if ev.kind.is_parameterized_replaceable() {
-                        if let Some(identifier) = ev.identifier() {
-                            self.param_replaceable_index.remove(&(
-                                ev.kind,
-                                ev.pubkey,
-                                identifier.to_string(),
-                            ));
-                        }
-                    }
+        let mut to_store: bool = false;
 
-                    if let Some(set) = self.kind_author_index.get_mut(&(ev.kind, ev.pubkey)) {
-                        set.remove(&ev);
-                    }
-                }
-                self.deleted_ids.insert(*id);
+        if should_insert {
+            let e: DatabaseEvent = DatabaseEvent::new(event.clone());
+
+            // The discarded events would be removed before the insertion
+            let removed: BTreeSet<&DatabaseEvent> = to_discard
+                .iter()
+                .filter_map(|id| self.ids.get(id))
+                .collect();
+            let InsertResult { inserted, pop } = self.events.insert_outcome(&e, &removed);
+
+            if inserted {
+                to_store = true;
+            } else {
+                to_discard.insert(e.id);
+            }
+
+            if let Some(event) = pop {
+                to_discard.insert(event.id);
             }
         }
+
+        index_result(event, to_store, replaced, to_discard)
     }
 
-    fn discard_event(&mut self, ev: DatabaseEvent) {
-        self.ids.remove(&ev.id);
+    /// Insert an event, already added to `events`, in all the other indexes
+    fn insert_in_indexes(&mut self, e: DatabaseEvent) {
+        debug_assert!(
+            !self.deleted_ids.contains(&e.id),
+            "deleted event indexed again"
+        );
 
-        if let Some(set) = self.author_index.get_mut(&ev.pubkey) {
-            set.remove(&ev);
+        let author: PublicKey = e.author();
+        let kind: Kind = e.kind();
+
+        if self.recent_capacity.is_some() {
+            self.recent.insert(e.clone());
         }
 
-        if ev.kind.is_parameterized_replaceable() {
-            if let Some(identifier) = ev.identifier() {
-                self.param_replaceable_index
-                    .remove(&(ev.kind, ev.pubkey, identifier.to_string()));
+        #[cfg(feature = "index-timing")]
+        let mut stopwatch = Stopwatch::start();
+
+        self.ids.insert(e.id, e.clone());
+        self.received_index.insert(e.id, self.next_received);
+        self.received_order.insert(self.next_received, e.id);
+        self.next_received += 1;
+        if let Some(expiration) = e.expiration() {
+            self.expiration_index
+                .entry(*expiration)
+                .or_default()
+                .insert(e.id);
+        }
+        self.author_index
+            .entry(author)
+            .or_default()
+            .insert(e.clone());
+
+        #[cfg(feature = "index-timing")]
+        {
+            self.index_timings.author += stopwatch.lap();
+        }
+
+        if kind.is_parameterized_replaceable() {
+            let identifier: &str = e.identifier().unwrap_or_default();
+            self.param_replaceable_index
+                .insert((kind, author, identifier.to_string()), e.clone());
+        }
+
+        #[cfg(feature = "index-timing")]
+        {
+            self.index_timings.kind += stopwatch.lap();
+        }
+
+        for (tag, values) in e.tags_indexes().iter() {
+            if !self.is_tag_indexed(tag) {
+                continue;
+            }
+
+            let normalize: bool = self.normalize_tags && is_normalized_tag(tag);
+            let map = self.tag_index.entry(*tag).or_default();
+            for value in values.iter() {
+                let value: String = if normalize {
+                    normalize_tag_value(value)
+                } else {
+                    value.clone()
+                };
+                map.entry(value).or_default().insert(e.clone());
             }
         }
 
-        if let Some(set) = self.kind_author_index.get_mut(&(ev.kind, ev.pubkey)) {
-            set.remove(&ev);
+        #[cfg(feature = "index-timing")]
+        {
+            self.index_timings.tags += stopwatch.lap();
         }
-    }
 
-    /// Import [Event]
-    ///
-    /// **This method assume that [`Event`] was already verified**
-    #[tracing::instrument(skip_all, level = "trace")]
-    pub fn index_event(&mut self, event: &Event) -> DatabaseEventResult {
-        // Check if it's expired or ephemeral (in `internal_index_event` is checked only the raw event expiration)
-        if event.is_expired() || event.is_ephemeral() {
-            return DatabaseEventResult::default();
+        for word in tokenize(&e.content).into_iter() {
+            self.search_index.entry(word).or_default().insert(e.clone());
+        }
+
+        if let Some(index) = &mut self.content_hash_index {
+            index
+                .entry(content_hash(&e.content))
+                .or_default()
+                .insert(e.clone());
+        }
+
+        #[cfg(feature = "index-timing")]
+        {
+            self.index_timings.content += stopwatch.lap();
+        }
+
+        if kind.is_replaceable() {
+            let set: EventSet = OrdSet::unit(e);
+            let previous: usize = self
+                .kind_author_index
+                .insert((kind, author), set)
+                .map_or(0, |set| set.len());
+            *self.kind_counts.entry(kind).or_default() += 1;
+            self.remove_from_kind_counts(kind, previous);
+        } else if self
+            .kind_author_index
+            .entry((kind, author))
+            .or_default()
+            .insert(e)
+            .is_none()
+        {
+            *self.kind_counts.entry(kind).or_default() += 1;
+        }
+        self.kind_authors.entry(kind).or_default().insert(author);
+
+        #[cfg(feature = "index-timing")]
+        {
+            self.index_timings.kind += stopwatch.lap();
         }
-        let now = Timestamp::now();
-        self.internal_index_event(event, &now)
     }
 
-    /// Query by public key
-    fn internal_query_by_author<'a>(
-        &'a self,
-        params: QueryByAuthorParams,
-    ) -> Box<dyn Iterator<Item = &'a DatabaseEvent> + 'a> {
-        let QueryByAuthorParams {
-            author,
-            since,
-            until,
-        } = params;
-        match self.author_index.get(&author) {
-            Some(set) => Box::new(set.iter().filter(move |ev| {
-                if self.deleted_ids.contains(&ev.id) {
-                    return false;
-                }
+    /// Remove `n` events from the count of `kind`, dropping it when no event is left
+    fn remove_from_kind_counts(&mut self, kind: Kind, n: usize) {
+        if let Some(count) = self.kind_counts.get_mut(&kind) {
+            *count = count.saturating_sub(n);
+            if *count == 0 {
+                self.kind_counts.remove(&kind);
+            }
+        }
+    }
 
-                if let Some(since) = since {
-                    if ev.created_at < since {
-                        return false;
-                    }
-                }
+    /// Remove the events and return the details of the ones that were indexed
+    fn discard_events(&mut self, ids: &HashSet<EventId>) -> Vec<DiscardedEvent> {
+        let mut discarded: Vec<DiscardedEvent> = Vec::new();
+        for id in ids.iter() {
+            if let Some(ev) = self.ids.remove(id) {
+                discarded.push(DiscardedEvent::from(ev.deref()));
+                self.events.remove(&ev);
+                self.discard_event(ev);
+            }
+            self.insert_deleted_id(*id);
+        }
+        discarded
+    }
 
-                if let Some(until) = until {
-                    if ev.created_at > until {
-                        return false;
-                    }
-                }
+    /// Keep the tombstone of a deleted event, dropping the oldest ones over `max_deleted_ids`
+    fn insert_deleted_id(&mut self, id: EventId) {
+        if self.deleted_ids.insert(id).is_none() {
+            self.deleted_ids_order.push_back(id);
+            self.trim_deleted_ids();
+        }
+    }
 
-                true
-            })),
-            None => Box::new(iter::empty()),
+    /// Keep a deletion request of an event not received yet, dropping the oldest ones over `max_deleted_ids`
+    fn insert_pending_deletion(&mut self, id: EventId, author: PublicKey) {
+        if self.pending_deletions.insert((id, author)).is_none() {
+            self.pending_deletions_order.push_back((id, author));
+            self.trim_deleted_ids();
         }
     }
 
-    /// Query by [`Kind`] and [`PublicKeyPrefix`]
-    fn internal_query_by_kind_and_author<'a>(
-        &'a self,
-        params: QueryByKindAndAuthorParams,
-    ) -> Box<dyn Iterator<Item = &'a DatabaseEvent> + 'a> {
-        let QueryByKindAndAuthorParams {
-            kind,
-            author,
-            since,
-            until,
-        } = params;
-        match self.kind_author_index.get(&(kind, author)) {
-            Some(set) => Box::new(set.iter().filter(move |ev| {
-                if self.deleted_ids.contains(&ev.id) {
-                    return false;
+    fn trim_deleted_ids(&mut self) {
+        if let Some(max) = self.max_deleted_ids {
+            while self.deleted_ids_order.len() > max {
+                if let Some(id) = self.deleted_ids_order.pop_front() {
+                    self.deleted_ids.remove(&id);
                 }
+            }
 
-                if let Some(since) = since {
-                    if ev.created_at < since {
-                        return false;
-                    }
+            while self.pending_deletions_order.len() > max {
+                if let Some(pending) = self.pending_deletions_order.pop_front() {
+                    self.pending_deletions.remove(&pending);
                 }
+            }
+        }
+    }
 
-                if let Some(until) = until {
-                    if ev.created_at > until {
-                        return false;
-                    }
+    pub fn set_max_deleted_ids(&mut self, max: Option<usize>) {
+        self.max_deleted_ids = max;
+        self.trim_deleted_ids();
+    }
+
+    pub fn set_recent_capacity(&mut self, capacity: Option<usize>) {
+        self.recent_capacity = capacity;
+        self.recent = match capacity {
+            Some(max) => {
+                let mut recent = BTreeCappedSet::bounded_with_policy(max, OverCapacityPolicy::Last);
+                for ev in self.events.iter().take(max) {
+                    recent.insert(ev.clone());
                 }
+                recent
+            }
+            None => BTreeCappedSet::default(),
+        };
+    }
 
-                true
-            })),
-            None => Box::new(iter::empty()),
+    /// Remove event from the `recent` ones, replacing it with the newest event not kept yet
+    fn remove_from_recent(&mut self, ev: &DatabaseEvent) {
+        if !self.recent.remove(ev) {
+            return;
         }
-    }
 
-    /// Query by param. replaceable
-    fn internal_query_param_replaceable(
-        &self,
-        params: QueryByParamReplaceable,
-    ) -> Option<&DatabaseEvent> {
-        let QueryByParamReplaceable {
-            kind,
-            author,
-            identifier,
-            since,
-            until,
-        } = params;
+        let next: Option<&DatabaseEvent> = match self.recent.iter().next_back() {
+            Some(oldest) => self
+                .events
+                .range((Bound::Excluded(oldest.key), Bound::Unbounded))
+                .find(|next| next.id != ev.id),
+            None => self.events.iter().find(|next| next.id != ev.id),
+        };
+        if let Some(next) = next.cloned() {
+            self.recent.insert(next);
+        }
+    }
 
-        if !kind.is_parameterized_replaceable() {
-            return None;
+    /// Remove event from all indexes except the sorted events
+    fn discard_event(&mut self, ev: DatabaseEvent) {
+        self.remove_from_recent(&ev);
+        self.ids.remove(&ev.id);
+        self.source_index.remove(&ev.id);
+        if let Some(received) = self.received_index.remove(&ev.id) {
+            self.received_order.remove(&received);
+        }
+        if let Some(expiration) = ev.expiration() {
+            if let Some(ids) = self.expiration_index.get_mut(expiration) {
+                ids.remove(&ev.id);
+                if ids.is_empty() {
+                    self.expiration_index.remove(expiration);
+                }
+            }
         }
 
-        let ev = self
-            .param_replaceable_index
-            .get(&(kind, author, identifier))?;
+        if let Some(set) = self.author_index.get_mut(&ev.pubkey) {
+            set.remove(&ev);
+        }
 
-        if self.deleted_ids.contains(&ev.id) {
-            return None;
+        if ev.kind.is_parameterized_replaceable() {
+            let identifier: &str = ev.identifier().unwrap_or_default();
+            let key = (ev.kind, ev.pubkey, identifier.to_string());
+            // Remove only if the coordinate still points to this event
+            if self
+                .param_replaceable_index
+                .get(&key)
+                .is_some_and(|current| current.id == ev.id)
+            {
+                self.param_replaceable_index.remove(&key);
+            }
         }
 
-        if let Some(since) = since {
-            if ev.created_at < since {
-                return None;
+        let key = (ev.kind, ev.pubkey);
+        if let Some(set) = self.kind_author_index.get_mut(&key) {
+            if set.remove(&ev).is_some() {
+                self.remove_from_kind_counts(ev.kind, 1);
+            }
+
+            // Drop the empty set, so `kind_authors` lists only the authors with events
+            if set.is_empty() {
+                self.kind_author_index.remove(&key);
+                if let Some(authors) = self.kind_authors.get_mut(&ev.kind) {
+                    authors.remove(&ev.pubkey);
+                    if authors.is_empty() {
+                        self.kind_authors.remove(&ev.kind);
+                    }
+                }
             }
         }
 
-        if let Some(until) = until {
-            if ev.created_at > until {
-                return None;
+        self.remove_from_tag_index(&ev);
+
+        for word in tokenize(&ev.content).iter() {
+            if let Some(set) = self.search_index.get_mut(word) {
+                set.remove(&ev);
+
+                if set.is_empty() {
+                    self.search_index.remove(word);
+                }
             }
         }
 
-        Some(ev)
+        if let Some(index) = &mut self.content_hash_index {
+            let hash: u64 = content_hash(&ev.content);
+            if let Some(set) = index.get_mut(&hash) {
+                set.remove(&ev);
+
+                if set.is_empty() {
+                    index.remove(&hash);
+                }
+            }
+        }
     }
 
-    /// Generic query
     #[inline]
-    fn internal_generic_query(&self, filter: Filter) -> impl Iterator<Item = &DatabaseEvent> {
-        self.events
-            .iter()
-            .filter(move |event| !self.deleted_ids.contains(&event.id) && filter.match_event(event))
+    fn is_tag_indexed(&self, tag: &SingleLetterTag) -> bool {
+        self.indexed_tags
+            .as_ref()
+            .map_or(true, |letters| letters.contains(&tag.character))
     }
 
-    fn internal_query<I>(&self, filters: I) -> InternalQueryResult
-    where
-        I: IntoIterator<Item = Filter>,
-    {
-        let mut matching_ids: BTreeSet<&DatabaseEvent> = BTreeSet::new();
-
-        for filter in filters.into_iter() {
-            if filter.is_empty() {
-                return InternalQueryResult::All;
-            }
-
-            if let (Some(since), Some(until)) = (filter.since, filter.until) {
-                if since > until {
-                    continue;
+    /// Check if the indexed event matches the (normalized) [Filter], with the same semantics of the queries
+    ///
+    /// The `limit` isn't considered.
+    fn matches(&self, filter: &Filter, ev: &DatabaseEvent) -> bool {
+        filter
+            .generic_tags
+            .keys()
+            .all(|tag| self.is_tag_indexed(tag))
+            && match &filter.search {
+                Some(search) => {
+                    tokenize(search).is_subset(&tokenize(&ev.content))
+                        && check_sets(filter).is_ok()
+                        && self.match_event(&filter.clone().remove_search(), ev)
                 }
+                None => check_sets(filter).is_ok() && self.match_event(filter, ev),
             }
+    }
 
-            let limit: Option<usize> = filter.limit;
+    /// Same of [`Filter::match_event`], but the `e` and `p` values of the event are compared normalized
+    /// if `normalize_tags` is enabled (the ones of the filter are expected already normalized)
+    fn match_event(&self, filter: &Filter, ev: &Event) -> bool {
+        // Fast path: the tags of the event are usually already lowercase (NIP-01)
+        if filter.match_event(ev) {
+            return true;
+        }
 
-            let evs: Box<dyn Iterator<Item = &DatabaseEvent>> = match QueryPattern::from(filter) {
-                QueryPattern::Author(params) => self.internal_query_by_author(params),
-                QueryPattern::KindAuthor(params) => self.internal_query_by_kind_and_author(params),
-                QueryPattern::ParamReplaceable(params) => {
-                    match self.internal_query_param_replaceable(params) {
-                        Some(ev) => Box::new(iter::once(ev)),
-                        None => Box::new(iter::empty()),
-                    }
-                }
-                QueryPattern::Generic(filter) => Box::new(self.internal_generic_query(*filter)),
-            };
+        if !self.normalize_tags || !filter.generic_tags.keys().any(is_normalized_tag) {
+            return false;
+        }
 
-            if let Some(limit) = limit {
-                matching_ids.extend(evs.take(limit))
-            } else {
-                matching_ids.extend(evs)
+        let mut rest: Filter = filter.clone();
+        rest.generic_tags.retain(|tag, _| !is_normalized_tag(tag));
+
+        let tags: &TagsIndexes = ev.tags_indexes();
+        filter
+            .generic_tags
+            .iter()
+            .filter(|(tag, _)| is_normalized_tag(tag))
+            .all(|(tag, values)| {
+                tags.get(tag).map_or(false, |ev_values| {
+                    ev_values
+                        .iter()
+                        .any(|value| values.contains(&normalize_tag_value(value)))
+                })
+            })
+            && rest.match_event(ev)
+    }
+
+    /// Check that all the tags of the filters are indexed
+    fn check_indexed_tags(&self, filters: &[Filter]) -> Result<(), Error> {
+        for filter in filters.iter() {
+            if let Some(tag) = filter
+                .generic_tags
+                .keys()
+                .find(|tag| !self.is_tag_indexed(tag))
+            {
+                return Err(Error::TagNotIndexed(*tag));
             }
         }
-
-        InternalQueryResult::Set(matching_ids)
+        Ok(())
     }
 
-    #[inline]
-    pub fn event_by_id(&self, id: &EventId) -> Option<&Event> {
-        self.ids.get(id).map(|e| e.deref())
+    pub fn set_indexed_tags(&mut self, letters: Option<HashSet<Alphabet>>) {
+        self.indexed_tags = letters;
+        self.rebuild_tag_index();
     }
 
-    #[inline]
-    pub fn has_event(&self, id: &EventId) -> bool {
-        self.ids.contains_key(id)
+    pub fn set_normalize_tags(&mut self, enable: bool) {
+        if self.normalize_tags != enable {
+            self.normalize_tags = enable;
+            self.rebuild_tag_index();
+        }
     }
 
-    /// Query
-    #[tracing::instrument(skip_all, level = "trace")]
-    pub fn query<I>(&self, filters: I, order: Order) -> Vec<Event>
-    where
-        I: IntoIterator<Item = Filter>,
-    {
-        match self.internal_query(filters) {
-            InternalQueryResult::All => match order {
-                Order::Asc => self
-                    .events
-                    .iter()
-                    .rev()
-                    .map(|ev| ev.deref().clone())
-                    .collect(),
-                Order::Desc => self.events.iter().map(|ev| ev.deref().clone()).collect(),
-            },
-            InternalQueryResult::Set(set) => match order {
-                Order::Asc => set.into_iter().rev().map(|ev| ev.deref().clone()).collect(),
-                Order::Desc => set.into_iter().map(|ev| ev.deref().clone()).collect(),
-            },
+    /// Build the tags index again
+    fn rebuild_tag_index(&mut self) {
+        let mut tag_index: HashMap<SingleLetterTag, im::HashMap<String, EventSet>> = HashMap::new();
+        for ev in self.events.iter() {
+            for (tag, values) in ev.tags_indexes().iter() {
+                if !self.is_tag_indexed(tag) {
+                    continue;
+                }
+
+                let normalize: bool = self.normalize_tags && is_normalized_tag(tag);
+                let map = tag_index.entry(*tag).or_default();
+                for value in values.iter() {
+                    let value: String = if normalize {
+                        normalize_tag_value(value)
+                    } else {
+                        value.clone()
+                    };
+                    map.entry(value).or_default().insert(ev.clone());
+                }
+            }
         }
+        self.tag_index = tag_index;
     }
 
-    /// Count events
-    #[tracing::instrument(skip_all, level = "trace")]
-    pub fn count<I>(&self, filters: I) -> usize
-    where
-        I: IntoIterator<Item = Filter>,
-    {
-        match self.internal_query(filters) {
-            InternalQueryResult::All => self.events.len(),
-            InternalQueryResult::Set(set) => set.len(),
-        }
+    pub fn try_query(&self, filters: Vec<Filter>, order: Order) -> Result<Vec<Event>, Error> {
+        self.check_indexed_tags(&filters)?;
+        Ok(self.query(filters, order))
     }
 
-    #[tracing::instrument(skip_all, level = "trace")]
-    pub fn negentropy_items(&self, filter: Filter) -> Vec<(EventId, Timestamp)> {
-        match self.internal_query([filter]) {
-            InternalQueryResult::All => self
-                .events
-                .iter()
-                .map(|ev| (ev.id, ev.created_at))
-                .collect(),
-            InternalQueryResult::Set(set) => {
-                set.into_iter().map(|ev| (ev.id, ev.created_at)).collect()
+    pub fn set_content_hash_index(&mut self, enable: bool) {
+        if !enable {
+            self.content_hash_index = None;
+        } else if self.content_hash_index.is_none() {
+            let mut index: im::HashMap<u64, EventSet> = im::HashMap::new();
+            for ev in self.events.iter() {
+                index
+                    .entry(content_hash(&ev.content))
+                    .or_default()
+                    .insert(ev.clone());
             }
+            self.content_hash_index = Some(index);
         }
     }
 
-    /// Check if an event with [`EventId`] has been deleted
-    pub fn has_event_id_been_deleted(&self, event_id: &EventId) -> bool {
-        self.deleted_ids.contains(event_id)
-    }
+    /// Get the IDs of the other events with the same content (newest first)
+    pub fn events_with_same_content(&self, event: &Event) -> Vec<EventId> {
+        let set: Option<&EventSet> = self
+            .content_hash_index
+            .as_ref()
+            .and_then(|index| index.get(&content_hash(&event.content)));
 
-    /// Check if event with [`Coordinate`] has been deleted before [`Timestamp`]
-    pub fn has_coordinate_been_deleted(
-        &self,
-        coordinate: &Coordinate,
-        timestamp: &Timestamp,
-    ) -> bool {
-        if let Some(t) = self.deleted_coordinates.get(coordinate) {
-            t >= timestamp
-        } else {
-            false
+        match set {
+            Some(set) => set
+                .iter()
+                // Skip the hash collisions
+                .filter(|ev| ev.id != event.id && ev.content == event.content)
+                .map(|ev| ev.id)
+                .collect(),
+            None => Vec::new(),
         }
     }
 
-    pub fn delete(&mut self, filter: Filter) -> Option<HashSet<EventId>> {
-        match self.internal_query([filter]) {
-            InternalQueryResult::All => {
-                self.clear();
-                None
-            }
-            InternalQueryResult::Set(set) => {
-                let ids: HashSet<EventId> = set.into_iter().map(|ev| ev.id).collect();
-                self.discard_events(&ids);
-                Some(ids)
-            }
-        }
-    }
+    fn remove_from_tag_index(&mut self, ev: &DatabaseEvent) {
+        for (tag, values) in ev.tags_indexes().iter() {
+            let normalize: bool = self.normalize_tags && is_normalized_tag(tag);
+            if let Some(map) = self.tag_index.get_mut(tag) {
+                for value in values.iter() {
+                    let value: String = if normalize {
+                        normalize_tag_value(value)
+                    } else {
+                        value.clone()
+                    };
 
-    pub fn clear(&mut self) {
-        // Get current capacity
-        let capacity: Capacity = self.events.capacity();
+                    if let Some(set) = map.get_mut(&value) {
+                        set.remove(ev);
 
-        // Reset helper to default
-        *self = Self::default();
+                        if set.is_empty() {
+                            map.remove(&value);
+                        }
+                    }
+                }
 
-        // Change capacity
-        self.events.change_capacity(capacity);
+                if map.is_empty() {
+                    self.tag_index.remove(tag);
+                }
+            }
+        }
     }
-}
-
-/// Database Indexes
-#[derive(Debug, Clone, Default)]
-pub struct DatabaseHelper {
-    inner: Arc<RwLock<InternalDatabaseHelper>>,
-}
 
-impl DatabaseHelper {
-    /// Unbounded database helper
-    #[inline]
-    pub fn unbounded() -> Self {
-        Self::default()
+    /// Import [Event]
+    ///
+    /// **This method assume that [`Event`] was already verified**
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn index_event(&mut self, event: &Event) -> DatabaseEventResult {
+        // Check if it's expired or ephemeral (in `internal_index_event` is checked only the raw event expiration)
+        if let Some(res) = unindexed_result(event) {
+            return res;
+        }
+        let now = Timestamp::now();
+        self.internal_index_event(event, &now)
     }
 
-    /// Bounded database helper
-    #[inline]
-    pub fn bounded(max: usize) -> Self {
-        Self {
-            inner: Arc::new(RwLock::new(InternalDatabaseHelper::bounded(max))),
+    /// Index [`Event`] and get the details of the discarded events
+    pub fn index_event_detailed(&mut self, event: &Event) -> DetailedEventResult {
+        if let Some(res) = unindexed_result(event) {
+            return res.into();
         }
+        let now = Timestamp::now();
+        self.internal_index_event_detailed(event, &now)
     }
 
-    /// Bulk index
-    #[tracing::instrument(skip_all)]
-    pub async fn bulk_load(&self, events: BTreeSet<Event>) -> HashSet<EventId> {
-        let mut inner = self.inner.write().await;
-        inner.bulk_load(events)
+    /// Index [`Event`] and record the relay where it was seen
+    ///
+    /// The source is recorded also if the event was already indexed.
+    pub fn index_event_with_source(&mut self, event: &Event, source: Url) -> DetailedEventResult {
+        let res: DetailedEventResult = self.index_event_detailed(event);
+        match res.result.status {
+            EventStatus::Saved | EventStatus::Replaced | EventStatus::Duplicate => {
+                self.source_index
+                    .entry(event.id)
+                    .or_default()
+                    .insert(source);
+            }
+            EventStatus::Rejected
+            | EventStatus::Invalid
+            | EventStatus::Ephemeral
+            | EventStatus::FutureTimestamp
+            | EventStatus::Blocked => {}
+        }
+        res
     }
 
-    /// Bulk import
-    ///
-    /// Take a set of [Event], index them and return **only** the ones that must be stored into the database
+    /// Index events in the given order
     #[tracing::instrument(skip_all)]
-    pub async fn bulk_import(&self, events: BTreeSet<Event>) -> BTreeSet<Event> {
-        let mut inner = self.inner.write().await;
-        inner.bulk_import(events).collect()
+    pub fn bulk_index<I>(&mut self, events: I) -> Vec<DatabaseEventResult>
+    where
+        I: IntoIterator<Item = Event>,
+    {
+        events
+            .into_iter()
+            .map(|event| self.index_event(&event))
+            .collect()
     }
 
-    /// Index [`Event`]
+    /// Index the events of another helper (oldest first), with their sources, and then its tombstones (oldest first)
     ///
-    /// **This method assume that [`Event`] was already verified**
-    #[tracing::instrument(skip_all, level = "trace")]
-    pub async fn index_event(&self, event: &Event) -> DatabaseEventResult {
-        // Check if it's expired or ephemeral
-        if event.is_expired() || event.is_ephemeral() {
-            return DatabaseEventResult::default();
+    /// The tombstones of the events still indexed here are skipped: they are deleted only by their deletion events.
+    pub fn merge(
+        &mut self,
+        events: Vec<(Event, Option<HashSet<Url>>)>,
+        deleted_ids: Vector<EventId>,
+        pending_deletions: Vector<(EventId, PublicKey)>,
+    ) -> Vec<DatabaseEventResult> {
+        let results: Vec<DatabaseEventResult> = events
+            .into_iter()
+            .map(|(event, sources)| {
+                let res: DatabaseEventResult = self.index_event(&event);
+                if let Some(sources) = sources {
+                    if self.ids.contains_key(&event.id) {
+                        self.source_index
+                            .entry(event.id)
+                            .or_default()
+                            .extend(sources);
+                    }
+                }
+                res
+            })
+            .collect();
+
+        // Bounded by `max_deleted_ids`, as the own ones
+        for id in deleted_ids.into_iter() {
+            if !self.ids.contains_key(&id) {
+                self.insert_deleted_id(id);
+            }
+        }
+        for (id, author) in pending_deletions.into_iter() {
+            if !self.ids.contains_key(&id) {
+                self.insert_pending_deletion(id, author);
+            }
         }
 
-        // Acquire write lock
-        let mut inner = self.inner.write().await;
-        inner.index_event(event)
+        results
     }
 
-    /// Get [Event] by ID
-    #[tracing::instrument(skip_all, level = "trace")]
-    pub async fn event_by_id(&self, id: &EventId) -> Option<Event> {
-        let inner = self.inner.read().await;
-        inner.event_by_id(id).cloned()
+    /// Query by authors
+    ///
+    /// The sets of the authors are merged lazily, so only the needed events are visited when a `limit` is set.
+    fn internal_query_by_authors<'a>(
+        &'a self,
+        params: QueryByAuthorsParams,
+        visit: &'a dyn Fn() -> bool,
+    ) -> impl Iterator<Item = &'a DatabaseEvent> + 'a {
+        let QueryByAuthorsParams {
+            authors,
+            since,
+            until,
+        } = params;
+        let sets = authors
+            .into_iter()
+            .filter_map(|author| self.author_index.get(&author));
+        MergeIter::with_window(sets, since, until).take_while(move |_| visit())
     }
 
-    /// Check if event exists
-    #[tracing::instrument(skip_all, level = "trace")]
-    pub async fn has_event(&self, id: &EventId) -> bool {
-        let inner = self.inner.read().await;
-        inner.has_event(id)
+    /// Query by public key
+    fn internal_query_by_author<'a>(
+        &'a self,
+        params: QueryByAuthorParams,
+        visit: &'a dyn Fn() -> bool,
+    ) -> Box<dyn Iterator<Item = &'a DatabaseEvent> + 'a> {
+        let QueryByAuthorParams {
+            author,
+            since,
+            until,
+        } = params;
+        match (
+            self.author_index.get(&author),
+            sort_key_window(since, until),
+        ) {
+            (Some(set), Some(window)) => Box::new(set.range(window).take_while(move |_| visit())),
+            _ => Box::new(iter::empty()),
+        }
     }
 
-    /// Query
-    #[tracing::instrument(skip_all, level = "trace")]
-    pub async fn query<I>(&self, filters: I, order: Order) -> Vec<Event>
-    where
-        I: IntoIterator<Item = Filter>,
-    {
-        let inner = self.inner.read().await;
-        inner.query(filters, order)
+    /// Query by [`Kind`] and [`PublicKeyPrefix`]
+    fn internal_query_by_kind_and_author<'a>(
+        &'a self,
+        params: QueryByKindAndAuthorParams,
+        visit: &'a dyn Fn() -> bool,
+    ) -> Box<dyn Iterator<Item = &'a DatabaseEvent> + 'a> {
+        let QueryByKindAndAuthorParams {
+            kind,
+            author,
+            since,
+            until,
+        } = params;
+        match (
+            self.kind_author_index.get(&(kind, author)),
+            sort_key_window(since, until),
+        ) {
+            (Some(set), Some(window)) => Box::new(set.range(window).take_while(move |_| visit())),
+            _ => Box::new(iter::empty()),
+        }
     }
 
-    /// Count events
-    #[tracing::instrument(skip_all, level = "trace")]
-    pub async fn count<I>(&self, filters: I) -> usize
-    where
-        I: IntoIterator<Item = Filter>,
-    {
-        let inner = self.inner.read().await;
-        inner.count(filters)
-    }
+    /// Query by param. replaceable
+    fn internal_query_param_replaceable(
+        &self,
+        params: QueryByParamReplaceable,
+    ) -> Option<&DatabaseEvent> {
+        let QueryByParamReplaceable {
+            kind,
+            author,
+            identifier,
+            since,
+            until,
+        } = params;
 
-    /// Get negentropy items
-    #[tracing::instrument(skip_all, level = "trace")]
-    pub async fn negentropy_items(&self, filter: Filter) -> Vec<(EventId, Timestamp)> {
-        let inner = self.inner.read().await;
-        inner.negentropy_items(filter)
-    }
+        if !kind.is_parameterized_replaceable() {
+            return None;
+        }
 
-    /// Check if an event with [`EventId`] has been deleted
-    pub async fn has_event_id_been_deleted(&self, event_id: &EventId) -> bool {
-        let inner = self.inner.read().await;
-        inner.has_event_id_been_deleted(event_id)
-    }
+        let ev = self
+            .param_replaceable_index
+            .get(&(kind, author, identifier))?;
 
-    /// Check if event with [`Coordinate`] has been deleted before [`Timestamp`]
-    pub async fn has_coordinate_been_deleted(
-        &self,
-        coordinate: &Coordinate,
-        timestamp: Timestamp,
-    ) -> bool {
-        let inner = self.inner.read().await;
-        inner.has_coordinate_been_deleted(coordinate, &timestamp)
-    }
+        if let Some(since) = since {
+            if ev.created_at < since {
+                return None;
+            }
+        }
 
-    /// Delete all events that match [Filter]
-    ///
-    /// If return `None`, means that all events must be deleted from DB
-    pub async fn delete(&self, filter: Filter) -> Option<HashSet<EventId>> {
-        let mut inner = self.inner.write().await;
-        inner.delete(filter)
-    }
+        if let Some(until) = until {
+            if ev.created_at > until {
+                return None;
+            }
+        }
 
-    /// Clear helper
-    pub async fn clear(&self) {
-        let mut inner = self.inner.write().await;
-        inner.clear();
+        Some(ev)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use nostr::{FromBech32, JsonUtil, Keys, SecretKey};
+    /// Query by IDs
+    ///
+    /// Return only the indexed events that also match the other fields of the filter.
+    fn internal_query_by_ids(&self, filter: Filter) -> impl Iterator<Item = &DatabaseEvent> {
+        let evs: BTreeSet<&DatabaseEvent> = match &filter.ids {
+            Some(ids) => ids
+                .iter()
+                .filter_map(|id| self.ids.get(id))
+                .filter(|ev| self.match_event(&filter, ev))
+                .collect(),
+            None => BTreeSet::new(),
+        };
+        evs.into_iter()
+    }
+
+    /// Query by tags
+    ///
+    /// Candidates are taken from the most selective index (the one with the fewest events) between the authors
+    /// (with kinds, if any) and the tags of the filter, then checked against the whole filter
+    /// (values of the same tag are OR-ed, different tags are AND-ed).
+    ///
+    /// I.e. for `authors: <follows>, kinds: [1], #p: [<me>]` only the notes of the follows or only the events
+    /// mentioning me are checked, whichever are fewer.
+    ///
+    /// The candidates are limited to the `since`/`until` window before checking the filter.
+    fn internal_query_by_tags<'a>(
+        &'a self,
+        filter: Filter,
+        visit: &'a dyn Fn() -> bool,
+    ) -> impl Iterator<Item = &'a DatabaseEvent> + 'a {
+        let authors_sets: Option<Vec<&EventSet>> =
+            filter.authors.as_ref().map(|authors| match &filter.kinds {
+                Some(kinds) => kinds
+                    .iter()
+                    .flat_map(|kind| {
+                        authors
+                            .iter()
+                            .filter_map(move |author| self.kind_author_index.get(&(*kind, *author)))
+                    })
+                    .collect(),
+                None => authors
+                    .iter()
+                    .filter_map(|author| self.author_index.get(author))
+                    .collect(),
+            });
+
+        let tags_sets = filter
+            .generic_tags
+            .iter()
+            .map(|(tag, values)| -> Vec<&EventSet> {
+                match self.tag_index.get(tag) {
+                    Some(map) => values.iter().filter_map(|value| map.get(value)).collect(),
+                    None => Vec::new(),
+                }
+            });
+
+        let candidates: Vec<&EventSet> = authors_sets
+            .into_iter()
+            .chain(tags_sets)
+            .min_by_key(|sets| sets.iter().map(|set| set.len()).sum::<usize>())
+            .unwrap_or_default();
+
+        MergeIter::with_window(candidates, filter.since, filter.until)
+            .take_while(move |_| visit())
+            .filter(move |ev| self.match_event(&filter, ev))
+    }
+
+    /// Search query (NIP-50)
+    ///
+    /// The search string is split in words and an event matches only if its content contains all of them.
+    /// Words are matched entirely: partial words (i.e. `nost` for `nostr`) don't match.
+    fn internal_query_by_search<'a>(
+        &'a self,
+        mut filter: Filter,
+        visit: &'a dyn Fn() -> bool,
+    ) -> Box<dyn Iterator<Item = &'a DatabaseEvent> + 'a> {
+        let words: BTreeSet<String> = filter
+            .search
+            .take()
+            .map(|search| tokenize(&search))
+            .unwrap_or_default();
+
+        if words.is_empty() {
+            return Box::new(self.internal_generic_query(filter, visit));
+        }
+
+        let mut sets: Vec<&EventSet> = Vec::with_capacity(words.len());
+        for word in words.iter() {
+            match self.search_index.get(word) {
+                Some(set) => sets.push(set),
+                None => return Box::new(iter::empty()),
+            }
+        }
+
+        let window = match sort_key_window(filter.since, filter.until) {
+            Some(window) => window,
+            None => return Box::new(iter::empty()),
+        };
+
+        // Iterate the smallest set and check the others
+        sets.sort_by_key(|set| set.len());
+        let smallest: &EventSet = sets.remove(0);
+
+        Box::new(
+            smallest
+                .range(window)
+                .take_while(move |_| visit())
+                .filter(move |ev| {
+                    sets.iter().all(|set| set.contains(*ev)) && self.match_event(&filter, ev)
+                }),
+        )
+    }
+
+    /// Generic query
+    ///
+    /// Events are sorted from newest to oldest, so only the ones between `since` and `until` are visited,
+    /// starting from a range lookup.
+    #[inline]
+    fn internal_generic_query<'a>(
+        &'a self,
+        filter: Filter,
+        visit: &'a dyn Fn() -> bool,
+    ) -> impl Iterator<Item = &'a DatabaseEvent> + 'a {
+        sort_key_window(filter.since, filter.until)
+            .into_iter()
+            .flat_map(move |window| self.events.range(window))
+            .take_while(move |_| visit())
+            .filter(move |event| self.match_event(&filter, event))
+    }
+
+    /// Query by single filter, honoring its `limit`
+    #[inline]
+    fn internal_query_by_filter<'a>(
+        &'a self,
+        filter: Filter,
+    ) -> Box<dyn Iterator<Item = &'a DatabaseEvent> + 'a> {
+        self.internal_query_by_filter_visiting(filter, &visit_all)
+    }
+
+    /// Query by single filter, honoring its `limit`
+    ///
+    /// `visit` is called for every candidate taken from the indexes, also the ones not matching the filter,
+    /// and the iteration stops as soon as it returns `false`.
+    fn internal_query_by_filter_visiting<'a>(
+        &'a self,
+        filter: Filter,
+        visit: &'a dyn Fn() -> bool,
+    ) -> Box<dyn Iterator<Item = &'a DatabaseEvent> + 'a> {
+        // Contradictory time window, no events requested (NIP-01) or empty sets: skip the lookups
+        if validate_filter(&filter).is_err() {
+            return Box::new(iter::empty());
+        }
+
+        let filter: Filter = if self.normalize_tags {
+            normalize_tag_values(filter)
+        } else {
+            filter
+        };
+
+        let limit: Option<usize> = filter.limit;
+
+        let evs: Box<dyn Iterator<Item = &DatabaseEvent>> = match QueryPattern::from(filter) {
+            QueryPattern::Author(params) => self.internal_query_by_author(params, visit),
+            QueryPattern::Authors(params) => {
+                Box::new(self.internal_query_by_authors(params, visit))
+            }
+            QueryPattern::KindAuthor(params) => {
+                self.internal_query_by_kind_and_author(params, visit)
+            }
+            QueryPattern::ParamReplaceable(params) => {
+                match self.internal_query_param_replaceable(params) {
+                    Some(ev) => Box::new(iter::once(ev)),
+                    None => Box::new(iter::empty()),
+                }
+            }
+            QueryPattern::Ids(filter) => Box::new(self.internal_query_by_ids(*filter)),
+            QueryPattern::Tags(filter) => Box::new(self.internal_query_by_tags(*filter, visit)),
+            QueryPattern::Search(filter) => self.internal_query_by_search(*filter, visit),
+            QueryPattern::Generic(filter) => Box::new(self.internal_generic_query(*filter, visit)),
+        };
+
+        match limit {
+            Some(limit) => Box::new(evs.take(limit)),
+            None => evs,
+        }
+    }
+
+    fn internal_query<I>(&self, filters: I) -> InternalQueryResult
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let mut matching_ids: Vec<&DatabaseEvent> = Vec::new();
+        let mut filters_len: usize = 0;
+
+        for filter in filters.into_iter() {
+            if filter.is_empty() {
+                return InternalQueryResult::All;
+            }
+
+            matching_ids.extend(self.internal_query_by_filter(filter));
+            filters_len += 1;
+        }
+
+        // The events of every filter are already sorted and unique: merge only when there are more filters
+        if filters_len > 1 {
+            matching_ids.sort_unstable();
+            matching_ids.dedup_by_key(|ev| ev.id);
+        }
+
+        // Sorted and without duplicates: every event is returned once
+        debug_assert!(
+            matching_ids.windows(2).all(|w| w[0] < w[1]),
+            "query results not sorted or with duplicates"
+        );
+
+        InternalQueryResult::Set(matching_ids)
+    }
+
+    /// Query keeping only the events matching `predicate`
+    ///
+    /// The `limit` of every filter is applied after the `predicate`, so it's never filled by dropped events.
+    fn internal_query_where<I>(
+        &self,
+        filters: I,
+        predicate: &dyn Fn(&DatabaseEvent) -> bool,
+    ) -> Vec<&DatabaseEvent>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let mut matching_ids: Vec<&DatabaseEvent> = Vec::new();
+        let mut filters_len: usize = 0;
+
+        for mut filter in filters.into_iter() {
+            let limit: Option<usize> = filter.limit.take();
+            matching_ids.extend(
+                self.internal_query_by_filter(filter)
+                    .filter(|ev| predicate(ev))
+                    .take(limit.unwrap_or(usize::MAX)),
+            );
+            filters_len += 1;
+        }
+
+        if filters_len > 1 {
+            matching_ids.sort_unstable();
+            matching_ids.dedup_by_key(|ev| ev.id);
+        }
+
+        matching_ids
+    }
+
+    #[inline]
+    pub fn event_by_id(&self, id: &EventId) -> Option<&Event> {
+        self.ids.get(id).map(|e| e.deref())
+    }
+
+    #[inline]
+    pub fn has_event(&self, id: &EventId) -> bool {
+        self.ids.contains_key(id)
+    }
+
+    /// Count events by kind
+    pub fn count_by_kind(&self) -> HashMap<Kind, usize> {
+        self.kind_counts.clone()
+    }
+
+    #[inline]
+    pub fn has_kind(&self, kind: &Kind) -> bool {
+        self.kind_counts.contains_key(kind)
+    }
+
+    pub fn distinct_kinds(&self) -> Vec<Kind> {
+        let mut kinds: Vec<Kind> = self.kind_counts.keys().copied().collect();
+        kinds.sort();
+        kinds
+    }
+
+    /// Count events of an author
+    pub fn count_by_author(&self, author: &PublicKey) -> usize {
+        self.author_index
+            .get(author)
+            .map(|set| set.len())
+            .unwrap_or_default()
+    }
+
+    /// Get the ID of the current version of a parameterized replaceable event
+    pub fn get_addressable(
+        &self,
+        kind: Kind,
+        author: &PublicKey,
+        identifier: &str,
+    ) -> Option<EventId> {
+        let params: QueryByParamReplaceable =
+            QueryByParamReplaceable::new(kind, *author, identifier.to_string());
+        self.internal_query_param_replaceable(params)
+            .map(|ev| ev.id)
+    }
+
+    /// Get the ID of the current version of a replaceable event
+    pub fn get_replaceable(&self, author: &PublicKey, kind: Kind) -> Option<EventId> {
+        if !kind.is_replaceable() {
+            return None;
+        }
+
+        // Replaceable events keep a single entry per author and kind
+        self.kind_author_index
+            .get(&(kind, *author))?
+            .iter()
+            .next()
+            .map(|ev| ev.id)
+    }
+
+    /// Get the IDs of the events of a kind (newest first)
+    pub fn events_of_kind(&self, kind: Kind, limit: Option<usize>) -> Vec<EventId> {
+        let sets = self
+            .kind_authors
+            .get(&kind)
+            .into_iter()
+            .flatten()
+            .filter_map(|author| self.kind_author_index.get(&(kind, *author)));
+        MergeIter::new(sets)
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|ev| ev.id)
+            .collect()
+    }
+
+    /// Get the IDs of the events with a kind in `range` and matching `extra` (newest first)
+    pub fn query_kind_range(&self, range: RangeInclusive<u16>, extra: &Filter) -> Vec<EventId> {
+        let filter: Filter = if self.normalize_tags {
+            normalize_tag_values(extra.clone())
+        } else {
+            extra.clone()
+        };
+        if check_sets(&filter).is_err() || range.is_empty() {
+            return Vec::new();
+        }
+        let since: Option<Timestamp> = filter.since;
+        let until: Option<Timestamp> = filter.until;
+
+        // Only the kinds in the range are visited
+        let kinds = Kind::from(*range.start())..=Kind::from(*range.end());
+        let sets = self.kind_authors.range(kinds).flat_map(|(kind, authors)| {
+            authors
+                .iter()
+                .filter(|author| {
+                    filter
+                        .authors
+                        .as_ref()
+                        .map_or(true, |authors| authors.contains(*author))
+                })
+                .filter_map(move |author| self.kind_author_index.get(&(*kind, *author)))
+        });
+        MergeIter::with_window(sets, since, until)
+            .filter(|ev| self.matches(&filter, ev))
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .map(|ev| ev.id)
+            .collect()
+    }
+
+    /// Count the events in `bucket_secs` wide buckets from `since` to `until` (oldest bucket first)
+    pub fn histogram(
+        &self,
+        since: Timestamp,
+        until: Timestamp,
+        bucket_secs: u64,
+    ) -> Result<Vec<(Timestamp, usize)>, Error> {
+        if bucket_secs == 0 || since > until {
+            return Ok(Vec::new());
+        }
+
+        // The number of buckets comes from the caller: never trust it for the allocation
+        let start: u64 = since.as_u64();
+        let buckets: usize = ((until.as_u64() - start) / bucket_secs)
+            .checked_add(1)
+            .and_then(|buckets| usize::try_from(buckets).ok())
+            .filter(|buckets| *buckets <= MAX_HISTOGRAM_BUCKETS)
+            .ok_or(Error::TooManyBuckets)?;
+        let mut counts: Vec<usize> = vec![0; buckets];
+
+        // Only the events in the window are visited
+        for ev in sort_key_window(Some(since), Some(until))
+            .into_iter()
+            .flat_map(|window| self.events.range(window))
+        {
+            let index: u64 = (ev.created_at.as_u64() - start) / bucket_secs;
+            counts[index as usize] += 1;
+        }
+
+        Ok(counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| (Timestamp::from(start + i as u64 * bucket_secs), count))
+            .collect())
+    }
+
+    /// Count the events referencing each value of a tag (most referenced first)
+    pub fn tag_value_counts(&self, tag: Alphabet, top_n: usize) -> Vec<(String, usize)> {
+        let mut counts: Vec<(String, usize)> =
+            match self.tag_index.get(&SingleLetterTag::lowercase(tag)) {
+                Some(map) => map
+                    .iter()
+                    .filter(|(_, set)| !set.is_empty())
+                    .map(|(value, set)| (value.clone(), set.len()))
+                    .collect(),
+                None => return Vec::new(),
+            };
+        counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+        counts.truncate(top_n);
+        counts
+    }
+
+    /// Get the IDs of the events referencing `event_id` with an `e` tag (newest first)
+    pub fn replies_to(&self, event_id: &EventId, limit: Option<usize>) -> Vec<EventId> {
+        let set: Option<&EventSet> = self
+            .tag_index
+            .get(&SingleLetterTag::lowercase(Alphabet::E))
+            .and_then(|map| map.get(&event_id.to_hex()));
+
+        match set {
+            Some(set) => set
+                .iter()
+                .filter(|ev| ev.id != *event_id)
+                .take(limit.unwrap_or(usize::MAX))
+                .map(|ev| ev.id)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Get the IDs of the events with a `t` tag (newest first)
+    pub fn events_with_hashtag(&self, hashtag: &str, limit: Option<usize>) -> Vec<EventId> {
+        let set: Option<&EventSet> = self
+            .tag_index
+            .get(&SingleLetterTag::lowercase(Alphabet::T))
+            .and_then(|map| map.get(hashtag));
+
+        match set {
+            Some(set) => set
+                .iter()
+                .take(limit.unwrap_or(usize::MAX))
+                .map(|ev| ev.id)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Get the IDs of the events referencing a coordinate with an `a` tag (newest first)
+    pub fn references_coordinate(
+        &self,
+        kind: Kind,
+        author: &PublicKey,
+        identifier: &str,
+        limit: Option<usize>,
+    ) -> Vec<EventId> {
+        let map = match self.tag_index.get(&SingleLetterTag::lowercase(Alphabet::A)) {
+            Some(map) => map,
+            None => return Vec::new(),
+        };
+
+        let coordinate: String = Coordinate::new(kind, *author)
+            .identifier(identifier)
+            .to_string();
+
+        // Coordinates without identifier are also written without the trailing `:`
+        let values: Vec<&str> = match coordinate.strip_suffix(':') {
+            Some(short) => vec![coordinate.as_str(), short],
+            None => vec![coordinate.as_str()],
+        };
+
+        let sets = values.into_iter().filter_map(|value| map.get(value));
+        MergeIter::new(sets)
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|ev| ev.id)
+            .collect()
+    }
+
+    /// Get the timestamp of the newest event of an author
+    pub fn latest_created_at(&self, author: &PublicKey) -> Option<Timestamp> {
+        self.author_index
+            .get(author)
+            .and_then(|set| set.get_min())
+            .map(|ev| ev.created_at)
+    }
+
+    /// Get the timestamp of the newest event
+    pub fn newest_timestamp(&self) -> Option<Timestamp> {
+        self.events.iter().next().map(|ev| ev.created_at)
+    }
+
+    /// Get IDs of all the indexed events (newest first)
+    pub fn all_event_ids(&self) -> Vec<EventId> {
+        self.events.iter().map(|ev| ev.id).collect()
+    }
+
+    /// Get the newest `n` events
+    pub fn query_recent_global(&self, n: usize) -> Vec<Event> {
+        let events: ordset::Iter<'_, DatabaseEvent> = match self.recent_capacity {
+            Some(max) if n <= max => self.recent.iter(),
+            _ => self.events.iter(),
+        };
+        events.take(n).map(|ev| ev.deref().clone()).collect()
+    }
+
+    /// Query
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn query<I>(&self, filters: I, order: Order) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        match self.internal_query(filters) {
+            InternalQueryResult::All => match order {
+                Order::Asc => self
+                    .events
+                    .iter()
+                    .rev()
+                    .map(|ev| ev.deref().clone())
+                    .collect(),
+                Order::Desc => self.events.iter().map(|ev| ev.deref().clone()).collect(),
+            },
+            InternalQueryResult::Set(set) => match order {
+                Order::Asc => set.into_iter().rev().map(|ev| ev.deref().clone()).collect(),
+                Order::Desc => set.into_iter().map(|ev| ev.deref().clone()).collect(),
+            },
+        }
+    }
+
+    /// Query and drop the events published by `exclude_authors` or with one of `exclude_kinds`
+    ///
+    /// The `limit` of every [Filter] is applied after the exclusions.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn query_excluding<I>(
+        &self,
+        filters: I,
+        order: Order,
+        exclude_authors: &HashSet<PublicKey>,
+        exclude_kinds: &HashSet<Kind>,
+    ) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let is_allowed = |ev: &DatabaseEvent| -> bool {
+            !exclude_authors.contains(&ev.pubkey) && !exclude_kinds.contains(&ev.kind)
+        };
+
+        let set: Vec<&DatabaseEvent> = self.internal_query_where(filters, &is_allowed);
+        match order {
+            Order::Asc => set.into_iter().rev().map(|ev| ev.deref().clone()).collect(),
+            Order::Desc => set.into_iter().map(|ev| ev.deref().clone()).collect(),
+        }
+    }
+
+    /// Query the events seen on at least one of the `sources`
+    ///
+    /// The `limit` of every [Filter] is applied after the source restriction.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn query_from_sources<I>(
+        &self,
+        filters: I,
+        order: Order,
+        sources: &HashSet<Url>,
+    ) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let is_allowed = |ev: &DatabaseEvent| -> bool {
+            self.source_index
+                .get(&ev.id)
+                .is_some_and(|seen| !seen.is_disjoint(sources))
+        };
+
+        let set: Vec<&DatabaseEvent> = self.internal_query_where(filters, &is_allowed);
+        match order {
+            Order::Asc => set.into_iter().rev().map(|ev| ev.deref().clone()).collect(),
+            Order::Desc => set.into_iter().map(|ev| ev.deref().clone()).collect(),
+        }
+    }
+
+    /// Get the IDs of the `candidate_ids` matching `filter` (newest first)
+    pub fn query_within_ids(
+        &self,
+        candidate_ids: &HashSet<EventId>,
+        filter: &Filter,
+    ) -> Vec<EventId> {
+        let ids: HashSet<EventId> = match &filter.ids {
+            Some(ids) => candidate_ids
+                .iter()
+                .filter(|id| ids.contains(id))
+                .copied()
+                .collect(),
+            None => candidate_ids.iter().copied().collect(),
+        };
+
+        if ids.is_empty() {
+            return Vec::new();
+        }
+
+        let mut filter: Filter = filter.clone();
+        filter.ids = Some(ids);
+        self.internal_query_by_filter(filter)
+            .map(|ev| ev.id)
+            .collect()
+    }
+
+    /// Get the IDs of the events indexed at or after the `since` position, and the position to continue from
+    pub fn events_received_since(&self, since: u64, limit: usize) -> (Vec<EventId>, u64) {
+        let mut next: u64 = since;
+        let ids: Vec<EventId> = self
+            .received_order
+            .range(since..)
+            .take(limit)
+            .map(|(received, id)| {
+                next = received + 1;
+                *id
+            })
+            .collect();
+        (ids, next)
+    }
+
+    /// Query events sorted by `order_by`
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn query_ordered_by<I>(&self, filters: I, order: Order, order_by: OrderBy) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        match order_by {
+            OrderBy::CreatedAt => self.query(filters, order),
+            OrderBy::ReceivedAt => {
+                let mut filters: Vec<(Filter, usize)> = filters
+                    .into_iter()
+                    .map(|mut filter| {
+                        let limit: usize = filter.limit.take().unwrap_or(usize::MAX);
+                        let filter: Filter = if self.normalize_tags {
+                            normalize_tag_values(filter)
+                        } else {
+                            filter
+                        };
+                        (filter, limit)
+                    })
+                    .collect();
+
+                // Walk from the last received, so the `limit` of every filter keeps the last received events
+                let mut evs: Vec<&DatabaseEvent> = Vec::new();
+                for (_, id) in self.received_order.iter().rev() {
+                    if filters.iter().all(|(_, remaining)| *remaining == 0) {
+                        break;
+                    }
+
+                    let ev: &DatabaseEvent = match self.ids.get(id) {
+                        Some(ev) => ev,
+                        None => continue,
+                    };
+                    let mut matched: bool = false;
+                    for (filter, remaining) in filters.iter_mut() {
+                        if *remaining > 0 && self.matches(filter, ev) {
+                            *remaining -= 1;
+                            matched = true;
+                        }
+                    }
+                    if matched {
+                        evs.push(ev);
+                    }
+                }
+
+                match order {
+                    Order::Asc => evs.into_iter().rev().map(|ev| ev.deref().clone()).collect(),
+                    Order::Desc => evs.into_iter().map(|ev| ev.deref().clone()).collect(),
+                }
+            }
+        }
+    }
+
+    /// Query events, with the ones of the `preferred` authors first among the events with the same timestamp
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn query_with_preferred_authors<I>(
+        &self,
+        filters: I,
+        order: Order,
+        preferred: &HashSet<PublicKey>,
+    ) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let mut evs: Vec<&DatabaseEvent> = match self.internal_query(filters) {
+            InternalQueryResult::All => self.events.iter().collect(),
+            InternalQueryResult::Set(set) => set,
+        };
+
+        if let Order::Asc = order {
+            evs.reverse();
+        }
+
+        // Stable sort: the order of the events with the same timestamp and preference is kept
+        let is_preferred = |ev: &DatabaseEvent| preferred.contains(&ev.pubkey);
+        evs.sort_by(|a, b| {
+            let by_timestamp: Ordering = match order {
+                Order::Asc => a.created_at.cmp(&b.created_at),
+                Order::Desc => b.created_at.cmp(&a.created_at),
+            };
+            by_timestamp.then_with(|| is_preferred(b).cmp(&is_preferred(a)))
+        });
+
+        evs.into_iter().map(|ev| ev.deref().clone()).collect()
+    }
+
+    /// Query the IDs of the newest events matching `filter`, with at most `per_author_cap` events of every author
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn query_diversified(
+        &self,
+        filter: &Filter,
+        overall_limit: usize,
+        per_author_cap: usize,
+    ) -> Vec<EventId> {
+        let mut filter: Filter = filter.clone();
+        // Applied after the cap, so it's never filled by the skipped events
+        let limit: usize = filter
+            .limit
+            .take()
+            .map_or(overall_limit, |limit| limit.min(overall_limit));
+
+        let evs: Box<dyn Iterator<Item = &DatabaseEvent>> = if filter.is_empty() {
+            Box::new(self.events.iter())
+        } else {
+            self.internal_query_by_filter(filter)
+        };
+
+        let mut per_author: HashMap<PublicKey, usize> = HashMap::new();
+        evs.filter(|ev| {
+            let count: &mut usize = per_author.entry(ev.pubkey).or_default();
+            if *count < per_author_cap {
+                *count += 1;
+                true
+            } else {
+                false
+            }
+        })
+        .take(limit)
+        .map(|ev| ev.id)
+        .collect()
+    }
+
+    /// Query the IDs of the events matching `filter` (newest first), giving up when `deadline` is reached
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn query_with_deadline(
+        &self,
+        filter: &Filter,
+        deadline: Instant,
+    ) -> Result<Vec<EventId>, Error> {
+        // Check the deadline while visiting the candidates, also the ones not matching the filter
+        let visited: Cell<usize> = Cell::new(0);
+        let timed_out: Cell<bool> = Cell::new(false);
+        let visit = || -> bool {
+            let index: usize = visited.get();
+            visited.set(index + 1);
+            if index % DEADLINE_CHECK_INTERVAL == 0 && Instant::now() >= deadline {
+                timed_out.set(true);
+                return false;
+            }
+            true
+        };
+
+        let ids: Vec<EventId> = self
+            .internal_query_by_filter_visiting(filter.clone(), &visit)
+            .map(|ev| ev.id)
+            .collect();
+
+        if timed_out.get() {
+            return Err(Error::QueryTimeout);
+        }
+
+        Ok(ids)
+    }
+
+    /// Query the IDs of the events matching `filter` without any `missing` tag (newest first)
+    ///
+    /// The `limit` of the [Filter] is applied after the exclusion.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn query_missing_tag(&self, filter: &Filter, missing: Alphabet) -> Vec<EventId> {
+        let tag: SingleLetterTag = SingleLetterTag::lowercase(missing);
+        let mut filter: Filter = filter.clone();
+        let limit: Option<usize> = filter.limit.take();
+        self.internal_query_by_filter(filter)
+            .filter(|ev| !ev.tags_indexes().contains_key(&tag))
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|ev| ev.id)
+            .collect()
+    }
+
+    /// Query the events created within `within` before `now`
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn query_recent(
+        &self,
+        filter: Filter,
+        within: Duration,
+        now: &Timestamp,
+        order: Order,
+    ) -> Vec<Event> {
+        let filter: Filter = filter.since(*now - within);
+        self.query([filter], order)
+    }
+
+    /// Query a page of events (newest first), starting after `cursor`
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn query_page(
+        &self,
+        mut filter: Filter,
+        cursor: Option<Cursor>,
+        page_size: usize,
+    ) -> (Vec<Event>, Option<Cursor>) {
+        let page_size: usize = filter
+            .limit
+            .take()
+            .map_or(page_size, |limit| limit.min(page_size));
+
+        // Seek to the second of the cursor, then skip the events up to it within that second
+        if let Some(cursor) = &cursor {
+            filter.until = Some(
+                filter
+                    .until
+                    .map_or(cursor.created_at, |until| until.min(cursor.created_at)),
+            );
+        }
+
+        // Take one more event to know if there is a next page
+        let mut events: Vec<Event> = self
+            .internal_query_by_filter(filter)
+            .skip_while(|ev| cursor.is_some_and(|cursor| !cursor.is_before(ev)))
+            .take(page_size.saturating_add(1))
+            .map(|ev| ev.deref().clone())
+            .collect();
+
+        let next: Option<Cursor> = if events.len() > page_size {
+            events.truncate(page_size);
+            events.last().map(|ev| Cursor {
+                created_at: ev.created_at,
+                id: ev.id,
+            })
+        } else {
+            None
+        };
+
+        (events, next)
+    }
+
+    /// Query events from oldest to newest, the `limit` of every filter keeping the oldest events
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn query_oldest<I>(&self, filters: I) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let mut matching_ids: Vec<&DatabaseEvent> = Vec::new();
+        let mut filters_len: usize = 0;
+
+        for mut filter in filters.into_iter() {
+            let limit: usize = filter.limit.take().unwrap_or(usize::MAX);
+
+            // Walk from the oldest event only if all of them match
+            if filter.is_empty() {
+                matching_ids.extend(self.events.iter().rev().take(limit));
+            } else {
+                let evs: Vec<&DatabaseEvent> = self.internal_query_by_filter(filter).collect();
+                let skip: usize = evs.len().saturating_sub(limit);
+                matching_ids.extend(evs.into_iter().skip(skip).rev());
+            }
+            filters_len += 1;
+        }
+
+        if filters_len > 1 {
+            matching_ids.sort_unstable_by(|a, b| b.cmp(a));
+            matching_ids.dedup_by_key(|ev| ev.id);
+        }
+
+        matching_ids
+            .into_iter()
+            .map(|ev| ev.deref().clone())
+            .collect()
+    }
+
+    /// Query and keep only the newest `limit` events of the union
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn query_with_limit<I>(&self, filters: I, order: Order, limit: usize) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let mut events: Vec<Event> = match self.internal_query(filters) {
+            InternalQueryResult::All => self
+                .events
+                .iter()
+                .take(limit)
+                .map(|ev| ev.deref().clone())
+                .collect(),
+            InternalQueryResult::Set(set) => set
+                .into_iter()
+                .take(limit)
+                .map(|ev| ev.deref().clone())
+                .collect(),
+        };
+
+        if order == Order::Asc {
+            events.reverse();
+        }
+
+        events
+    }
+
+    /// Count events
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn count<I>(&self, filters: I) -> usize
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let mut filters = filters.into_iter();
+        match (filters.next(), filters.next()) {
+            // Single filter: no need to collect and de-duplicate the events
+            (Some(filter), None) => {
+                if filter.is_empty() {
+                    self.events.len()
+                } else {
+                    self.internal_query_by_filter(filter).count()
+                }
+            }
+            (first, second) => {
+                match self.internal_query(first.into_iter().chain(second).chain(filters)) {
+                    InternalQueryResult::All => self.events.len(),
+                    InternalQueryResult::Set(set) => set.len(),
+                }
+            }
+        }
+    }
+
+    pub fn explain(&self, filter: &Filter) -> QueryExplain {
+        let mut filter: Filter = if self.normalize_tags {
+            normalize_tag_values(filter.clone())
+        } else {
+            filter.clone()
+        };
+        let limit: Option<usize> = filter.limit.take();
+
+        let mut constraints: Vec<(String, usize)> = Vec::new();
+        let mut candidates: Vec<usize> = Vec::new();
+        // Intersection of the events matching the constraints so far
+        let mut running: Option<HashSet<EventId>> = None;
+        let mut step = |name: String, ids: HashSet<EventId>| {
+            constraints.push((name, ids.len()));
+            let ids: HashSet<EventId> = match running.take() {
+                Some(running) => running.intersection(&ids).copied().collect(),
+                None => ids,
+            };
+            candidates.push(ids.len());
+            running = Some(ids);
+        };
+
+        if let Some(ids) = &filter.ids {
+            step(
+                String::from("ids"),
+                ids.iter()
+                    .filter(|id| self.ids.contains_key(*id))
+                    .copied()
+                    .collect(),
+            );
+        }
+
+        if let Some(authors) = &filter.authors {
+            step(
+                String::from("authors"),
+                authors
+                    .iter()
+                    .filter_map(|author| self.author_index.get(author))
+                    .flatten()
+                    .map(|ev| ev.id)
+                    .collect(),
+            );
+        }
+
+        if let Some(kinds) = &filter.kinds {
+            step(
+                String::from("kinds"),
+                kinds
+                    .iter()
+                    .filter_map(|kind| self.kind_authors.get(kind).map(|authors| (kind, authors)))
+                    .flat_map(|(kind, authors)| {
+                        authors
+                            .iter()
+                            .filter_map(move |author| self.kind_author_index.get(&(*kind, *author)))
+                    })
+                    .flatten()
+                    .map(|ev| ev.id)
+                    .collect(),
+            );
+        }
+
+        for (tag, values) in filter.generic_tags.iter() {
+            // The same event can have more values of a tag: count it once
+            let ids: HashSet<EventId> = match self.tag_index.get(tag) {
+                Some(map) => values
+                    .iter()
+                    .filter_map(|value| map.get(value))
+                    .flatten()
+                    .map(|ev| ev.id)
+                    .collect(),
+                None => HashSet::new(),
+            };
+            step(format!("#{tag}"), ids);
+        }
+
+        if let Some(search) = &filter.search {
+            let mut sets: Vec<Option<&EventSet>> = tokenize(search)
+                .iter()
+                .map(|word| self.search_index.get(word))
+                .collect();
+            let ids: HashSet<EventId> = match sets.pop() {
+                Some(Some(first)) => first
+                    .iter()
+                    .filter(|ev| {
+                        sets.iter()
+                            .all(|set| set.is_some_and(|set| set.contains(*ev)))
+                    })
+                    .map(|ev| ev.id)
+                    .collect(),
+                Some(None) => HashSet::new(),
+                // No words: every event matches
+                None => self.events.iter().map(|ev| ev.id).collect(),
+            };
+            step(String::from("search"), ids);
+        }
+
+        let (index, matched): (QueryIndex, usize) = if filter.is_empty() {
+            (QueryIndex::Scan, self.events.len())
+        } else {
+            (
+                QueryPattern::from(filter.clone()).index(),
+                self.internal_query_by_filter(filter).count(),
+            )
+        };
+
+        QueryExplain {
+            index,
+            constraints,
+            candidates,
+            matched,
+            returned: limit.map_or(matched, |limit| matched.min(limit)),
+        }
+    }
+
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn negentropy_items(&self, filter: Filter) -> Vec<(EventId, Timestamp)> {
+        match self.internal_query([filter]) {
+            InternalQueryResult::All => self
+                .events
+                .iter()
+                .map(|ev| (ev.id, ev.created_at))
+                .collect(),
+            InternalQueryResult::Set(set) => {
+                set.into_iter().map(|ev| (ev.id, ev.created_at)).collect()
+            }
+        }
+    }
+
+    /// Check if an event with [`EventId`] has been deleted
+    pub fn has_event_id_been_deleted(&self, event_id: &EventId) -> bool {
+        self.deleted_ids.contains(event_id)
+    }
+
+    /// Check if event with [`Coordinate`] has been deleted before [`Timestamp`]
+    pub fn has_coordinate_been_deleted(
+        &self,
+        coordinate: &Coordinate,
+        timestamp: &Timestamp,
+    ) -> bool {
+        if let Some(t) = self.deleted_coordinates.get(coordinate) {
+            t >= timestamp
+        } else {
+            false
+        }
+    }
+
+    pub fn remove_event(&mut self, id: &EventId) -> bool {
+        match self.ids.get(id).cloned() {
+            Some(ev) => {
+                self.events.remove(&ev);
+                self.discard_event(ev);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear_author(&mut self, author: &PublicKey) -> usize {
+        let evs: Vec<DatabaseEvent> = match self.author_index.remove(author) {
+            Some(set) => set.into_iter().collect(),
+            None => return 0,
+        };
+
+        for ev in evs.iter() {
+            self.events.remove(ev);
+            self.discard_event(ev.clone());
+        }
+
+        // Drop the now empty sets of the author
+        self.kind_author_index.retain(|(_, a), _| a != author);
+
+        evs.len()
+    }
+
+    pub fn check_integrity(&self) -> Result<(), Error> {
+        match self.verify_integrity().into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the index entries referencing events that aren't stored
+    fn dangling_events(&self) -> BTreeSet<&DatabaseEvent> {
+        self.ids
+            .values()
+            .chain(self.author_index.values().flatten())
+            .chain(self.kind_author_index.values().flatten())
+            .chain(self.param_replaceable_index.values())
+            .chain(
+                self.tag_index
+                    .values()
+                    .flat_map(|map| map.values())
+                    .flatten(),
+            )
+            .chain(self.search_index.values().flatten())
+            .filter(|ev| !self.events.contains(*ev))
+            .collect()
+    }
+
+    pub fn verify_integrity(&self) -> Vec<Error> {
+        let mut issues: Vec<Error> = self
+            .events
+            .iter()
+            .filter(|ev| !self.ids.contains_key(&ev.id))
+            .map(|ev| Error::MissingEvent(ev.id))
+            .collect();
+
+        let dangling: HashSet<EventId> =
+            self.dangling_events().into_iter().map(|ev| ev.id).collect();
+        issues.extend(dangling.iter().map(|id| Error::DanglingEvent(*id)));
+
+        // Sources of events never stored
+        issues.extend(
+            self.source_index
+                .keys()
+                .filter(|id| !self.ids.contains_key(id) && !dangling.contains(id))
+                .map(|id| Error::DanglingEvent(*id)),
+        );
+
+        issues
+    }
+
+    pub fn repair(&mut self) -> usize {
+        let dangling: Vec<DatabaseEvent> = self.dangling_events().into_iter().cloned().collect();
+        let missing: Vec<DatabaseEvent> = self
+            .events
+            .iter()
+            .filter(|ev| !self.ids.contains_key(&ev.id))
+            .cloned()
+            .collect();
+        let issues: usize = self.verify_integrity().len();
+
+        // Remove the entries of the events not stored
+        for ev in dangling.into_iter() {
+            self.discard_event(ev);
+        }
+
+        // Index again the stored events, keeping their sources and indexing order
+        for ev in missing.into_iter() {
+            let sources: Option<HashSet<Url>> = self.source_index.remove(&ev.id);
+            let received: Option<u64> = self.received_index.get(&ev.id).copied();
+            self.discard_event(ev.clone());
+            if let Some(sources) = sources {
+                self.source_index.insert(ev.id, sources);
+            }
+            let id: EventId = ev.id;
+            self.insert_in_indexes(ev);
+            if let Some(received) = received {
+                if let Some(new) = self.received_index.insert(id, received) {
+                    self.received_order.remove(&new);
+                }
+                self.received_order.insert(received, id);
+            }
+        }
+
+        self.source_index.retain(|id, _| self.ids.contains_key(id));
+
+        issues
+    }
+
+    pub fn reindex_event(&mut self, event: &Event) -> DatabaseEventResult {
+        self.remove_event(&event.id);
+        self.index_event(event)
+    }
+
+    pub fn prune_expired(&mut self, now: &Timestamp) -> HashSet<EventId> {
+        self.prune_expired_before(now).into_iter().collect()
+    }
+
+    #[inline]
+    pub fn next_expiration(&self) -> Option<Timestamp> {
+        self.expiration_index.keys().next().copied()
+    }
+
+    /// Remove the events expired before `now` and get their IDs, sorted by expiration
+    pub fn prune_expired_before(&mut self, now: &Timestamp) -> Vec<EventId> {
+        let expired: Vec<EventId> = self
+            .expiration_index
+            .range(..*now)
+            .flat_map(|(_, ids)| ids.iter().copied())
+            .collect();
+        // Expired, not deleted: no tombstone
+        for id in expired.iter() {
+            self.remove_event(id);
+        }
+        expired
+    }
+
+    pub fn stats(&self) -> DatabaseHelperStats {
+        let authors: usize = self
+            .author_index
+            .values()
+            .filter(|set| !set.is_empty())
+            .count();
+        let index_entries: usize = self.ids.len()
+            + self
+                .author_index
+                .values()
+                .map(|set| set.len())
+                .sum::<usize>()
+            + self
+                .kind_author_index
+                .values()
+                .map(|set| set.len())
+                .sum::<usize>()
+            + self.param_replaceable_index.len()
+            + self
+                .tag_index
+                .values()
+                .flat_map(|map| map.values())
+                .map(|set| set.len())
+                .sum::<usize>()
+            + self
+                .search_index
+                .values()
+                .map(|set| set.len())
+                .sum::<usize>();
+
+        DatabaseHelperStats {
+            events: self.events.len(),
+            authors,
+            kinds: self.kind_counts.len(),
+            param_replaceable: self.param_replaceable_index.len(),
+            tag_values: self.tag_index.values().map(|map| map.len()).sum(),
+            index_entries,
+            deleted_ids: self.deleted_ids.len(),
+            deleted_coordinates: self.deleted_coordinates.len(),
+            #[cfg(feature = "index-timing")]
+            index_timings: self.index_timings,
+        }
+    }
+
+    pub fn delete(&mut self, filter: Filter) -> Option<HashSet<EventId>> {
+        match self.internal_query([filter]) {
+            InternalQueryResult::All => {
+                self.clear();
+                None
+            }
+            InternalQueryResult::Set(set) => {
+                let ids: HashSet<EventId> = set.into_iter().map(|ev| ev.id).collect();
+                self.discard_events(&ids);
+                Some(ids)
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        // Get current capacity and options
+        let capacity: Capacity = self.events.capacity();
+        let normalize_tags: bool = self.normalize_tags;
+        let max_future_skew: Option<Duration> = self.max_future_skew;
+        let min_created_at: Option<Timestamp> = self.min_created_at;
+        let max_deleted_ids: Option<usize> = self.max_deleted_ids;
+        let recent_capacity: Option<usize> = self.recent_capacity;
+        let content_hash_index: bool = self.content_hash_index.is_some();
+        let indexed_tags: Option<HashSet<Alphabet>> = self.indexed_tags.take();
+
+        // Reset helper to default
+        *self = Self::default();
+
+        // Restore capacity and options
+        self.events.change_capacity(capacity);
+        self.normalize_tags = normalize_tags;
+        self.max_future_skew = max_future_skew;
+        self.min_created_at = min_created_at;
+        self.max_deleted_ids = max_deleted_ids;
+        self.set_recent_capacity(recent_capacity);
+        self.set_content_hash_index(content_hash_index);
+        self.indexed_tags = indexed_tags;
+    }
+
+    pub fn reset_soft(&mut self) {
+        let next_received: u64 = self.next_received;
+        self.clear();
+        self.next_received = next_received;
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        buf.push(SNAPSHOT_VERSION);
+        buf.push(self.normalize_tags as u8);
+        match self.max_future_skew {
+            Some(skew) => {
+                buf.push(1);
+                buf.extend(skew.as_secs().to_be_bytes());
+            }
+            None => buf.push(0),
+        }
+        match self.min_created_at {
+            Some(min_created_at) => {
+                buf.push(1);
+                buf.extend(min_created_at.as_u64().to_be_bytes());
+            }
+            None => buf.push(0),
+        }
+        match self.max_deleted_ids {
+            Some(max) => {
+                buf.push(1);
+                write_len(&mut buf, max);
+            }
+            None => buf.push(0),
+        }
+        match self.recent_capacity {
+            Some(max) => {
+                buf.push(1);
+                write_len(&mut buf, max);
+            }
+            None => buf.push(0),
+        }
+        buf.push(self.content_hash_index.is_some() as u8);
+        match &self.indexed_tags {
+            Some(letters) => {
+                buf.push(1);
+                write_len(&mut buf, letters.len());
+                for letter in letters.iter() {
+                    buf.push(SingleLetterTag::lowercase(*letter).as_char() as u8);
+                }
+            }
+            None => buf.push(0),
+        }
+
+        match self.events.capacity() {
+            Capacity::Unbounded => buf.push(0),
+            Capacity::Bounded { max, policy } => {
+                buf.push(match policy {
+                    OverCapacityPolicy::First => 1,
+                    OverCapacityPolicy::Last => 2,
+                });
+                write_len(&mut buf, max);
+            }
+        }
+
+        // In indexing order, with their positions, to keep the cursors of `events_received_since`
+        let mut events: Vec<(u64, &DatabaseEvent)> = self
+            .events
+            .iter()
+            .map(|ev| {
+                (
+                    self.received_index.get(&ev.id).copied().unwrap_or_default(),
+                    ev,
+                )
+            })
+            .collect();
+        events.sort_by_key(|(received, _)| *received);
+        write_len(&mut buf, events.len());
+        for (received, ev) in events.into_iter() {
+            write_bytes(&mut buf, ev.as_json().as_bytes());
+            buf.extend(received.to_be_bytes());
+        }
+        buf.extend(self.next_received.to_be_bytes());
+
+        // In deletion order, to drop the oldest first also after loading the snapshot
+        write_len(&mut buf, self.deleted_ids_order.len());
+        for id in self.deleted_ids_order.iter() {
+            buf.extend(id.as_bytes());
+        }
+
+        write_len(&mut buf, self.deleted_coordinates.len());
+        for (coordinate, timestamp) in self.deleted_coordinates.iter() {
+            write_bytes(&mut buf, coordinate.to_string().as_bytes());
+            buf.extend(timestamp.as_u64().to_be_bytes());
+        }
+
+        // In receiving order, like the deleted IDs
+        write_len(&mut buf, self.pending_deletions_order.len());
+        for (id, author) in self.pending_deletions_order.iter() {
+            buf.extend(id.as_bytes());
+            buf.extend(author.to_bytes());
+        }
+
+        write_len(&mut buf, self.source_index.len());
+        for (id, sources) in self.source_index.iter() {
+            buf.extend(id.as_bytes());
+            write_len(&mut buf, sources.len());
+            for url in sources.iter() {
+                write_bytes(&mut buf, url.as_str().as_bytes());
+            }
+        }
+
+        buf
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        let mut reader = SnapshotReader { bytes };
+
+        let version: u8 = reader.u8()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(Error::UnsupportedSnapshotVersion(version));
+        }
+
+        let mut helper: Self = Self {
+            normalize_tags: reader.u8()? != 0,
+            ..Default::default()
+        };
+
+        helper.max_future_skew = match reader.u8()? {
+            0 => None,
+            1 => Some(Duration::from_secs(reader.u64()?)),
+            _ => return Err(Error::InvalidSnapshot),
+        };
+        helper.min_created_at = match reader.u8()? {
+            0 => None,
+            1 => Some(Timestamp::from(reader.u64()?)),
+            _ => return Err(Error::InvalidSnapshot),
+        };
+        helper.max_deleted_ids = match reader.u8()? {
+            0 => None,
+            1 => Some(reader.len()?),
+            _ => return Err(Error::InvalidSnapshot),
+        };
+        // Set before indexing the events
+        match reader.u8()? {
+            0 => {}
+            1 => helper.set_recent_capacity(Some(reader.len()?)),
+            _ => return Err(Error::InvalidSnapshot),
+        }
+        helper.set_content_hash_index(reader.u8()? != 0);
+        // Set before indexing the events
+        helper.indexed_tags = match reader.u8()? {
+            0 => None,
+            1 => {
+                let mut letters: HashSet<Alphabet> = HashSet::new();
+                for _ in 0..reader.len()? {
+                    let tag: SingleLetterTag = SingleLetterTag::from_char(reader.u8()? as char)
+                        .map_err(|_| Error::InvalidSnapshot)?;
+                    letters.insert(tag.character);
+                }
+                Some(letters)
+            }
+            _ => return Err(Error::InvalidSnapshot),
+        };
+
+        let capacity: Capacity = match reader.u8()? {
+            0 => Capacity::Unbounded,
+            1 => Capacity::Bounded {
+                max: reader.len()?,
+                policy: OverCapacityPolicy::First,
+            },
+            2 => Capacity::Bounded {
+                max: reader.len()?,
+                policy: OverCapacityPolicy::Last,
+            },
+            _ => return Err(Error::InvalidSnapshot),
+        };
+        helper.events.change_capacity(capacity);
+
+        // The events were already accepted: skip the replaceable and deletion checks
+        for _ in 0..reader.len()? {
+            let json: &str = reader.str()?;
+            let event: Event = Event::from_json(json).map_err(|_| Error::InvalidSnapshot)?;
+            let received: u64 = reader.u64()?;
+            // Sorted by position, without duplicates
+            if received < helper.next_received {
+                return Err(Error::InvalidSnapshot);
+            }
+            let e: DatabaseEvent = DatabaseEvent::new(event);
+            let res: InsertResult<DatabaseEvent> = helper.events.insert(e.clone());
+            // Never duplicated nor over capacity: the popped event would be still indexed
+            if !res.inserted || res.pop.is_some() {
+                return Err(Error::InvalidSnapshot);
+            }
+            // Indexed at its own position
+            helper.next_received = received;
+            helper.insert_in_indexes(e);
+        }
+        let next_received: u64 = reader.u64()?;
+        if next_received < helper.next_received {
+            return Err(Error::InvalidSnapshot);
+        }
+        helper.next_received = next_received;
+
+        for _ in 0..reader.len()? {
+            let id: EventId = reader.event_id()?;
+            // The deleted events are never indexed
+            if helper.ids.contains_key(&id) {
+                return Err(Error::InvalidSnapshot);
+            }
+            helper.insert_deleted_id(id);
+        }
+
+        for _ in 0..reader.len()? {
+            let coordinate: Coordinate =
+                Coordinate::parse(reader.str()?).map_err(|_| Error::InvalidSnapshot)?;
+            let timestamp: Timestamp = Timestamp::from(reader.u64()?);
+            helper.deleted_coordinates.insert(coordinate, timestamp);
+        }
+
+        for _ in 0..reader.len()? {
+            let id: EventId = reader.event_id()?;
+            let author: PublicKey = reader.public_key()?;
+            helper.insert_pending_deletion(id, author);
+        }
+
+        for _ in 0..reader.len()? {
+            let id: EventId = reader.event_id()?;
+            let mut sources: HashSet<Url> = HashSet::new();
+            for _ in 0..reader.len()? {
+                sources.insert(Url::parse(reader.str()?).map_err(|_| Error::InvalidSnapshot)?);
+            }
+            helper.source_index.insert(id, sources);
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(Error::InvalidSnapshot);
+        }
+
+        Ok(helper)
+    }
+}
+
+/// Observer of [DatabaseHelper] operations (i.e. to collect metrics)
+///
+/// Methods are called after releasing the lock. All of them are no-op by default.
+pub trait DatabaseHelperObserver: fmt::Debug + Send + Sync {
+    /// Called after indexing an event
+    fn on_index(&self, _result: &DatabaseEventResult) {}
+
+    /// Called after a query by [Filter]s, with the filters, the number of matching events and the time taken
+    ///
+    /// The failed queries are reported with `0` matching events. The lazy queries ([`DatabaseHelper::query_iter`])
+    /// are reported when exhausted, with the time taken to resolve the events.
+    fn on_query(&self, _filters: &[Filter], _matched: usize, _elapsed: Duration) {}
+}
+
+/// Admission policy decision (see [`DatabaseHelper::with_policy`])
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PolicyDecision {
+    /// Index the event
+    Accept,
+    /// Don't index the event, for the given reason
+    Reject(String),
+}
+
+type PolicyFn = dyn Fn(&Event) -> PolicyDecision + Send + Sync;
+
+/// Admission policy of [DatabaseHelper]
+#[derive(Clone)]
+struct Policy(Arc<PolicyFn>);
+
+impl fmt::Debug for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Policy").finish()
+    }
+}
+
+/// Database Indexes
+///
+/// All the indexes are behind a single [`RwLock`], taken once per call and released before returning.
+/// The lock is fair, so a waiting writer isn't starved by new readers.
+/// With the `parking_lot` feature the lock is taken without awaiting (the critical sections never `.await`):
+/// cheaper under heavy read concurrency, but a long write blocks the thread of the waiting tasks.
+/// Every call sees a consistent state of all the indexes: an event is never visible in some of them only.
+///
+/// The indexes are copy-on-write: a [snapshot](DatabaseHelper::snapshot) holds the lock only to get a reference to them,
+/// and the first write after it copies them, so a long query on a snapshot doesn't block the writers.
+#[derive(Debug, Clone, Default)]
+pub struct DatabaseHelper {
+    inner: Arc<RwLock<Arc<InternalDatabaseHelper>>>,
+    /// Number of indexed events, updated when releasing the write lock
+    event_count: Arc<AtomicUsize>,
+    observer: Option<Arc<dyn DatabaseHelperObserver>>,
+    policy: Option<Policy>,
+    verify: bool,
+}
+
+/// Write guard updating the event count of the [DatabaseHelper] when dropped
+struct WriteGuard<'a> {
+    guard: RwLockWriteGuard<'a, Arc<InternalDatabaseHelper>>,
+    event_count: &'a AtomicUsize,
+}
+
+impl Deref for WriteGuard<'_> {
+    type Target = InternalDatabaseHelper;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl DerefMut for WriteGuard<'_> {
+    /// Clone the state if a [`DatabaseHelperSnapshot`] still references it
+    ///
+    /// The indexes are persistent collections, so the clone is cheap (`O(1)` per index):
+    /// the following writes copy only the nodes they modify.
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        Arc::make_mut(&mut self.guard)
+    }
+}
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        self.event_count
+            .store(self.guard.events.len(), atomic::Ordering::Relaxed);
+    }
+}
+
+impl DatabaseHelper {
+    /// Unbounded database helper
+    #[inline]
+    pub fn unbounded() -> Self {
+        Self::default()
+    }
+
+    /// Bounded database helper
+    #[inline]
+    pub fn bounded(max: usize) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Arc::new(InternalDatabaseHelper::bounded(max)))),
+            event_count: Arc::default(),
+            observer: None,
+            policy: None,
+            verify: false,
+        }
+    }
+
+    /// Verify the events before indexing them (disabled by default)
+    ///
+    /// Events with invalid ID or signature are rejected with [`EventStatus::Invalid`].
+    /// The verification is done without holding the lock.
+    /// Keep it disabled if the events are already verified, to avoid doing it twice.
+    #[inline]
+    pub fn verifying(mut self) -> Self {
+        self.verify = true;
+        self
+    }
+
+    #[inline]
+    async fn read(&self) -> RwLockReadGuard<'_, Arc<InternalDatabaseHelper>> {
+        #[cfg(not(feature = "parking_lot"))]
+        {
+            self.inner.read().await
+        }
+
+        #[cfg(feature = "parking_lot")]
+        {
+            self.inner.read()
+        }
+    }
+
+    #[inline]
+    async fn write(&self) -> WriteGuard<'_> {
+        #[cfg(not(feature = "parking_lot"))]
+        let guard = self.inner.write().await;
+
+        #[cfg(feature = "parking_lot")]
+        let guard = self.inner.write();
+
+        WriteGuard {
+            guard,
+            event_count: &self.event_count,
+        }
+    }
+
+    #[inline]
+    fn is_invalid(&self, event: &Event) -> bool {
+        self.verify && event.verify().is_err()
+    }
+
+    /// Check the signature (if [verifying](Self::verifying)) and the admission policy, before taking the lock
+    fn admission_result(&self, event: &Event) -> Option<DatabaseEventResult> {
+        if let Some(Policy(policy)) = &self.policy {
+            if let PolicyDecision::Reject(reason) = policy(event) {
+                return Some(DatabaseEventResult {
+                    status: EventStatus::Blocked,
+                    reason: Some(reason),
+                    ..Default::default()
+                });
+            }
+        }
+
+        if self.is_invalid(event) {
+            return Some(DatabaseEventResult {
+                status: EventStatus::Invalid,
+                ..Default::default()
+            });
+        }
+
+        None
+    }
+
+    /// Install an observer
+    #[inline]
+    pub fn with_observer<T>(mut self, observer: T) -> Self
+    where
+        T: DatabaseHelperObserver + 'static,
+    {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Install an admission policy (i.e. PoW difficulty, allowed kinds or banned authors)
+    ///
+    /// The policy is consulted before anything else when indexing or [evaluating](Self::evaluate) an event, without holding the lock.
+    /// Rejected events aren't indexed: the result has [`EventStatus::Blocked`] and the reason of the rejection.
+    /// The events loaded with [`DatabaseHelper::bulk_load`] and [`DatabaseHelper::bulk_import`] are trusted and not checked.
+    #[inline]
+    pub fn with_policy<F>(mut self, policy: F) -> Self
+    where
+        F: Fn(&Event) -> PolicyDecision + Send + Sync + 'static,
+    {
+        self.policy = Some(Policy(Arc::new(policy)));
+        self
+    }
+
+    /// Restore a helper from a [`DatabaseHelper::serialize`] snapshot
+    ///
+    /// Much faster than indexing again all the events.
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        let inner: InternalDatabaseHelper = InternalDatabaseHelper::deserialize(bytes)?;
+        Ok(Self {
+            event_count: Arc::new(AtomicUsize::new(inner.events.len())),
+            inner: Arc::new(RwLock::new(Arc::new(inner))),
+            observer: None,
+            policy: None,
+            verify: false,
+        })
+    }
+
+    /// Serialize the whole state of the helper
+    ///
+    /// The snapshot is versioned and can be restored with [`DatabaseHelper::deserialize`].
+    /// Observer, policy and verification options aren't included.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn serialize(&self) -> Vec<u8> {
+        let inner = self.read().await;
+        inner.serialize()
+    }
+
+    /// Bulk index
+    #[tracing::instrument(skip_all)]
+    pub async fn bulk_load(&self, events: BTreeSet<Event>) -> HashSet<EventId> {
+        let mut inner = self.write().await;
+        inner.bulk_load(events)
+    }
+
+    /// Bulk import
+    ///
+    /// Take a set of [Event], index them and return **only** the ones that must be stored into the database
+    #[tracing::instrument(skip_all)]
+    pub async fn bulk_import(&self, events: BTreeSet<Event>) -> BTreeSet<Event> {
+        let mut inner = self.write().await;
+        inner.bulk_import(events).collect()
+    }
+
+    /// Index [`Event`]
+    ///
+    /// **This method assume that [`Event`] was already verified**, unless the helper is [verifying](Self::verifying)
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn index_event(&self, event: &Event) -> DatabaseEventResult {
+        self.internal_index_event(event, None).await.result
+    }
+
+    /// Index [`Event`] and get the kind, author and timestamp of the discarded events
+    ///
+    /// Useful to update the indexes of a persistent storage without fetching the discarded events.
+    ///
+    /// **This method assume that [`Event`] was already verified**, unless the helper is [verifying](Self::verifying)
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn index_event_detailed(&self, event: &Event) -> DetailedEventResult {
+        self.internal_index_event(event, None).await
+    }
+
+    /// Index [`Event`] and record the relay where it was seen
+    ///
+    /// The source is recorded also if the event was already indexed.
+    /// Use [`DatabaseHelper::query_from_sources`] to query only the events seen on some relays.
+    ///
+    /// **This method assume that [`Event`] was already verified**, unless the helper is [verifying](Self::verifying)
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn index_event_with_source(&self, event: &Event, source: Url) -> DatabaseEventResult {
+        self.internal_index_event(event, Some(source)).await.result
+    }
+
+    async fn internal_index_event(
+        &self,
+        event: &Event,
+        source: Option<Url>,
+    ) -> DetailedEventResult {
+        // Verify before reporting an ephemeral event, since it'll be delivered
+        let res: DetailedEventResult = if let Some(res) = self.admission_result(event) {
+            res.into()
+        } else if let Some(res) = unindexed_result(event) {
+            res.into()
+        } else {
+            // Acquire write lock
+            let mut inner = self.write().await;
+            match source {
+                Some(source) => inner.index_event_with_source(event, source),
+                None => inner.index_event_detailed(event),
+            }
+        };
+
+        if let Some(observer) = &self.observer {
+            observer.on_index(&res.result);
+        }
+
+        res
+    }
+
+    /// Get what [`DatabaseHelper::index_event`] would return, without indexing the event
+    ///
+    /// Useful to keep the helper and a persistent storage in sync: write the event to the storage first,
+    /// then index it only if the write succeeded.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn evaluate(&self, event: &Event) -> DatabaseEventResult {
+        if let Some(res) = self.admission_result(event) {
+            return res;
+        }
+
+        let inner = self.read().await;
+        inner.evaluate(event)
+    }
+
+    /// Bulk index
+    ///
+    /// Index the events in the given order, acquiring the lock only once,
+    /// and return the [DatabaseEventResult] of every event.
+    ///
+    /// **This method assume that [`Event`] was already verified**, unless the helper is [verifying](Self::verifying)
+    #[tracing::instrument(skip_all)]
+    pub async fn bulk_index<I>(&self, events: I) -> Vec<DatabaseEventResult>
+    where
+        I: IntoIterator<Item = Event>,
+    {
+        let results: Vec<DatabaseEventResult> = if self.verify || self.policy.is_some() {
+            // Verify and check the policy before acquiring the lock
+            let events: Vec<(Event, Option<DatabaseEventResult>)> = events
+                .into_iter()
+                .map(|event| {
+                    let res: Option<DatabaseEventResult> = self.admission_result(&event);
+                    (event, res)
+                })
+                .collect();
+
+            let mut inner = self.write().await;
+            events
+                .into_iter()
+                .map(|(event, res)| match res {
+                    Some(res) => res,
+                    None => inner.index_event(&event),
+                })
+                .collect()
+        } else {
+            let mut inner = self.write().await;
+            inner.bulk_index(events)
+        };
+
+        if let Some(observer) = &self.observer {
+            for res in results.iter() {
+                observer.on_index(res);
+            }
+        }
+
+        results
+    }
+
+    /// Merge the events of `other` into this helper (i.e. to query the shards indexed by different tasks)
+    ///
+    /// The events of `other` are indexed again, oldest first, so the replaceable events and the deletions are resolved
+    /// across both helpers: only the current versions survive. Their sources are kept.
+    /// The tombstones of `other` (deleted IDs and pending deletions) are kept too, within [`DatabaseHelper::set_max_deleted_ids`].
+    /// The events get a new received order (see [`OrderBy::ReceivedAt`]), after the ones of this helper.
+    /// Return the [DatabaseEventResult] of every event of `other`, to update a persistent storage.
+    #[tracing::instrument(skip_all)]
+    pub async fn merge(&self, other: &DatabaseHelper) -> Vec<DatabaseEventResult> {
+        // Release the lock of `other` before writing, also if it's the same helper
+        let (events, deleted_ids, pending_deletions) = {
+            let other = other.read().await;
+            let events: Vec<(Event, Option<HashSet<Url>>)> = other
+                .events
+                .iter()
+                .rev()
+                .map(|ev| (ev.deref().clone(), other.source_index.get(&ev.id).cloned()))
+                .collect();
+            (
+                events,
+                other.deleted_ids_order.clone(),
+                other.pending_deletions_order.clone(),
+            )
+        };
+
+        let results: Vec<DatabaseEventResult> = {
+            let mut inner = self.write().await;
+            inner.merge(events, deleted_ids, pending_deletions)
+        };
+
+        if let Some(observer) = &self.observer {
+            for res in results.iter() {
+                observer.on_index(res);
+            }
+        }
+
+        results
+    }
+
+    /// Get [Event] by ID
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn event_by_id(&self, id: &EventId) -> Option<Event> {
+        let inner = self.read().await;
+        inner.event_by_id(id).cloned()
+    }
+
+    /// Check if event exists
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn has_event(&self, id: &EventId) -> bool {
+        let inner = self.read().await;
+        inner.has_event(id)
+    }
+
+    /// Check if there is at least an event of `kind`
+    ///
+    /// Read directly from the kinds count, without running a query.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn has_kind(&self, kind: Kind) -> bool {
+        let inner = self.read().await;
+        inner.has_kind(&kind)
+    }
+
+    /// Get the kinds of the indexed events (sorted)
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn distinct_kinds(&self) -> Vec<Kind> {
+        let inner = self.read().await;
+        inner.distinct_kinds()
+    }
+
+    /// Count events by kind
+    ///
+    /// Read directly from the indexes, without running a query.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn count_by_kind(&self) -> HashMap<Kind, usize> {
+        let inner = self.read().await;
+        inner.count_by_kind()
+    }
+
+    /// Count events of an author
+    ///
+    /// Read directly from the indexes, without running a query.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn count_by_author(&self, author: &PublicKey) -> usize {
+        let inner = self.read().await;
+        inner.count_by_author(author)
+    }
+
+    /// Get the ID of the current version of the replaceable event of `author` (i.e. metadata or contact list)
+    ///
+    /// Look up the author and kind index directly, without running a query.
+    /// Return `None` if `kind` isn't replaceable.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn get_replaceable(&self, author: &PublicKey, kind: Kind) -> Option<EventId> {
+        let inner = self.read().await;
+        inner.get_replaceable(author, kind)
+    }
+
+    /// Get the ID of the current version of the parameterized replaceable event at `kind:author:identifier` (NIP-01 coordinate)
+    ///
+    /// Look up the coordinate index directly, without running a query.
+    /// Return `None` if `kind` isn't parameterized replaceable.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn get_addressable(
+        &self,
+        kind: Kind,
+        author: &PublicKey,
+        identifier: &str,
+    ) -> Option<EventId> {
+        let inner = self.read().await;
+        inner.get_addressable(kind, author, identifier)
+    }
+
+    /// Get the IDs of the events of a kind
+    ///
+    /// Sorted from newest to oldest. Replaced events are never included.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn events_of_kind(&self, kind: Kind, limit: Option<usize>) -> Vec<EventId> {
+        let inner = self.read().await;
+        inner.events_of_kind(kind, limit)
+    }
+
+    /// Query the events with a kind in `range`
+    ///
+    /// The other constraints of `extra` (authors, tags, since, until, limit, ...) are applied too.
+    /// Return the event IDs, sorted from newest to oldest.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query_kind_range(
+        &self,
+        range: RangeInclusive<u16>,
+        extra: &Filter,
+    ) -> Vec<EventId> {
+        self.observed(
+            slice::from_ref(extra),
+            |inner| inner.query_kind_range(range, extra),
+            Vec::len,
+        )
+        .await
+    }
+
+    /// Count the events created from `since` to `until` (both included), grouped in `bucket_secs` wide buckets
+    ///
+    /// Return the start of every bucket with its count, oldest first. Empty buckets are included, so the series is contiguous.
+    /// Return an empty series if `bucket_secs` is `0` or `since` is after `until`,
+    /// and [`Error::TooManyBuckets`] if the series would have more than [`MAX_HISTOGRAM_BUCKETS`] buckets.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn histogram(
+        &self,
+        since: Timestamp,
+        until: Timestamp,
+        bucket_secs: u64,
+    ) -> Result<Vec<(Timestamp, usize)>, Error> {
+        let inner = self.read().await;
+        inner.histogram(since, until, bucket_secs)
+    }
+
+    /// Count the events referencing each value of a lowercase single-letter tag (i.e. the most mentioned public keys with `p`)
+    ///
+    /// Return the `top_n` values, sorted by count (descending) and then by value.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn tag_value_counts(&self, tag: Alphabet, top_n: usize) -> Vec<(String, usize)> {
+        let inner = self.read().await;
+        inner.tag_value_counts(tag, top_n)
+    }
+
+    /// Get the IDs of the events tagged with `hashtag` (NIP-12 topic feed)
+    ///
+    /// Look up the `t` tag index directly. The value must match exactly (no case folding).
+    /// Each event is returned once, also if tagged multiple times, sorted from newest to oldest.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn events_with_hashtag(&self, hashtag: &str, limit: Option<usize>) -> Vec<EventId> {
+        let inner = self.read().await;
+        inner.events_with_hashtag(hashtag, limit)
+    }
+
+    /// Get the IDs of the events referencing the coordinate of an addressable event (i.e. the comments of a long-form article)
+    ///
+    /// Look up the `a` tag index with the canonical `<kind>:<pubkey>:<identifier>` coordinate.
+    /// With an empty `identifier`, also the coordinates without the trailing `:` are matched.
+    /// Sorted from newest to oldest.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn references_coordinate(
+        &self,
+        kind: Kind,
+        author: &PublicKey,
+        identifier: &str,
+        limit: Option<usize>,
+    ) -> Vec<EventId> {
+        let inner = self.read().await;
+        inner.references_coordinate(kind, author, identifier, limit)
+    }
+
+    /// Get the IDs of the other events with exactly the same content of `event` (i.e. to detect spam waves)
+    ///
+    /// Sorted from newest to oldest, `event` excluded.
+    /// Always empty if the content hash index isn't [enabled](DatabaseHelper::set_content_hash_index).
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn events_with_same_content(&self, event: &Event) -> Vec<EventId> {
+        let inner = self.read().await;
+        inner.events_with_same_content(event)
+    }
+
+    /// Get the replies to an event
+    ///
+    /// Return the IDs of the events referencing `event_id` with an `e` tag (the event itself excluded),
+    /// sorted from newest to oldest.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn replies_to(&self, event_id: &EventId, limit: Option<usize>) -> Vec<EventId> {
+        let inner = self.read().await;
+        inner.replies_to(event_id, limit)
+    }
+
+    /// Get the timestamp of the newest event of an author
+    ///
+    /// Useful to reconcile with relays (i.e. `since` of the next filter), without running a query.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn latest_created_at(&self, author: &PublicKey) -> Option<Timestamp> {
+        let inner = self.read().await;
+        inner.latest_created_at(author)
+    }
+
+    /// Get the timestamp of the newest event
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn newest_timestamp(&self) -> Option<Timestamp> {
+        let inner = self.read().await;
+        inner.newest_timestamp()
+    }
+
+    /// Get the newest `n` events (i.e. a global feed)
+    ///
+    /// The events are already kept sorted from newest to oldest, and the replaced, deleted and evicted ones are removed,
+    /// so only the returned events are visited and no filter is matched.
+    /// Up to the capacity set with [`DatabaseHelper::set_recent_capacity`], they are taken from the newest events kept apart.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query_recent_global(&self, n: usize) -> Vec<Event> {
+        let inner = self.read().await;
+        inner.query_recent_global(n)
+    }
+
+    /// Get IDs of all the indexed events
+    ///
+    /// Sorted from newest to oldest (same order of [`Order::Desc`] queries).
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn all_event_ids(&self) -> Vec<EventId> {
+        let inner = self.read().await;
+        inner.all_event_ids()
+    }
+
+    /// Run `query` holding the read lock, then report it to the observer (see [`DatabaseHelperObserver::on_query`])
+    async fn observed<T, Q, M>(&self, filters: &[Filter], query: Q, matched: M) -> T
+    where
+        Q: FnOnce(&InternalDatabaseHelper) -> T,
+        M: FnOnce(&T) -> usize,
+    {
+        let start: Option<Instant> = self.observer.as_ref().map(|_| Instant::now());
+
+        let res: T = {
+            let inner = self.read().await;
+            query(&inner)
+        };
+
+        if let (Some(observer), Some(start)) = (&self.observer, start) {
+            observer.on_query(filters, matched(&res), start.elapsed());
+        }
+
+        res
+    }
+
+    /// Query
+    ///
+    /// [`Filter::ids`] and [`Filter::authors`] are always matched exactly, through the indexes:
+    /// [Filter] holds full [`EventId`]s and [`PublicKey`]s, so hex prefixes (deprecated by NIP-01) can't be expressed.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query<I>(&self, filters: I, order: Order) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let filters: Vec<Filter> = filters.into_iter().collect();
+        self.observed(
+            &filters,
+            |inner| inner.query(filters.iter().cloned(), order),
+            Vec::len,
+        )
+        .await
+    }
+
+    /// Query, failing if a [Filter] has a tag that isn't [indexed](DatabaseHelper::set_indexed_tags)
+    ///
+    /// Same of [`DatabaseHelper::query`] otherwise. Return [`Error::TagNotIndexed`] instead of silently matching nothing.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn try_query<I>(&self, filters: I, order: Order) -> Result<Vec<Event>, Error>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let filters: Vec<Filter> = filters.into_iter().collect();
+        self.observed(
+            &filters,
+            |inner| inner.try_query(filters.clone(), order),
+            |res| res.as_ref().map_or(0, Vec::len),
+        )
+        .await
+    }
+
+    /// Query lazily
+    ///
+    /// The read lock is held only to take a snapshot (see [`DatabaseHelper::snapshot`]): the matching events are
+    /// resolved from it and cloned only while iterating (see [QueryIter]),
+    /// useful to avoid allocating all the [Event]s of big result sets up front.
+    /// Changes made later to the helper aren't reflected.
+    ///
+    /// To get a [`Stream`](https://docs.rs/futures/latest/futures/stream/trait.Stream.html), wrap it with `futures::stream::iter`.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query_iter<I>(&self, filters: I, order: Order) -> QueryIter
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let filters: Vec<Filter> = filters.into_iter().collect();
+        let report: Option<QueryReport> = self.observer.as_ref().map(|observer| QueryReport {
+            observer: Arc::clone(observer),
+            filters: filters.clone(),
+            matched: 0,
+            elapsed: Duration::ZERO,
+        });
+        let snapshot: Arc<InternalDatabaseHelper> = Arc::clone(&*self.read().await);
+        let mut iter: QueryIter = QueryIter::new(snapshot, filters, order);
+        iter.report = report;
+        iter
+    }
+
+    /// Query excluding some authors and kinds
+    ///
+    /// The exclusions are applied before the `limit` of every filter, also when the filters match all the events.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query_excluding<I>(
+        &self,
+        filters: I,
+        order: Order,
+        exclude_authors: &HashSet<PublicKey>,
+        exclude_kinds: &HashSet<Kind>,
+    ) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let filters: Vec<Filter> = filters.into_iter().collect();
+        self.observed(
+            &filters,
+            |inner| {
+                inner.query_excluding(
+                    filters.iter().cloned(),
+                    order,
+                    exclude_authors,
+                    exclude_kinds,
+                )
+            },
+            Vec::len,
+        )
+        .await
+    }
+
+    /// Get which of the `candidate_ids` match the filter
+    ///
+    /// All the constraints of the filter are applied (also `limit`); if it has `ids` too, only the IDs in both sets are considered.
+    /// Return the IDs sorted from newest to oldest.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query_within_ids(
+        &self,
+        candidate_ids: &HashSet<EventId>,
+        filter: &Filter,
+    ) -> Vec<EventId> {
+        self.observed(
+            slice::from_ref(filter),
+            |inner| inner.query_within_ids(candidate_ids, filter),
+            Vec::len,
+        )
+        .await
+    }
+
+    /// Get the IDs of the events indexed since the last sync, in indexing order
+    ///
+    /// Unlike a `since` filter, the position is the indexing order (see [`OrderBy::ReceivedAt`]), not `created_at`:
+    /// also the old events delivered late are returned. Start from `0`,
+    /// then pass the returned position to get only the events indexed after the previous call.
+    /// The replaced, deleted and evicted events are skipped.
+    /// The positions start again from `0` after [`DatabaseHelper::clear`], but not after [`DatabaseHelper::reset_soft`].
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn events_received_since(&self, since: u64, limit: usize) -> (Vec<EventId>, u64) {
+        let inner = self.read().await;
+        inner.events_received_since(since, limit)
+    }
+
+    /// Query events sorted by `order_by`
+    ///
+    /// With [`OrderBy::ReceivedAt`] the events are sorted by indexing order instead of `created_at`,
+    /// so a spoofed timestamp can't move an event to the top of a feed ([`Order::Desc`] returns the last indexed first).
+    /// The `limit` of every filter keeps the last indexed events too, so a spoofed timestamp can't take its slots.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query_ordered_by<I>(
+        &self,
+        filters: I,
+        order: Order,
+        order_by: OrderBy,
+    ) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let filters: Vec<Filter> = filters.into_iter().collect();
+        self.observed(
+            &filters,
+            |inner| inner.query_ordered_by(filters.iter().cloned(), order, order_by),
+            Vec::len,
+        )
+        .await
+    }
+
+    /// Query events, boosting the ones of the `preferred` authors
+    ///
+    /// The `preferred` authors change only the order of the results, not which events match:
+    /// the events are sorted by `created_at` (according to `order`) first,
+    /// then the ones of the preferred authors come before the others with the same timestamp.
+    /// Ties between events with the same timestamp and preference are in the same order of [`DatabaseHelper::query`].
+    /// The `limit` of the filters still selects the newest events, regardless of the preference.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query_with_preferred_authors<I>(
+        &self,
+        filters: I,
+        order: Order,
+        preferred: &HashSet<PublicKey>,
+    ) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let filters: Vec<Filter> = filters.into_iter().collect();
+        self.observed(
+            &filters,
+            |inner| inner.query_with_preferred_authors(filters.iter().cloned(), order, preferred),
+            Vec::len,
+        )
+        .await
+    }
+
+    /// Query the IDs of the newest events matching `filter`, with at most `per_author_cap` events of every author
+    ///
+    /// Useful for the feeds that shouldn't be dominated by a prolific author (i.e. newest 50 events, at most 3 per author).
+    /// The events are walked from newest to oldest, skipping the ones of the authors that already reached the cap,
+    /// until `overall_limit` (or the `limit` of the filter, if lower) is reached.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query_diversified(
+        &self,
+        filter: &Filter,
+        overall_limit: usize,
+        per_author_cap: usize,
+    ) -> Vec<EventId> {
+        self.observed(
+            slice::from_ref(filter),
+            |inner| inner.query_diversified(filter, overall_limit, per_author_cap),
+            Vec::len,
+        )
+        .await
+    }
+
+    /// Query the IDs of the events matching `filter`, giving up when `deadline` is reached
+    ///
+    /// Protects from the queries visiting a huge number of events, matching them (i.e. an empty [Filter]
+    /// without `limit`) or not (i.e. a kind that no event has, checked against all the events):
+    /// the deadline is checked while visiting the candidates and the lock is released as soon as it's reached.
+    /// Partial results are never returned: [`Error::QueryTimeout`] is returned instead.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query_with_deadline(
+        &self,
+        filter: &Filter,
+        deadline: Instant,
+    ) -> Result<Vec<EventId>, Error> {
+        self.observed(
+            slice::from_ref(filter),
+            |inner| inner.query_with_deadline(filter, deadline),
+            |res| res.as_ref().map_or(0, Vec::len),
+        )
+        .await
+    }
+
+    /// Query the IDs of the events matching `filter` that have no `missing` tag
+    ///
+    /// Useful to audit the data (i.e. text notes without a `client` tag). Results are sorted from newest to oldest
+    /// and the `limit` of the [Filter] is applied after the exclusion.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query_missing_tag(&self, filter: &Filter, missing: Alphabet) -> Vec<EventId> {
+        self.observed(
+            slice::from_ref(filter),
+            |inner| inner.query_missing_tag(filter, missing),
+            Vec::len,
+        )
+        .await
+    }
+
+    /// Query the events seen on at least one of the `sources`
+    ///
+    /// Events without a recorded source (indexed with [`DatabaseHelper::index_event`]) are never returned.
+    /// The restriction is applied before the `limit` of every filter.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query_from_sources<I>(
+        &self,
+        filters: I,
+        order: Order,
+        sources: &HashSet<Url>,
+    ) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let filters: Vec<Filter> = filters.into_iter().collect();
+        self.observed(
+            &filters,
+            |inner| inner.query_from_sources(filters.iter().cloned(), order, sources),
+            Vec::len,
+        )
+        .await
+    }
+
+    /// Query the events created in the `within` before `now`
+    ///
+    /// The `since` of the [Filter] is replaced with `now - within`, while `until` is kept.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query_recent(
+        &self,
+        filter: Filter,
+        within: Duration,
+        now: Timestamp,
+        order: Order,
+    ) -> Vec<Event> {
+        let filters: [Filter; 1] = [filter];
+        self.observed(
+            &filters,
+            |inner| inner.query_recent(filters[0].clone(), within, &now, order),
+            Vec::len,
+        )
+        .await
+    }
+
+    /// Query a page of events
+    ///
+    /// Events are sorted from newest to oldest (see [`Order::Desc`]). Pass the returned [Cursor] to get the next page:
+    /// `None` means that there are no more events.
+    /// Every page starts with a range lookup at the cursor, so paging through all the events visits each of them once.
+    /// The `limit` of the [Filter] caps the size of every page.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query_page(
+        &self,
+        filter: Filter,
+        cursor: Option<Cursor>,
+        page_size: usize,
+    ) -> (Vec<Event>, Option<Cursor>) {
+        let filters: [Filter; 1] = [filter];
+        self.observed(
+            &filters,
+            |inner| inner.query_page(filters[0].clone(), cursor, page_size),
+            |(events, _)| events.len(),
+        )
+        .await
+    }
+
+    /// Query events from oldest to newest (i.e. chat history)
+    ///
+    /// Unlike [`DatabaseHelper::query`] with [`Order::Asc`], that reverses the newest events,
+    /// the `limit` of every [Filter] keeps the **oldest** matching events.
+    /// Events with the same timestamp are in the same order of [`Order::Asc`].
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query_oldest<I>(&self, filters: I) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let filters: Vec<Filter> = filters.into_iter().collect();
+        self.observed(
+            &filters,
+            |inner| inner.query_oldest(filters.iter().cloned()),
+            Vec::len,
+        )
+        .await
+    }
+
+    /// Query with an overall limit
+    ///
+    /// Every [Filter] keeps honoring its own `limit`, while `limit` caps the union of the results,
+    /// keeping the newest events.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn query_with_limit<I>(&self, filters: I, order: Order, limit: usize) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let filters: Vec<Filter> = filters.into_iter().collect();
+        self.observed(
+            &filters,
+            |inner| inner.query_with_limit(filters.iter().cloned(), order, limit),
+            Vec::len,
+        )
+        .await
+    }
+
+    /// Count events
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn count<I>(&self, filters: I) -> usize
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        let filters: Vec<Filter> = filters.into_iter().collect();
+        self.observed(
+            &filters,
+            |inner| inner.count(filters.iter().cloned()),
+            |count| *count,
+        )
+        .await
+    }
+
+    /// Explain how a [Filter] is queried
+    ///
+    /// Read-only introspection aid, useful to debug surprising results: report the index used to look up
+    /// the candidates, how many events match every constraint of the filter alone,
+    /// and how many match the whole filter, before and after the `limit`.
+    pub async fn explain(&self, filter: &Filter) -> QueryExplain {
+        let inner = self.read().await;
+        inner.explain(filter)
+    }
+
+    /// Get negentropy items
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn negentropy_items(&self, filter: Filter) -> Vec<(EventId, Timestamp)> {
+        let filters: [Filter; 1] = [filter];
+        self.observed(
+            &filters,
+            |inner| inner.negentropy_items(filters[0].clone()),
+            Vec::len,
+        )
+        .await
+    }
+
+    /// Check if an event with [`EventId`] has been deleted
+    pub async fn has_event_id_been_deleted(&self, event_id: &EventId) -> bool {
+        let inner = self.read().await;
+        inner.has_event_id_been_deleted(event_id)
+    }
+
+    /// Check if event with [`Coordinate`] has been deleted before [`Timestamp`]
+    pub async fn has_coordinate_been_deleted(
+        &self,
+        coordinate: &Coordinate,
+        timestamp: Timestamp,
+    ) -> bool {
+        let inner = self.read().await;
+        inner.has_coordinate_been_deleted(coordinate, &timestamp)
+    }
+
+    /// Remove all the events of an author
+    ///
+    /// The events are removed from all the indexes, while the ones of the other authors are left intact.
+    /// Like [`DatabaseHelper::remove_event`], the IDs are not marked as deleted.
+    ///
+    /// Return the number of removed events.
+    pub async fn clear_author(&self, author: &PublicKey) -> usize {
+        let mut inner = self.write().await;
+        inner.clear_author(author)
+    }
+
+    /// Remove [Event] by ID
+    ///
+    /// Unlike NIP-09 deletions, the ID is not marked as deleted, so the event can be indexed again.
+    ///
+    /// Return `true` if the event was indexed.
+    pub async fn remove_event(&self, id: &EventId) -> bool {
+        let mut inner = self.write().await;
+        inner.remove_event(id)
+    }
+
+    /// Check that all the indexes are consistent with the stored events
+    ///
+    /// Walk all the indexes, so it's meant for tests and debugging.
+    pub async fn check_integrity(&self) -> Result<(), Error> {
+        let inner = self.read().await;
+        inner.check_integrity()
+    }
+
+    /// Get all the inconsistencies between the indexes and the stored events
+    ///
+    /// Like [`DatabaseHelper::check_integrity`], but without stopping at the first one.
+    /// Every event is reported once, also if referenced by more indexes.
+    pub async fn verify_integrity(&self) -> Vec<Error> {
+        let inner = self.read().await;
+        inner.verify_integrity()
+    }
+
+    /// Fix the issues reported by [`DatabaseHelper::verify_integrity`]
+    ///
+    /// Index entries of events not stored are removed, while stored events missing from the indexes are indexed again.
+    /// Return the number of fixed issues.
+    pub async fn repair(&self) -> usize {
+        let mut inner = self.write().await;
+        inner.repair()
+    }
+
+    /// Re-index [Event]
+    ///
+    /// Remove the current indexes of the event (if any) and index it again, without releasing the lock in between.
+    /// If the event isn't indexed, it's the same of [`DatabaseHelper::index_event`].
+    pub async fn reindex_event(&self, event: &Event) -> DatabaseEventResult {
+        let mut inner = self.write().await;
+        inner.reindex_event(event)
+    }
+
+    /// Remove the events that expired since they were indexed (NIP-40)
+    ///
+    /// Return the IDs of the removed events, that must be deleted also from DB.
+    pub async fn prune_expired(&self) -> HashSet<EventId> {
+        let mut inner = self.write().await;
+        inner.prune_expired(&Timestamp::now())
+    }
+
+    /// Get the expiration of the first indexed event to expire (NIP-40)
+    ///
+    /// Useful to schedule [`DatabaseHelper::prune_expired_before`] exactly when needed, instead of polling.
+    pub async fn next_expiration(&self) -> Option<Timestamp> {
+        let inner = self.read().await;
+        inner.next_expiration()
+    }
+
+    /// Remove the events expired before `now` (NIP-40)
+    ///
+    /// Only the expired events are visited, through the expiration index.
+    /// Return the IDs of the removed events sorted by expiration, that must be deleted also from DB.
+    pub async fn prune_expired_before(&self, now: Timestamp) -> Vec<EventId> {
+        let mut inner = self.write().await;
+        inner.prune_expired_before(&now)
+    }
+
+    /// Enable or disable the normalization of the tags of the filters (disabled by default)
+    ///
+    /// When enabled, the hex values of the `e` (event IDs) and `p` (public keys) tags are lowercased,
+    /// both in the filters and in the tags index, so they match also if uppercase in the filters or in the events.
+    /// Other tags are never changed, since their case can be meaningful.
+    ///
+    /// Changing it builds the tags index again.
+    pub async fn set_normalize_tags(&self, enable: bool) {
+        let mut inner = self.write().await;
+        inner.set_normalize_tags(enable);
+    }
+
+    /// Enable or disable the content hash index (disabled by default)
+    ///
+    /// Required by [`DatabaseHelper::events_with_same_content`]. When enabled, the already indexed events are added to it.
+    /// Costs an entry per event: keep it disabled if not needed.
+    pub async fn set_content_hash_index(&self, enable: bool) {
+        let mut inner = self.write().await;
+        inner.set_content_hash_index(enable);
+    }
+
+    /// Reject the events created more than `skew` in the future (disabled by default)
+    ///
+    /// Such events aren't indexed and get [`EventStatus::FutureTimestamp`]. `None` accepts any timestamp.
+    pub async fn set_max_future_skew(&self, skew: Option<Duration>) {
+        let mut inner = self.write().await;
+        inner.max_future_skew = skew;
+    }
+
+    /// Reject the events created before `min_created_at` (disabled by default)
+    ///
+    /// Unlike the expiration, it's a static cutoff: useful to not store again very old events replayed by clients.
+    /// Such events aren't indexed and get [`EventStatus::Rejected`]. `None` removes the floor.
+    pub async fn set_min_created_at(&self, min_created_at: Option<Timestamp>) {
+        let mut inner = self.write().await;
+        inner.min_created_at = min_created_at;
+    }
+
+    /// Index only the tags with these letters, both lowercase and uppercase (all single-letter tags by default)
+    ///
+    /// Saves memory when only some tags are queried (i.e. `e`, `p`, `a`, `d` and `t`).
+    /// The tags index is built again from the indexed events. `None` indexes all of them.
+    /// The filters by the other tags match nothing: use [`DatabaseHelper::try_query`] to detect them.
+    pub async fn set_indexed_tags(&self, letters: Option<HashSet<Alphabet>>) {
+        let mut inner = self.write().await;
+        inner.set_indexed_tags(letters);
+    }
+
+    /// Keep at most `max` tombstones of the deleted events (unbounded by default)
+    ///
+    /// The IDs of the deleted events are kept to reject them if delivered again.
+    /// Over the limit the oldest tombstones are dropped, and their events can be indexed again.
+    /// The same limit applies to the deletion requests of the events not received yet, dropping the oldest ones.
+    /// `None` keeps all of them.
+    pub async fn set_max_deleted_ids(&self, max: Option<usize>) {
+        let mut inner = self.write().await;
+        inner.set_max_deleted_ids(max);
+    }
+
+    /// Keep the newest `capacity` events apart, for [`DatabaseHelper::query_recent_global`] (disabled by default)
+    ///
+    /// The kept events are updated while indexing, and refilled when some of them are replaced, deleted or evicted.
+    /// `None` disables them.
+    pub async fn set_recent_capacity(&self, capacity: Option<usize>) {
+        let mut inner = self.write().await;
+        inner.set_recent_capacity(capacity);
+    }
+
+    /// Get the approximate number of indexed events, without taking the lock
+    ///
+    /// Updated when a write is completed: it's exact when no event is being indexed or removed,
+    /// but may lag behind under concurrent mutation.
+    #[inline]
+    pub fn approx_event_count(&self) -> u64 {
+        self.event_count.load(atomic::Ordering::Relaxed) as u64
+    }
+
+    /// Get helper statistics
+    pub async fn stats(&self) -> DatabaseHelperStats {
+        let inner = self.read().await;
+        inner.stats()
+    }
+
+    /// Delete all events that match [Filter]
+    ///
+    /// If return `None`, means that all events must be deleted from DB
+    pub async fn delete(&self, filter: Filter) -> Option<HashSet<EventId>> {
+        let mut inner = self.write().await;
+        inner.delete(filter)
+    }
+
+    /// Clear helper
+    pub async fn clear(&self) {
+        let mut inner = self.write().await;
+        inner.clear();
+    }
+
+    /// Clear helper, without resetting the received order
+    ///
+    /// Same of [`DatabaseHelper::clear`], but the events indexed after it keep the received order going on
+    /// from the one before clearing, so it's never reused (append-only semantic within a session, see [`OrderBy::ReceivedAt`]).
+    pub async fn reset_soft(&self) {
+        let mut inner = self.write().await;
+        inner.reset_soft();
+    }
+
+    /// Get a read-only snapshot of the current state
+    ///
+    /// The lock is released as soon as the snapshot is taken: the writers aren't blocked while the snapshot is queried,
+    /// and the snapshot doesn't see their changes.
+    /// The indexes are structurally shared with the snapshot: the writes copy only the nodes they modify,
+    /// so taking a snapshot is cheap also while indexing.
+    pub async fn snapshot(&self) -> DatabaseHelperSnapshot {
+        let inner = self.read().await;
+        DatabaseHelperSnapshot {
+            inner: Arc::clone(&inner),
+        }
+    }
+
+    /// Convert into a [LocalDatabaseHelper], to use it from a single thread without an async runtime
+    ///
+    /// Return the helper back if it's shared (cloned). The observer and the policy aren't kept.
+    pub fn into_local(self) -> Result<LocalDatabaseHelper, Self> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(lock) => Ok(LocalDatabaseHelper {
+                // Copy the indexes only if a snapshot still references them
+                inner: RefCell::new(
+                    Arc::try_unwrap(lock.into_inner()).unwrap_or_else(|inner| (*inner).clone()),
+                ),
+                verify: self.verify,
+            }),
+            Err(inner) => Err(Self {
+                inner,
+                event_count: self.event_count,
+                observer: self.observer,
+                policy: self.policy,
+                verify: self.verify,
+            }),
+        }
+    }
+}
+
+/// Read-only snapshot of a [DatabaseHelper]
+///
+/// Get it with [`DatabaseHelper::snapshot`]. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct DatabaseHelperSnapshot {
+    inner: Arc<InternalDatabaseHelper>,
+}
+
+impl DatabaseHelperSnapshot {
+    /// Query
+    pub fn query<I>(&self, filters: I, order: Order) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        self.inner.query(filters, order)
+    }
+
+    /// Query lazily (see [QueryIter])
+    pub fn query_iter<I>(&self, filters: I, order: Order) -> QueryIter
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        QueryIter::new(
+            Arc::clone(&self.inner),
+            filters.into_iter().collect(),
+            order,
+        )
+    }
+
+    /// Count events
+    pub fn count<I>(&self, filters: I) -> usize
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        self.inner.count(filters)
+    }
+
+    /// Get [Event] by [EventId]
+    pub fn event_by_id(&self, id: &EventId) -> Option<Event> {
+        self.inner.event_by_id(id).cloned()
+    }
+
+    /// Check if event exists
+    pub fn has_event(&self, id: &EventId) -> bool {
+        self.inner.has_event(id)
+    }
+
+    /// Get helper statistics
+    pub fn stats(&self) -> DatabaseHelperStats {
+        self.inner.stats()
+    }
+}
+
+/// Single-threaded database helper
+///
+/// Same indexes of [DatabaseHelper], behind a [RefCell] instead of a lock: the methods are synchronous,
+/// so no async runtime is needed (i.e. CLI tools or single-threaded WASM). It's not [Send].
+/// Get it with [`DatabaseHelper::into_local`].
+#[derive(Debug, Default)]
+pub struct LocalDatabaseHelper {
+    inner: RefCell<InternalDatabaseHelper>,
+    verify: bool,
+}
+
+impl LocalDatabaseHelper {
+    /// Bulk index
+    pub fn bulk_load(&self, events: BTreeSet<Event>) -> HashSet<EventId> {
+        self.inner.borrow_mut().bulk_load(events)
+    }
+
+    /// Index [`Event`]
+    ///
+    /// **This method assume that [`Event`] was already verified**, unless the helper was [verifying](DatabaseHelper::verifying)
+    pub fn index_event(&self, event: &Event) -> DatabaseEventResult {
+        if event.is_expired() {
+            return DatabaseEventResult::default();
+        }
+
+        if self.verify && event.verify().is_err() {
+            return DatabaseEventResult {
+                status: EventStatus::Invalid,
+                ..Default::default()
+            };
+        }
+
+        self.inner.borrow_mut().index_event(event)
+    }
+
+    /// Query
+    pub fn query<I>(&self, filters: I, order: Order) -> Vec<Event>
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        self.inner.borrow().query(filters, order)
+    }
+
+    /// Count events
+    pub fn count<I>(&self, filters: I) -> usize
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        self.inner.borrow().count(filters)
+    }
+
+    /// Get [Event] by [EventId]
+    pub fn event_by_id(&self, id: &EventId) -> Option<Event> {
+        self.inner.borrow().event_by_id(id).cloned()
+    }
+
+    /// Check if event exists
+    pub fn has_event(&self, id: &EventId) -> bool {
+        self.inner.borrow().has_event(id)
+    }
+
+    /// Check if an event with [`EventId`] has been deleted
+    pub fn has_event_id_been_deleted(&self, event_id: &EventId) -> bool {
+        self.inner.borrow().has_event_id_been_deleted(event_id)
+    }
+
+    /// Remove an event from all the indexes
+    pub fn remove_event(&self, id: &EventId) -> bool {
+        self.inner.borrow_mut().remove_event(id)
+    }
+
+    /// Get helper statistics
+    pub fn stats(&self) -> DatabaseHelperStats {
+        self.inner.borrow().stats()
+    }
+
+    /// Clear helper
+    pub fn clear(&self) {
+        self.inner.borrow_mut().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr::{EventBuilder, FromBech32, JsonUtil, Keys, Metadata, SecretKey, Tag};
 
     use super::*;
 
-    const SECRET_KEY_A: &str = "nsec1j4c6269y9w0q2er2xjw8sv2ehyrtfxq3jwgdlxj6qfn8z4gjsq5qfvfk99"; // aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4
-    const SECRET_KEY_B: &str = "nsec1ufnus6pju578ste3v90xd5m2decpuzpql2295m3sknqcjzyys9ls0qlc85"; // 79dff8f82963424e0bb02708a22e44b4980893e3a4be0fa3cb60a43b946764e3
+    const SECRET_KEY_A: &str = "nsec1j4c6269y9w0q2er2xjw8sv2ehyrtfxq3jwgdlxj6qfn8z4gjsq5qfvfk99"; // aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4
+    const SECRET_KEY_B: &str = "nsec1ufnus6pju578ste3v90xd5m2decpuzpql2295m3sknqcjzyys9ls0qlc85"; // 79dff8f82963424e0bb02708a22e44b4980893e3a4be0fa3cb60a43b946764e3
+
+    const EVENTS: [&str; 14] = [
+        r#"{"id":"b7b1fb52ad8461a03e949820ae29a9ea07e35bcd79c95c4b59b0254944f62805","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704644581,"kind":1,"tags":[],"content":"Text note","sig":"ed73a8a4e7c26cd797a7b875c634d9ecb6958c57733305fed23b978109d0411d21b3e182cb67c8ad750884e30ca383b509382ae6187b36e76ee76e6a142c4284"}"#,
+        r#"{"id":"7296747d91c53f1d71778ef3e12d18b66d494a41f688ef244d518abf37c959b6","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704644586,"kind":32121,"tags":[["d","id-1"]],"content":"Empty 1","sig":"8848989a8e808f7315e950f871b231c1dff7752048f8957d4a541881d2005506c30e85c7dd74dab022b3e01329c88e69c9d5d55d961759272a738d150b7dbefc"}"#,
+        r#"{"id":"ec6ea04ba483871062d79f78927df7979f67545b53f552e47626cb1105590442","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704644591,"kind":32122,"tags":[["d","id-1"]],"content":"Empty 2","sig":"89946113a97484850fe35fefdb9120df847b305de1216dae566616fe453565e8707a4da7e68843b560fa22a932f81fc8db2b5a2acb4dcfd3caba9a91320aac92"}"#,
+        r#"{"id":"63b8b829aa31a2de870c3a713541658fcc0187be93af2032ec2ca039befd3f70","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704644596,"kind":32122,"tags":[["d","id-2"]],"content":"","sig":"607b1a67bef57e48d17df4e145718d10b9df51831d1272c149f2ab5ad4993ae723f10a81be2403ae21b2793c8ed4c129e8b031e8b240c6c90c9e6d32f62d26ff"}"#,
+        r#"{"id":"6fe9119c7db13ae13e8ecfcdd2e5bf98e2940ba56a2ce0c3e8fba3d88cd8e69d","pubkey":"79dff8f82963424e0bb02708a22e44b4980893e3a4be0fa3cb60a43b946764e3","created_at":1704644601,"kind":32122,"tags":[["d","id-3"]],"content":"","sig":"d07146547a726fc9b4ec8d67bbbe690347d43dadfe5d9890a428626d38c617c52e6945f2b7144c4e0c51d1e2b0be020614a5cadc9c0256b2e28069b70d9fc26e"}"#,
+        r#"{"id":"a82f6ebfc709f4e7c7971e6bf738e30a3bc112cfdb21336054711e6779fd49ef","pubkey":"79dff8f82963424e0bb02708a22e44b4980893e3a4be0fa3cb60a43b946764e3","created_at":1704644606,"kind":32122,"tags":[["d","id-1"]],"content":"","sig":"96d3349b42ed637712b4d07f037457ab6e9180d58857df77eb5fa27ff1fd68445c72122ec53870831ada8a4d9a0b484435f80d3ff21a862238da7a723a0d073c"}"#,
+        r#"{"id":"8ab0cb1beceeb68f080ec11a3920b8cc491ecc7ec5250405e88691d733185832","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704644611,"kind":32122,"tags":[["d","id-1"]],"content":"Test","sig":"49153b482d7110e2538eb48005f1149622247479b1c0057d902df931d5cea105869deeae908e4e3b903e3140632dc780b3f10344805eab77bb54fb79c4e4359d"}"#,
+        r#"{"id":"63dc49a8f3278a2de8dc0138939de56d392b8eb7a18c627e4d78789e2b0b09f2","pubkey":"79dff8f82963424e0bb02708a22e44b4980893e3a4be0fa3cb60a43b946764e3","created_at":1704644616,"kind":5,"tags":[["a","32122:aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4:"]],"content":"","sig":"977e54e5d57d1fbb83615d3a870037d9eb5182a679ca8357523bbf032580689cf481f76c88c7027034cfaf567ba9d9fe25fc8cd334139a0117ad5cf9fe325eef"}"#,
+        r#"{"id":"6975ace0f3d66967f330d4758fbbf45517d41130e2639b54ca5142f37757c9eb","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704644621,"kind":5,"tags":[["a","32122:aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4:id-2"]],"content":"","sig":"9bb09e4759899d86e447c3fa1be83905fe2eda74a5068a909965ac14fcdabaed64edaeb732154dab734ca41f2fc4d63687870e6f8e56e3d9e180e4a2dd6fb2d2"}"#,
+        r#"{"id":"33f5b4e6a38e107638c20f4536db35191d4b8651ba5a2cefec983b9ec2d65084","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704645586,"kind":0,"tags":[],"content":"{\"name\":\"Key A\"}","sig":"285d090f45a6adcae717b33771149f7840a8c27fb29025d63f1ab8d95614034a54e9f4f29cee9527c4c93321a7ebff287387b7a19ba8e6f764512a40e7120429"}"#,
+        r#"{"id":"90a761aec9b5b60b399a76826141f529db17466deac85696a17e4a243aa271f9","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704645606,"kind":0,"tags":[],"content":"{\"name\":\"key-a\",\"display_name\":\"Key A\",\"lud16\":\"keya@ln.address\"}","sig":"ec8f49d4c722b7ccae102d49befff08e62db775e5da43ef51b25c47dfdd6a09dc7519310a3a63cbdb6ec6b3250e6f19518eb47be604edeb598d16cdc071d3dbc"}"#,
+        r#"{"id":"a295422c636d3532875b75739e8dae3cdb4dd2679c6e4994c9a39c7ebf8bc620","pubkey":"79dff8f82963424e0bb02708a22e44b4980893e3a4be0fa3cb60a43b946764e3","created_at":1704646569,"kind":5,"tags":[["e","90a761aec9b5b60b399a76826141f529db17466deac85696a17e4a243aa271f9"]],"content":"","sig":"d4dc8368a4ad27eef63cacf667345aadd9617001537497108234fc1686d546c949cbb58e007a4d4b632c65ea135af4fbd7a089cc60ab89b6901f5c3fc6a47b29"}"#,
+        r#"{"id":"999e3e270100d7e1eaa98fcfab4a98274872c1f2dfdab024f32e42a5a12d5b5e","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704646606,"kind":5,"tags":[["e","90a761aec9b5b60b399a76826141f529db17466deac85696a17e4a243aa271f9"]],"content":"","sig":"4f3a33fd52784cea7ca8428fd35d94d65049712e9aa11a70b1a16a1fcd761c7b7e27afac325728b1c00dfa11e33e78b2efd0430a7e4b28f4ede5b579b3f32614"}"#,
+        r#"{"id":"99a022e6d61c4e39c147d08a2be943b664e8030c0049325555ac1766429c2832","pubkey":"79dff8f82963424e0bb02708a22e44b4980893e3a4be0fa3cb60a43b946764e3","created_at":1705241093,"kind":30333,"tags":[["d","multi-id"],["p","aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4"]],"content":"Multi-tags","sig":"0abfb2b696a7ed7c9e8e3bf7743686190f3f1b3d4045b72833ab6187c254f7ed278d289d52dfac3de28be861c1471421d9b1bfb5877413cbc81c84f63207a826"}"#,
+    ];
+
+    const REPLACEABLE_EVENT_1: &str = r#"{"id":"f06d755821e56fe9e25373d6bd142979ebdca0063bb0f10a95a95baf41bb5419","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1707478309,"kind":0,"tags":[],"content":"{\"name\":\"Test 1\"}","sig":"a095d9cf4f26794e6421445c0d1c4ada8273ad79a9809aaa20c566fc8d679b57f09889121050853c47be9222106abad0215705a80723f002fd47616ff6ba7bb9"}"#;
+    const REPLACEABLE_EVENT_2: &str = r#"{"id":"e0899bedc802a836c331282eddf712600fea8e00123b541e25a81aa6a4669b4a","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1707478348,"kind":0,"tags":[],"content":"{\"name\":\"Test 2\"}","sig":"ca1192ac72530010a895b4d76943bf373696a6969911c486c835995122cd59a46988026e8c0ad8322bc3f5942ecd633fc903e93c0460c9a186243ab1f1597a9c"}"#;
+
+    #[tokio::test]
+    async fn test_database_indexes() {
+        // Keys
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+        let keys_b = Keys::new(SecretKey::from_bech32(SECRET_KEY_B).unwrap());
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let mut events: BTreeSet<Event> = BTreeSet::new();
+        for event in EVENTS.into_iter() {
+            let event = Event::from_json(event).unwrap();
+            events.insert(event);
+        }
+        indexes.bulk_load(events).await;
+
+        // Test expected output
+        let expected_output = vec![
+            Event::from_json(EVENTS[13]).unwrap(),
+            Event::from_json(EVENTS[12]).unwrap(),
+            Event::from_json(EVENTS[11]).unwrap(),
+            // Event 10 deleted by event 12
+            // Event 9 replaced by event 10
+            Event::from_json(EVENTS[8]).unwrap(),
+            Event::from_json(EVENTS[7]).unwrap(),
+            Event::from_json(EVENTS[6]).unwrap(),
+            Event::from_json(EVENTS[5]).unwrap(),
+            Event::from_json(EVENTS[4]).unwrap(),
+            // Event 3 deleted by Event 8
+            // Event 2 replaced by Event 6
+            Event::from_json(EVENTS[1]).unwrap(),
+            Event::from_json(EVENTS[0]).unwrap(),
+        ];
+        assert_eq!(
+            indexes.query([Filter::new()], Order::Desc).await,
+            expected_output
+        );
+        assert_eq!(indexes.count([Filter::new()]).await, 10);
+
+        // Test get previously deleted replaceable event (check if was deleted by indexes)
+        assert!(indexes
+            .query(
+                [Filter::new()
+                    .kind(Kind::Metadata)
+                    .author(keys_a.public_key())],
+                Order::Desc
+            )
+            .await
+            .is_empty());
+
+        // Test get previously deleted param. replaceable event (check if was deleted by indexes)
+        assert!(indexes
+            .query(
+                [Filter::new()
+                    .kind(Kind::ParameterizedReplaceable(32122))
+                    .author(keys_a.public_key())
+                    .identifier("id-2")],
+                Order::Desc
+            )
+            .await
+            .is_empty());
+
+        // Test get param replaceable events WITHOUT using indexes (identifier not passed)
+        // Test ascending order
+        assert_eq!(
+            indexes
+                .query(
+                    [Filter::new()
+                        .kind(Kind::ParameterizedReplaceable(32122))
+                        .author(keys_b.public_key())],
+                    Order::Asc
+                )
+                .await,
+            vec![
+                Event::from_json(EVENTS[4]).unwrap(),
+                Event::from_json(EVENTS[5]).unwrap(),
+            ]
+        );
+
+        // Test get param replaceable events using indexes
+        assert_eq!(
+            indexes
+                .query(
+                    [Filter::new()
+                        .kind(Kind::ParameterizedReplaceable(32122))
+                        .author(keys_b.public_key())
+                        .identifier("id-3")],
+                    Order::Desc
+                )
+                .await,
+            vec![Event::from_json(EVENTS[4]).unwrap()]
+        );
+
+        assert_eq!(
+            indexes
+                .query([Filter::new().author(keys_a.public_key())], Order::Desc)
+                .await,
+            vec![
+                Event::from_json(EVENTS[12]).unwrap(),
+                Event::from_json(EVENTS[8]).unwrap(),
+                Event::from_json(EVENTS[6]).unwrap(),
+                Event::from_json(EVENTS[1]).unwrap(),
+                Event::from_json(EVENTS[0]).unwrap(),
+            ]
+        );
+
+        assert_eq!(
+            indexes
+                .query(
+                    [Filter::new()
+                        .author(keys_a.public_key())
+                        .kinds([Kind::TextNote, Kind::Custom(32121)])],
+                    Order::Desc
+                )
+                .await,
+            vec![
+                Event::from_json(EVENTS[1]).unwrap(),
+                Event::from_json(EVENTS[0]).unwrap(),
+            ]
+        );
+
+        assert_eq!(
+            indexes
+                .query(
+                    [Filter::new()
+                        .authors([keys_a.public_key(), keys_b.public_key()])
+                        .kinds([Kind::TextNote, Kind::Custom(32121)])],
+                    Order::Desc
+                )
+                .await,
+            vec![
+                Event::from_json(EVENTS[1]).unwrap(),
+                Event::from_json(EVENTS[0]).unwrap(),
+            ]
+        );
+
+        // Test get param replaceable events using identifier
+        assert_eq!(
+            indexes
+                .query([Filter::new().identifier("id-1")], Order::Desc)
+                .await,
+            vec![
+                Event::from_json(EVENTS[6]).unwrap(),
+                Event::from_json(EVENTS[5]).unwrap(),
+                Event::from_json(EVENTS[1]).unwrap(),
+            ]
+        );
+
+        // Test get param replaceable events with multiple tags using identifier
+        assert_eq!(
+            indexes
+                .query([Filter::new().identifier("multi-id")], Order::Desc)
+                .await,
+            vec![Event::from_json(EVENTS[13]).unwrap()]
+        );
+        // As above but by using kind and pubkey
+        assert_eq!(
+            indexes
+                .query(
+                    [Filter::new()
+                        .pubkey(keys_a.public_key())
+                        .kind(Kind::Custom(30333))
+                        .limit(1)],
+                    Order::Desc
+                )
+                .await,
+            vec![Event::from_json(EVENTS[13]).unwrap()]
+        );
+
+        // Test add new replaceable event (metadata)
+        let first_ev_metadata = Event::from_json(REPLACEABLE_EVENT_1).unwrap();
+        let res = indexes.index_event(&first_ev_metadata).await;
+        assert!(res.to_store);
+        assert!(res.to_discard.is_empty());
+        assert_eq!(
+            indexes
+                .query(
+                    [Filter::new()
+                        .kind(Kind::Metadata)
+                        .author(keys_a.public_key())],
+                    Order::Desc
+                )
+                .await,
+            vec![first_ev_metadata.clone()]
+        );
+
+        // Test add replace metadata
+        let ev = Event::from_json(REPLACEABLE_EVENT_2).unwrap();
+        let res = indexes.index_event(&ev).await;
+        assert!(res.to_store);
+        assert!(res.to_discard.contains(&first_ev_metadata.id));
+        assert_eq!(
+            indexes
+                .query(
+                    [Filter::new()
+                        .kind(Kind::Metadata)
+                        .author(keys_a.public_key())],
+                    Order::Desc
+                )
+                .await,
+            vec![ev]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replaceable_event_same_timestamp() {
+        let keys = Keys::generate();
+        let created_at = Timestamp::from(1707478309);
+
+        let ev1 = EventBuilder::metadata(&Metadata::new().name("Test 1"))
+            .custom_created_at(created_at)
+            .to_event(&keys)
+            .unwrap();
+        let ev2 = EventBuilder::metadata(&Metadata::new().name("Test 2"))
+            .custom_created_at(created_at)
+            .to_event(&keys)
+            .unwrap();
+        let (lowest, highest) = if ev1.id < ev2.id {
+            (ev1, ev2)
+        } else {
+            (ev2, ev1)
+        };
+        let filter = Filter::new().kind(Kind::Metadata).author(keys.public_key());
+
+        // The event with the lowest ID replaces the other one
+        let indexes = DatabaseHelper::unbounded();
+        assert!(indexes.index_event(&highest).await.to_store);
+        let res = indexes.index_event(&lowest).await;
+        assert!(res.to_store);
+        assert!(res.to_discard.contains(&highest.id));
+        assert_eq!(
+            indexes.query([filter.clone()], Order::Desc).await,
+            vec![lowest.clone()]
+        );
+
+        // The event with the highest ID is rejected
+        let indexes = DatabaseHelper::unbounded();
+        assert!(indexes.index_event(&lowest).await.to_store);
+        let res = indexes.index_event(&highest).await;
+        assert!(!res.to_store);
+        assert!(res.to_discard.is_empty());
+        assert_eq!(indexes.query([filter], Order::Desc).await, vec![lowest]);
+    }
+
+    #[tokio::test]
+    async fn test_param_replaceable_event_without_identifier() {
+        let keys = Keys::generate();
+        let kind = Kind::ParameterizedReplaceable(32122);
+
+        let ev1 = EventBuilder::new(kind, "Old", [])
+            .custom_created_at(Timestamp::from(1707478309))
+            .to_event(&keys)
+            .unwrap();
+        let ev2 = EventBuilder::new(kind, "New", [Tag::identifier("")])
+            .custom_created_at(Timestamp::from(1707478348))
+            .to_event(&keys)
+            .unwrap();
+
+        let indexes = DatabaseHelper::unbounded();
+        assert!(indexes.index_event(&ev1).await.to_store);
+
+        // Missing `d` tag and empty `d` tag share the same coordinate
+        let res = indexes.index_event(&ev2).await;
+        assert!(res.to_store);
+        assert!(res.to_discard.contains(&ev1.id));
+        assert_eq!(
+            indexes
+                .query(
+                    [Filter::new()
+                        .kind(kind)
+                        .author(keys.public_key())
+                        .identifier("")],
+                    Order::Desc
+                )
+                .await,
+            vec![ev2.clone()]
+        );
+
+        // Older revision is rejected
+        assert!(!indexes.index_event(&ev1).await.to_store);
+        assert_eq!(indexes.count([Filter::new()]).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_clear() {
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+        assert!(indexes.count([Filter::new()]).await > 0);
+
+        indexes.clear().await;
+
+        let filters = [
+            Filter::new(),
+            Filter::new().author(keys_a.public_key()),
+            Filter::new()
+                .kind(Kind::ParameterizedReplaceable(32122))
+                .author(keys_a.public_key()),
+            Filter::new()
+                .kind(Kind::ParameterizedReplaceable(32122))
+                .author(keys_a.public_key())
+                .identifier("id-1"),
+            Filter::new()
+                .since(Timestamp::from(1704644581))
+                .until(Timestamp::from(1705241093)),
+            Filter::new().pubkey(keys_a.public_key()),
+            Filter::new().kind(Kind::TextNote).limit(10),
+        ];
+        for filter in filters.into_iter() {
+            assert!(indexes
+                .query([filter.clone()], Order::Desc)
+                .await
+                .is_empty());
+            assert_eq!(indexes.count([filter]).await, 0);
+        }
+
+        // Deleted IDs are cleared too
+        let deleted =
+            EventId::from_hex("90a761aec9b5b60b399a76826141f529db17466deac85696a17e4a243aa271f9")
+                .unwrap();
+        assert!(!indexes.has_event_id_been_deleted(&deleted).await);
+    }
+
+    #[tokio::test]
+    async fn test_deletion_before_target_event() {
+        let keys_a = Keys::generate();
+        let keys_b = Keys::generate();
+
+        let note = EventBuilder::text_note("Text note", [])
+            .to_event(&keys_a)
+            .unwrap();
+        let deletion_a = EventBuilder::delete([note.id]).to_event(&keys_a).unwrap();
+        let deletion_b = EventBuilder::delete([note.id]).to_event(&keys_b).unwrap();
+
+        // Deletion from another author is ignored
+        let indexes = DatabaseHelper::unbounded();
+        assert!(indexes.index_event(&deletion_b).await.to_store);
+        assert!(indexes.index_event(&note).await.to_store);
+
+        // Deletion from the author is remembered
+        let indexes = DatabaseHelper::unbounded();
+        assert!(indexes.index_event(&deletion_a).await.to_store);
+        let res = indexes.index_event(&note).await;
+        assert!(!res.to_store);
+        assert!(indexes.event_by_id(&note.id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_count() {
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+        let keys_b = Keys::new(SecretKey::from_bech32(SECRET_KEY_B).unwrap());
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let filters = [
+            vec![Filter::new()],
+            vec![Filter::new().limit(3)],
+            vec![Filter::new().author(keys_a.public_key())],
+            vec![Filter::new().author(keys_a.public_key()).limit(2)],
+            vec![Filter::new().kind(Kind::EventDeletion)],
+            vec![
+                Filter::new().author(keys_a.public_key()),
+                Filter::new().kind(Kind::EventDeletion),
+            ],
+            vec![
+                Filter::new().author(keys_a.public_key()),
+                Filter::new().author(keys_b.public_key()),
+            ],
+            vec![],
+        ];
+        for filters in filters.into_iter() {
+            assert_eq!(
+                indexes.count(filters.clone()).await,
+                indexes.query(filters, Order::Desc).await.len()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_multiple_filters() {
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+        let keys_b = Keys::new(SecretKey::from_bech32(SECRET_KEY_B).unwrap());
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        // No filters, no events
+        assert!(indexes
+            .query(Vec::<Filter>::new(), Order::Desc)
+            .await
+            .is_empty());
+
+        // Union of overlapping filters is de-duplicated and sorted
+        let filters = vec![
+            Filter::new().author(keys_a.public_key()),
+            Filter::new().kind(Kind::EventDeletion),
+            Filter::new().author(keys_b.public_key()).limit(1),
+        ];
+        let expected = vec![
+            Event::from_json(EVENTS[13]).unwrap(),
+            Event::from_json(EVENTS[12]).unwrap(),
+            Event::from_json(EVENTS[11]).unwrap(),
+            Event::from_json(EVENTS[8]).unwrap(),
+            Event::from_json(EVENTS[7]).unwrap(),
+            Event::from_json(EVENTS[6]).unwrap(),
+            Event::from_json(EVENTS[1]).unwrap(),
+            Event::from_json(EVENTS[0]).unwrap(),
+        ];
+        assert_eq!(indexes.query(filters.clone(), Order::Desc).await, expected);
+
+        // Overall limit keeps the newest events
+        assert_eq!(
+            indexes
+                .query_with_limit(filters.clone(), Order::Desc, 3)
+                .await,
+            expected[..3].to_vec()
+        );
+        let mut expected_asc = expected[..3].to_vec();
+        expected_asc.reverse();
+        assert_eq!(
+            indexes.query_with_limit(filters, Order::Asc, 3).await,
+            expected_asc
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_by_ids() {
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+        let keys_b = Keys::new(SecretKey::from_bech32(SECRET_KEY_B).unwrap());
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let ev0 = Event::from_json(EVENTS[0]).unwrap();
+        let ev1 = Event::from_json(EVENTS[1]).unwrap();
+        let replaced = Event::from_json(EVENTS[9]).unwrap();
+
+        // Not indexed IDs are skipped
+        assert_eq!(
+            indexes
+                .query(
+                    [Filter::new().ids([ev0.id, ev1.id, replaced.id])],
+                    Order::Desc
+                )
+                .await,
+            vec![ev1.clone(), ev0.clone()]
+        );
+
+        // IDs combined with a non-matching author
+        assert!(indexes
+            .query(
+                [Filter::new()
+                    .ids([ev0.id, ev1.id])
+                    .author(keys_b.public_key())],
+                Order::Desc
+            )
+            .await
+            .is_empty());
+
+        // IDs combined with matching author and kind
+        assert_eq!(
+            indexes
+                .query(
+                    [Filter::new()
+                        .ids([ev0.id, ev1.id])
+                        .author(keys_a.public_key())
+                        .kind(Kind::TextNote)],
+                    Order::Desc
+                )
+                .await,
+            vec![ev0]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_by_ids_with_limit() {
+        let indexes = DatabaseHelper::unbounded();
+
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let event = |i: usize| Event::from_json(EVENTS[i]).unwrap();
+
+        // 3 and 9 deleted, the other ones requested in random order
+        let ids = [5, 3, 0, 13, 9, 6, 12].map(|i| event(i).id);
+
+        // Newest first, whatever the order of the IDs
+        assert_eq!(
+            indexes
+                .query([Filter::new().ids(ids).limit(3)], Order::Desc)
+                .await,
+            vec![event(13), event(12), event(6)]
+        );
+
+        // Limit greater than the indexed events
+        assert_eq!(
+            indexes
+                .query([Filter::new().ids(ids).limit(10)], Order::Desc)
+                .await,
+            vec![event(13), event(12), event(6), event(5), event(0)]
+        );
+
+        // The limit keeps the newest events, also in ascending order
+        assert_eq!(
+            indexes
+                .query([Filter::new().ids(ids).limit(2)], Order::Asc)
+                .await,
+            vec![event(12), event(13)]
+        );
+        assert_eq!(indexes.count([Filter::new().ids(ids).limit(4)]).await, 4);
+    }
+
+    #[tokio::test]
+    async fn test_has_event() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // Replaceable events
+        let ev1 = EventBuilder::metadata(&Metadata::new().name("Test 1"))
+            .custom_created_at(Timestamp::from(1707478309))
+            .to_event(&keys)
+            .unwrap();
+        let ev2 = EventBuilder::metadata(&Metadata::new().name("Test 2"))
+            .custom_created_at(Timestamp::from(1707478348))
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&ev1).await;
+        assert!(indexes.has_event(&ev1.id).await);
+        indexes.index_event(&ev2).await;
+        assert!(!indexes.has_event(&ev1.id).await);
+        assert!(indexes.has_event(&ev2.id).await);
+
+        // Deletion
+        let note = EventBuilder::text_note("Text note", [])
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&note).await;
+        assert!(indexes.has_event(&note.id).await);
+        let deletion = EventBuilder::delete([note.id]).to_event(&keys).unwrap();
+        indexes.index_event(&deletion).await;
+        assert!(!indexes.has_event(&note.id).await);
+        assert!(indexes.has_event(&deletion.id).await);
+
+        // Capacity
+        let indexes = DatabaseHelper::bounded(1);
+        let old = EventBuilder::text_note("Old", [])
+            .custom_created_at(Timestamp::from(1707478309))
+            .to_event(&keys)
+            .unwrap();
+        let new = EventBuilder::text_note("New", [])
+            .custom_created_at(Timestamp::from(1707478348))
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&old).await;
+        indexes.index_event(&new).await;
+        assert!(!indexes.has_event(&old.id).await);
+        assert!(indexes.has_event(&new.id).await);
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let indexes = DatabaseHelper::unbounded();
+        assert_eq!(indexes.stats().await, DatabaseHelperStats::default());
+
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let stats = indexes.stats().await;
+        assert_eq!(stats.events, 10);
+        assert_eq!(stats.authors, 2);
+        assert_eq!(stats.kinds, 5);
+        assert_eq!(stats.param_replaceable, 5);
+        assert_eq!(stats.tag_values, 7);
+        assert_eq!(stats.index_entries, 10 + 10 + 10 + 5 + 10 + 7);
+        assert_eq!(stats.deleted_ids, 4);
+        assert_eq!(stats.deleted_coordinates, 1);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_index() {
+        let keys = Keys::generate();
+
+        let ev1 = EventBuilder::metadata(&Metadata::new().name("Test 1"))
+            .custom_created_at(Timestamp::from(1707478309))
+            .to_event(&keys)
+            .unwrap();
+        let ev2 = EventBuilder::metadata(&Metadata::new().name("Test 2"))
+            .custom_created_at(Timestamp::from(1707478348))
+            .to_event(&keys)
+            .unwrap();
+        let note = EventBuilder::text_note("Text note", [])
+            .to_event(&keys)
+            .unwrap();
+
+        let indexes = DatabaseHelper::unbounded();
+        let results = indexes
+            .bulk_index([ev1.clone(), ev2.clone(), note.clone(), ev1.clone()])
+            .await;
+        assert_eq!(results.len(), 4);
+
+        assert!(results[0].to_store);
+        assert!(results[0].to_discard.is_empty());
+
+        // Replaced by the event in the same batch
+        assert!(results[1].to_store);
+        assert!(results[1].to_discard.contains(&ev1.id));
+
+        assert!(results[2].to_store);
+
+        // Already replaced
+        assert!(!results[3].to_store);
+
+        assert_eq!(
+            indexes.query([Filter::new()], Order::Desc).await,
+            vec![note, ev2]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prune_expired() {
+        let keys = Keys::generate();
+        let now = Timestamp::now();
+
+        let expiring = EventBuilder::text_note("Expiring", [Tag::expiration(now + 2)])
+            .to_event(&keys)
+            .unwrap();
+        let note = EventBuilder::text_note("Text note", [])
+            .to_event(&keys)
+            .unwrap();
+
+        let indexes = DatabaseHelper::unbounded();
+        indexes.index_event(&expiring).await;
+        indexes.index_event(&note).await;
+
+        // Not expired yet
+        assert!(indexes.prune_expired().await.is_empty());
+        assert_eq!(indexes.count([Filter::new()]).await, 2);
+
+        {
+            let mut inner = indexes.write().await;
+            let removed = inner.prune_expired(&(now + 3));
+            assert_eq!(removed.len(), 1);
+            assert!(removed.contains(&expiring.id));
+        }
+
+        assert_eq!(
+            indexes.query([Filter::new()], Order::Desc).await,
+            vec![note]
+        );
+        assert!(!indexes.has_event_id_been_deleted(&expiring.id).await);
+    }
+
+    #[tokio::test]
+    async fn test_remove_event() {
+        let keys = Keys::generate();
+
+        let note = EventBuilder::text_note("Text note", [])
+            .to_event(&keys)
+            .unwrap();
+
+        let indexes = DatabaseHelper::unbounded();
+        assert!(!indexes.remove_event(&note.id).await);
+
+        indexes.index_event(&note).await;
+        assert!(indexes.remove_event(&note.id).await);
+        assert!(!indexes.has_event(&note.id).await);
+        assert!(!indexes.has_event_id_been_deleted(&note.id).await);
+        assert!(indexes
+            .query([Filter::new().author(keys.public_key())], Order::Desc)
+            .await
+            .is_empty());
+        assert_eq!(indexes.count([Filter::new()]).await, 0);
+
+        // Can be indexed again
+        assert!(indexes.index_event(&note).await.to_store);
+    }
+
+    #[tokio::test]
+    async fn test_tag_index_only_single_letter_tags() {
+        let keys = Keys::generate();
+
+        let event = EventBuilder::text_note(
+            "Text note",
+            [
+                Tag::public_key(keys.public_key()),
+                Tag::alt("Alt"),
+                Tag::title("Title"),
+                Tag::description("Description"),
+                Tag::parse(&["client", "nostr-sdk"]).unwrap(),
+            ],
+        )
+        .to_event(&keys)
+        .unwrap();
+
+        let indexes = DatabaseHelper::unbounded();
+        indexes.index_event(&event).await;
+
+        // Only the `p` tag is indexed
+        let stats = indexes.stats().await;
+        assert_eq!(stats.tag_values, 1);
+        assert_eq!(stats.index_entries, 3 + 1);
+
+        indexes.remove_event(&event.id).await;
+        assert_eq!(indexes.stats().await.tag_values, 0);
+    }
+
+    #[tokio::test]
+    async fn test_query_by_tags() {
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+        let keys_b = Keys::new(SecretKey::from_bech32(SECRET_KEY_B).unwrap());
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let note_1 = EventBuilder::text_note(
+            "Note 1",
+            [Tag::public_key(keys_a.public_key()), Tag::hashtag("nostr")],
+        )
+        .custom_created_at(Timestamp::from(1))
+        .to_event(&keys_b)
+        .unwrap();
+        let note_2 = EventBuilder::text_note(
+            "Note 2",
+            [Tag::public_key(keys_a.public_key()), Tag::hashtag("rust")],
+        )
+        .custom_created_at(Timestamp::from(2))
+        .to_event(&keys_b)
+        .unwrap();
+        let note_3 = EventBuilder::text_note("Note 3", [Tag::public_key(keys_b.public_key())])
+            .custom_created_at(Timestamp::from(3))
+            .to_event(&keys_a)
+            .unwrap();
+        indexes
+            .bulk_index([note_1.clone(), note_2.clone(), note_3.clone()])
+            .await;
+
+        // Single value
+        let res = indexes
+            .query(vec![Filter::new().pubkey(keys_a.public_key())], Order::Desc)
+            .await;
+        assert_eq!(res, vec![note_2.clone(), note_1.clone()]);
+
+        // Values of the same tag are OR-ed
+        let res = indexes
+            .query(vec![Filter::new().hashtags(["nostr", "rust"])], Order::Desc)
+            .await;
+        assert_eq!(res, vec![note_2.clone(), note_1.clone()]);
+
+        // Different tags are AND-ed
+        let res = indexes
+            .query(
+                vec![Filter::new().pubkey(keys_a.public_key()).hashtag("rust")],
+                Order::Desc,
+            )
+            .await;
+        assert_eq!(res, vec![note_2.clone()]);
+
+        let res = indexes
+            .query(
+                vec![Filter::new().pubkey(keys_b.public_key()).hashtag("rust")],
+                Order::Desc,
+            )
+            .await;
+        assert!(res.is_empty());
+
+        // Unknown value
+        let res = indexes
+            .query(vec![Filter::new().hashtag("unknown")], Order::Desc)
+            .await;
+        assert!(res.is_empty());
+
+        // Other filter fields are still checked
+        let res = indexes
+            .query(
+                vec![Filter::new()
+                    .pubkey(keys_a.public_key())
+                    .until(Timestamp::from(1))],
+                Order::Desc,
+            )
+            .await;
+        assert_eq!(res, vec![note_1]);
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let note_1 = EventBuilder::text_note("Hello, Nostr!", [])
+            .custom_created_at(Timestamp::from(1))
+            .to_event(&keys)
+            .unwrap();
+        let note_2 = EventBuilder::text_note("Rust nostr SDK", [])
+            .custom_created_at(Timestamp::from(2))
+            .to_event(&keys)
+            .unwrap();
+        let metadata = EventBuilder::metadata(&Metadata::new().name("nostr"))
+            .custom_created_at(Timestamp::from(3))
+            .to_event(&keys)
+            .unwrap();
+        indexes
+            .bulk_index([note_1.clone(), note_2.clone(), metadata.clone()])
+            .await;
+
+        // Case insensitive
+        let res = indexes
+            .query(vec![Filter::new().search("NOSTR")], Order::Desc)
+            .await;
+        assert_eq!(res, vec![metadata.clone(), note_2.clone(), note_1.clone()]);
+
+        // Words are AND-ed, regardless of their order
+        let res = indexes
+            .query(vec![Filter::new().search("sdk nostr")], Order::Desc)
+            .await;
+        assert_eq!(res, vec![note_2.clone()]);
+
+        let res = indexes
+            .query(vec![Filter::new().search("hello sdk")], Order::Desc)
+            .await;
+        assert!(res.is_empty());
+
+        // Partial words don't match
+        let res = indexes
+            .query(vec![Filter::new().search("nost")], Order::Desc)
+            .await;
+        assert!(res.is_empty());
+
+        // Other filter fields are still checked
+        let res = indexes
+            .query(
+                vec![Filter::new().kind(Kind::TextNote).search("nostr")],
+                Order::Desc,
+            )
+            .await;
+        assert_eq!(res, vec![note_2.clone(), note_1.clone()]);
+
+        // Removed events are dropped from the search index
+        indexes.remove_event(&note_2.id).await;
+        let res = indexes
+            .query(vec![Filter::new().search("rust")], Order::Desc)
+            .await;
+        assert!(res.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_query_iter() {
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let filters = vec![
+            Filter::new().kind(Kind::TextNote),
+            Filter::new().kind(Kind::EventDeletion),
+        ];
+
+        for order in [Order::Asc, Order::Desc] {
+            let events: Vec<Event> = indexes.query_iter(filters.clone(), order).await.collect();
+            assert_eq!(events.len(), 5);
+            assert_eq!(events, indexes.query(filters.clone(), order).await);
+
+            let events: Vec<Event> = indexes
+                .query_iter(vec![Filter::new()], order)
+                .await
+                .collect();
+            assert_eq!(events.len(), 10);
+            assert_eq!(events, indexes.query(vec![Filter::new()], order).await);
+        }
+
+        // Changes after the query aren't reflected
+        let iter = indexes.query_iter(vec![Filter::new()], Order::Desc).await;
+        indexes.clear().await;
+        assert_eq!(iter.count(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_query_iter_pages() {
+        let keys_a = Keys::generate();
+        let keys_b = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // More events than a page, with many of them created in the same second
+        let events: BTreeSet<Event> = (0..700)
+            .map(|i| {
+                let keys: &Keys = if i % 3 == 0 { &keys_a } else { &keys_b };
+                EventBuilder::text_note(format!("Note {i}"), [])
+                    .custom_created_at(Timestamp::from(i / 10))
+                    .to_event(keys)
+                    .unwrap()
+            })
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let filters = vec![
+            vec![Filter::new()],
+            vec![Filter::new().author(keys_a.public_key())],
+            vec![
+                Filter::new().author(keys_a.public_key()).limit(150),
+                Filter::new().kind(Kind::TextNote).limit(300),
+            ],
+            vec![
+                Filter::new().authors([keys_a.public_key(), keys_b.public_key()]),
+                Filter::new()
+                    .author(keys_b.public_key())
+                    .until(Timestamp::from(20))
+                    .limit(100),
+            ],
+        ];
+
+        for filters in filters.into_iter() {
+            for order in [Order::Asc, Order::Desc] {
+                let events: Vec<Event> = indexes.query_iter(filters.clone(), order).await.collect();
+                assert_eq!(events, indexes.query(filters.clone(), order).await);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_order_with_and_without_limit() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let notes: Vec<Event> = (0..5)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"), [])
+                    .custom_created_at(Timestamp::from(i))
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+        indexes.bulk_index(notes.clone()).await;
+
+        let newest_first: Vec<Event> = notes.iter().rev().cloned().collect();
+
+        // Without limit
+        let res = indexes
+            .query(vec![Filter::new().author(keys.public_key())], Order::Desc)
+            .await;
+        assert_eq!(res, newest_first);
+
+        // With limit, the newest events are kept and the order is the same
+        let res = indexes
+            .query(
+                vec![Filter::new().author(keys.public_key()).limit(3)],
+                Order::Desc,
+            )
+            .await;
+        assert_eq!(res, newest_first[..3]);
+
+        let res = indexes
+            .query(
+                vec![Filter::new().author(keys.public_key()).limit(3)],
+                Order::Asc,
+            )
+            .await;
+        assert_eq!(res, notes[2..]);
+    }
+
+    #[tokio::test]
+    async fn test_query_order_same_timestamp() {
+        let keys = Keys::generate();
+        let created_at = Timestamp::from(1);
+
+        let mut notes: Vec<Event> = (0..5)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"), [])
+                    .custom_created_at(created_at)
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+
+        let indexes_a = DatabaseHelper::unbounded();
+        indexes_a.bulk_index(notes.clone()).await;
+
+        let indexes_b = DatabaseHelper::unbounded();
+        indexes_b.bulk_index(notes.iter().rev().cloned()).await;
+
+        // Lowest ID first
+        notes.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let filter = Filter::new().author(keys.public_key());
+        assert_eq!(
+            indexes_a.query(vec![filter.clone()], Order::Desc).await,
+            notes
+        );
+        assert_eq!(
+            indexes_b.query(vec![filter.clone()], Order::Desc).await,
+            notes
+        );
+
+        // The limit keeps the lowest IDs too
+        let res = indexes_b.query(vec![filter.limit(2)], Order::Desc).await;
+        assert_eq!(res, notes[..2]);
+    }
+
+    #[tokio::test]
+    async fn test_query_excluding() {
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+        let keys_b = Keys::new(SecretKey::from_bech32(SECRET_KEY_B).unwrap());
+
+        // Match all
+        let exclude_authors = HashSet::from([keys_a.public_key()]);
+        let res = indexes
+            .query_excluding(
+                vec![Filter::new()],
+                Order::Desc,
+                &exclude_authors,
+                &HashSet::new(),
+            )
+            .await;
+        assert!(!res.is_empty());
+        assert!(res.iter().all(|e| e.pubkey == keys_b.public_key()));
+        assert_eq!(
+            res,
+            indexes
+                .query(vec![Filter::new().author(keys_b.public_key())], Order::Desc)
+                .await
+        );
+
+        // Exclude kinds
+        let exclude_kinds = HashSet::from([Kind::EventDeletion]);
+        let res = indexes
+            .query_excluding(
+                vec![Filter::new().author(keys_a.public_key())],
+                Order::Asc,
+                &HashSet::new(),
+                &exclude_kinds,
+            )
+            .await;
+        assert!(!res.is_empty());
+        assert!(res.iter().all(|e| e.kind != Kind::EventDeletion));
+        assert!(res.windows(2).all(|w| w[0].created_at <= w[1].created_at));
+
+        // Exclude everything
+        let exclude_authors = HashSet::from([keys_a.public_key(), keys_b.public_key()]);
+        let res = indexes
+            .query_excluding(
+                vec![Filter::new()],
+                Order::Desc,
+                &exclude_authors,
+                &HashSet::new(),
+            )
+            .await;
+        assert!(res.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_query_excluding_with_limit() {
+        let keys = Keys::generate();
+        let spammer = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+        let mut notes: Vec<Event> = Vec::new();
+        for i in 0..3 {
+            let note = EventBuilder::text_note(format!("Note {i}"), [])
+                .custom_created_at(Timestamp::from(1000 + i))
+                .to_event(&keys)
+                .unwrap();
+            indexes.index_event(&note).await;
+            notes.push(note);
+        }
+
+        // The spammer has the newest events
+        for i in 0..3 {
+            let spam = EventBuilder::text_note(format!("Spam {i}"), [])
+                .custom_created_at(Timestamp::from(2000 + i))
+                .to_event(&spammer)
+                .unwrap();
+            indexes.index_event(&spam).await;
+        }
+
+        let exclude_authors = HashSet::from([spammer.public_key()]);
+        for filter in [
+            Filter::new().limit(2),
+            Filter::new().kind(Kind::TextNote).limit(2),
+        ] {
+            let res = indexes
+                .query_excluding(vec![filter], Order::Desc, &exclude_authors, &HashSet::new())
+                .await;
+            assert_eq!(res, vec![notes[2].clone(), notes[1].clone()]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_constraint_matching_nothing() {
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let unknown_a = Keys::generate().public_key();
+        let unknown_b = Keys::generate().public_key();
+
+        // Authors match nothing, kinds match something
+        let filter = Filter::new().author(unknown_a).kind(Kind::TextNote);
+        assert!(indexes.query(vec![filter], Order::Desc).await.is_empty());
+
+        let filter = Filter::new()
+            .authors([unknown_a, unknown_b])
+            .kinds([Kind::TextNote, Kind::EventDeletion]);
+        assert!(indexes
+            .query(vec![filter.clone()], Order::Desc)
+            .await
+            .is_empty());
+        assert_eq!(indexes.count(vec![filter]).await, 0);
+
+        // Tags match nothing, kinds match something
+        let filter = Filter::new().kind(Kind::TextNote).hashtag("unknown");
+        assert!(indexes.query(vec![filter], Order::Desc).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_all_event_ids() {
+        let indexes = DatabaseHelper::unbounded();
+        assert!(indexes.all_event_ids().await.is_empty());
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let ids: Vec<EventId> = indexes
+            .query(vec![Filter::new()], Order::Desc)
+            .await
+            .into_iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(ids.len(), 10);
+        assert_eq!(indexes.all_event_ids().await, ids);
+
+        // Removed events aren't returned
+        indexes.remove_event(&ids[0]).await;
+        assert_eq!(indexes.all_event_ids().await, ids[1..]);
+    }
+
+    #[tokio::test]
+    async fn test_count_by_kind_and_author() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+        assert!(indexes.count_by_kind().await.is_empty());
+        assert_eq!(indexes.count_by_author(&keys.public_key()).await, 0);
+
+        let note = EventBuilder::text_note("Text note", [])
+            .to_event(&keys)
+            .unwrap();
+        let metadata_1 = EventBuilder::metadata(&Metadata::new().name("account"))
+            .custom_created_at(Timestamp::from(1))
+            .to_event(&keys)
+            .unwrap();
+        indexes.bulk_index([note, metadata_1]).await;
+
+        let counts = indexes.count_by_kind().await;
+        assert_eq!(counts.get(&Kind::TextNote), Some(&1));
+        assert_eq!(counts.get(&Kind::Metadata), Some(&1));
+        assert_eq!(indexes.count_by_author(&keys.public_key()).await, 2);
+
+        // Replaced events are not counted
+        let metadata_2 = EventBuilder::metadata(&Metadata::new().name("account-2"))
+            .custom_created_at(Timestamp::from(2))
+            .to_event(&keys)
+            .unwrap();
+        assert!(indexes.index_event(&metadata_2).await.to_store);
+
+        let counts = indexes.count_by_kind().await;
+        assert_eq!(counts.get(&Kind::Metadata), Some(&1));
+        assert_eq!(indexes.count_by_author(&keys.public_key()).await, 2);
+
+        // Deleted events are not counted
+        let deletion = EventBuilder::delete([metadata_2.id])
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&deletion).await;
+
+        let counts = indexes.count_by_kind().await;
+        assert_eq!(counts.get(&Kind::Metadata), None);
+        assert_eq!(counts.get(&Kind::EventDeletion), Some(&1));
+        assert_eq!(indexes.count_by_author(&keys.public_key()).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_bounded_eviction() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::bounded(2);
+
+        let notes: Vec<Event> = (1..=3)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"), [Tag::hashtag("test")])
+                    .custom_created_at(Timestamp::from(i * 10))
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+
+        assert!(indexes.index_event(&notes[0]).await.to_discard.is_empty());
+        assert!(indexes.index_event(&notes[1]).await.to_discard.is_empty());
+
+        // The oldest event is evicted
+        let res = indexes.index_event(&notes[2]).await;
+        assert!(res.to_store);
+        assert_eq!(res.to_discard, HashSet::from([notes[0].id]));
+        assert!(!indexes.has_event(&notes[0].id).await);
+
+        // An event older than all the others isn't stored
+        let old = EventBuilder::text_note("Old", [Tag::hashtag("test")])
+            .custom_created_at(Timestamp::from(1))
+            .to_event(&keys)
+            .unwrap();
+        let res = indexes.index_event(&old).await;
+        assert!(!res.to_store);
+        assert_eq!(res.to_discard, HashSet::from([old.id]));
+        assert!(!indexes.has_event(&old.id).await);
+
+        // All indexes are consistent
+        let stats = indexes.stats().await;
+        assert_eq!(stats.events, 2);
+        assert_eq!(stats.tag_values, 1);
+        assert_eq!(indexes.count_by_author(&keys.public_key()).await, 2);
+        assert_eq!(
+            indexes
+                .query(vec![Filter::new().hashtag("test")], Order::Desc)
+                .await,
+            vec![notes[2].clone(), notes[1].clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reindex_event() {
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let stats = indexes.stats().await;
+
+        // Already indexed
+        let event = Event::from_json(EVENTS[13]).unwrap();
+        let res = indexes.reindex_event(&event).await;
+        assert!(res.to_store);
+        assert!(res.to_discard.is_empty());
+        assert_eq!(indexes.stats().await, stats);
+
+        // Not indexed
+        let keys = Keys::generate();
+        let note = EventBuilder::text_note("Text note", [Tag::hashtag("test")])
+            .to_event(&keys)
+            .unwrap();
+        let res = indexes.reindex_event(&note).await;
+        assert!(res.to_store);
+        assert!(indexes.has_event(&note.id).await);
+        assert_eq!(
+            indexes
+                .query(vec![Filter::new().hashtag("test")], Order::Desc)
+                .await,
+            vec![note]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_time_window() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let notes: Vec<Event> = (1..=10)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"), [])
+                    .custom_created_at(Timestamp::from(i))
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+        indexes.bulk_index(notes.clone()).await;
+
+        let filter = Filter::new()
+            .since(Timestamp::from(3))
+            .until(Timestamp::from(8));
+
+        // Without limit
+        let res = indexes.query(vec![filter.clone()], Order::Desc).await;
+        let expected: Vec<Event> = notes[2..8].iter().rev().cloned().collect();
+        assert_eq!(res, expected);
+
+        // With limit
+        let res = indexes
+            .query(vec![filter.clone().limit(2)], Order::Desc)
+            .await;
+        assert_eq!(res, expected[..2]);
+
+        // Other constraints are still checked
+        let res = indexes
+            .query(vec![filter.kind(Kind::Metadata).limit(2)], Order::Desc)
+            .await;
+        assert!(res.is_empty());
+
+        // Outside the indexed events
+        let filter = Filter::new().since(Timestamp::from(11));
+        assert!(indexes.query(vec![filter], Order::Desc).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_event_status() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let note = EventBuilder::text_note("Text note", [])
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(indexes.index_event(&note).await.status, EventStatus::Saved);
+
+        // Duplicate
+        let res = indexes.index_event(&note).await;
+        assert_eq!(res.status, EventStatus::Duplicate);
+        assert!(!res.to_store);
+        assert!(res.to_discard.is_empty());
+
+        // Replaced
+        let metadata_1 = EventBuilder::metadata(&Metadata::new().name("account"))
+            .custom_created_at(Timestamp::from(1))
+            .to_event(&keys)
+            .unwrap();
+        let metadata_2 = EventBuilder::metadata(&Metadata::new().name("account-2"))
+            .custom_created_at(Timestamp::from(2))
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(
+            indexes.index_event(&metadata_1).await.status,
+            EventStatus::Saved
+        );
+        assert_eq!(
+            indexes.index_event(&metadata_2).await.status,
+            EventStatus::Replaced
+        );
+
+        // Rejected: older than the current replaceable event
+        let metadata_0 = EventBuilder::metadata(&Metadata::new().name("account-0"))
+            .custom_created_at(Timestamp::from(0))
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(
+            indexes.index_event(&metadata_0).await.status,
+            EventStatus::Rejected
+        );
+
+        // A deletion isn't a replacement
+        let deletion = EventBuilder::delete([note.id]).to_event(&keys).unwrap();
+        let res = indexes.index_event(&deletion).await;
+        assert_eq!(res.status, EventStatus::Saved);
+        assert!(res.to_discard.contains(&note.id));
+
+        // Rejected: deleted
+        assert_eq!(
+            indexes.index_event(&note).await.status,
+            EventStatus::Rejected
+        );
+
+        // Not indexed: ephemeral
+        let ephemeral = EventBuilder::new(Kind::Custom(20001), "", [])
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(
+            indexes.index_event(&ephemeral).await.status,
+            EventStatus::Ephemeral
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_multiple_values_same_tag() {
+        let keys = Keys::generate();
+        let pubkey_a = Keys::generate().public_key();
+        let pubkey_b = Keys::generate().public_key();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let note_a =
+            EventBuilder::text_note("Note A", [Tag::public_key(pubkey_a), Tag::hashtag("test")])
+                .custom_created_at(Timestamp::from(1))
+                .to_event(&keys)
+                .unwrap();
+        let note_b = EventBuilder::text_note("Note B", [Tag::public_key(pubkey_b)])
+            .custom_created_at(Timestamp::from(2))
+            .to_event(&keys)
+            .unwrap();
+        indexes.bulk_index([note_a.clone(), note_b.clone()]).await;
+
+        // Any of the `p` values
+        let filter = Filter::new().pubkeys([pubkey_a, pubkey_b]);
+        let res = indexes.query(vec![filter.clone()], Order::Desc).await;
+        assert_eq!(res, vec![note_b, note_a.clone()]);
+
+        // Still AND-ed with the other tags
+        let res = indexes
+            .query(vec![filter.hashtag("test")], Order::Desc)
+            .await;
+        assert_eq!(res, vec![note_a]);
+    }
+
+    #[tokio::test]
+    async fn test_check_integrity() {
+        let indexes = DatabaseHelper::unbounded();
+        assert_eq!(indexes.check_integrity().await, Ok(()));
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+        assert_eq!(indexes.check_integrity().await, Ok(()));
+
+        // Dangling entry
+        let keys = Keys::generate();
+        let note = EventBuilder::text_note("Text note", [])
+            .to_event(&keys)
+            .unwrap();
+        {
+            let mut inner = indexes.write().await;
+            inner
+                .author_index
+                .entry(note.pubkey)
+                .or_default()
+                .insert(DatabaseEvent::new(note.clone()));
+        }
+        assert_eq!(
+            indexes.check_integrity().await,
+            Err(Error::DanglingEvent(note.id))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_integrity_and_repair() {
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+        assert!(indexes.verify_integrity().await.is_empty());
+        assert_eq!(indexes.repair().await, 0);
+
+        let stored = Event::from_json(EVENTS[6]).unwrap();
+        let note = EventBuilder::text_note("Text note #test", [Tag::hashtag("test")])
+            .to_event(&Keys::generate())
+            .unwrap();
+        {
+            let mut inner = indexes.write().await;
+
+            // Stored event missing from the ID index
+            inner.ids.remove(&stored.id);
+
+            // Event never stored, but referenced by more indexes
+            let ev = DatabaseEvent::new(note.clone());
+            inner.insert_in_indexes(ev);
+        }
+
+        let issues = indexes.verify_integrity().await;
+        assert_eq!(issues.len(), 2);
+        assert!(issues.contains(&Error::MissingEvent(stored.id)));
+        assert!(issues.contains(&Error::DanglingEvent(note.id)));
+
+        assert_eq!(indexes.repair().await, 2);
+        assert_eq!(indexes.check_integrity().await, Ok(()));
+        assert!(indexes.has_event(&stored.id).await);
+        assert!(!indexes.has_event(&note.id).await);
+        assert!(!indexes
+            .query([Filter::new().hashtag("test")], Order::Desc)
+            .await
+            .contains(&note));
+        assert_eq!(
+            indexes
+                .query(
+                    [Filter::new()
+                        .identifier("id-1")
+                        .author(stored.pubkey)
+                        .kind(stored.kind)],
+                    Order::Desc
+                )
+                .await,
+            vec![stored]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_latest_created_at() {
+        let keys_a = Keys::generate();
+        let keys_b = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+        assert_eq!(indexes.latest_created_at(&keys_a.public_key()).await, None);
+        assert_eq!(indexes.newest_timestamp().await, None);
+
+        let note_a1 = EventBuilder::text_note("A1", [])
+            .custom_created_at(Timestamp::from(10))
+            .to_event(&keys_a)
+            .unwrap();
+        let note_a2 = EventBuilder::text_note("A2", [])
+            .custom_created_at(Timestamp::from(20))
+            .to_event(&keys_a)
+            .unwrap();
+        let note_b = EventBuilder::text_note("B", [])
+            .custom_created_at(Timestamp::from(30))
+            .to_event(&keys_b)
+            .unwrap();
+        indexes
+            .bulk_index([note_a2.clone(), note_a1, note_b.clone()])
+            .await;
+
+        assert_eq!(
+            indexes.latest_created_at(&keys_a.public_key()).await,
+            Some(Timestamp::from(20))
+        );
+        assert_eq!(
+            indexes.latest_created_at(&keys_b.public_key()).await,
+            Some(Timestamp::from(30))
+        );
+        assert_eq!(indexes.newest_timestamp().await, Some(Timestamp::from(30)));
+
+        // After removal
+        indexes.remove_event(&note_a2.id).await;
+        indexes.remove_event(&note_b.id).await;
+        assert_eq!(
+            indexes.latest_created_at(&keys_a.public_key()).await,
+            Some(Timestamp::from(10))
+        );
+        assert_eq!(indexes.latest_created_at(&keys_b.public_key()).await, None);
+        assert_eq!(indexes.newest_timestamp().await, Some(Timestamp::from(10)));
+    }
+
+    #[tokio::test]
+    async fn test_query_authors_and_tags() {
+        let me = Keys::generate();
+        let follows: Vec<Keys> = (0..3).map(|_| Keys::generate()).collect();
+        let other = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let mut expected: Vec<Event> = Vec::new();
+        for (i, keys) in follows.iter().chain([&other]).enumerate() {
+            let mention = EventBuilder::text_note("Mention", [Tag::public_key(me.public_key())])
+                .custom_created_at(Timestamp::from(i as u64 * 10))
+                .to_event(keys)
+                .unwrap();
+            let note = EventBuilder::text_note("Note", [])
+                .custom_created_at(Timestamp::from(i as u64 * 10 + 1))
+                .to_event(keys)
+                .unwrap();
+            let reaction =
+                EventBuilder::new(Kind::Reaction, "+", [Tag::public_key(me.public_key())])
+                    .custom_created_at(Timestamp::from(i as u64 * 10 + 2))
+                    .to_event(keys)
+                    .unwrap();
+
+            if i < follows.len() {
+                expected.push(mention.clone());
+            }
+
+            indexes.bulk_index([mention, note, reaction]).await;
+        }
+        expected.reverse();
+
+        // Mentions are fewer than the notes of the follows and vice versa
+        for authors in [
+            follows.iter().map(|k| k.public_key()).collect::<Vec<_>>(),
+            vec![follows[0].public_key()],
+        ] {
+            let filter = Filter::new()
+                .authors(authors.clone())
+                .kind(Kind::TextNote)
+                .pubkey(me.public_key());
+            let res = indexes.query(vec![filter], Order::Desc).await;
+            let expected: Vec<Event> = expected
+                .iter()
+                .filter(|e| authors.contains(&e.pubkey))
+                .cloned()
+                .collect();
+            assert_eq!(res, expected);
+        }
+
+        // Unknown author
+        let filter = Filter::new()
+            .author(Keys::generate().public_key())
+            .pubkey(me.public_key());
+        assert!(indexes.query(vec![filter], Order::Desc).await.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_readers_and_writers() {
+        const WRITERS: usize = 4;
+        const EVENTS_PER_WRITER: usize = 100;
+        const READERS: usize = 8;
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let mut writers = Vec::with_capacity(WRITERS);
+        for _ in 0..WRITERS {
+            let indexes = indexes.clone();
+            writers.push(tokio::spawn(async move {
+                let keys = Keys::generate();
+                for i in 0..EVENTS_PER_WRITER {
+                    let note = EventBuilder::text_note(format!("Note {i}"), [])
+                        .to_event(&keys)
+                        .unwrap();
+                    assert!(indexes.index_event(&note).await.to_store);
+                }
+            }));
+        }
+
+        let mut readers = Vec::with_capacity(READERS);
+        for _ in 0..READERS {
+            let indexes = indexes.clone();
+            readers.push(tokio::spawn(async move {
+                for _ in 0..50 {
+                    let events = indexes
+                        .query(vec![Filter::new().kind(Kind::TextNote)], Order::Desc)
+                        .await;
+                    assert!(events.len() <= WRITERS * EVENTS_PER_WRITER);
+                    tokio::task::yield_now().await;
+                }
+            }));
+        }
+
+        for handle in writers.into_iter().chain(readers) {
+            handle.await.unwrap();
+        }
+
+        assert_eq!(
+            indexes
+                .count(vec![Filter::new().kind(Kind::TextNote)])
+                .await,
+            WRITERS * EVENTS_PER_WRITER
+        );
+        assert_eq!(indexes.check_integrity().await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_query_limit_zero() {
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let first = Event::from_json(EVENTS[0]).unwrap();
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+
+        let filters = [
+            Filter::new().limit(0),
+            Filter::new().id(first.id).limit(0),
+            Filter::new().author(keys_a.public_key()).limit(0),
+            Filter::new()
+                .author(keys_a.public_key())
+                .kind(Kind::TextNote)
+                .limit(0),
+            Filter::new().hashtag("test").limit(0),
+            Filter::new().search("text").limit(0),
+        ];
+
+        for filter in filters.into_iter() {
+            assert!(indexes
+                .query(vec![filter.clone()], Order::Desc)
+                .await
+                .is_empty());
+            assert_eq!(indexes.count(vec![filter.clone()]).await, 0);
+            assert_eq!(
+                indexes.query_iter(vec![filter], Order::Desc).await.count(),
+                0
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replies_to() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let root = EventBuilder::text_note("Root", [])
+            .custom_created_at(Timestamp::from(1))
+            .to_event(&keys)
+            .unwrap();
+        let replies: Vec<Event> = (2..5)
+            .map(|i| {
+                EventBuilder::text_note(format!("Reply {i}"), [Tag::event(root.id)])
+                    .custom_created_at(Timestamp::from(i))
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+        let other = EventBuilder::text_note("Other", [Tag::event(replies[0].id)])
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&root).await;
+        indexes.index_event(&other).await;
+        indexes.bulk_index(replies.clone()).await;
+
+        let newest_first: Vec<EventId> = replies.iter().rev().map(|e| e.id).collect();
+        assert_eq!(indexes.replies_to(&root.id, None).await, newest_first);
+        assert_eq!(
+            indexes.replies_to(&root.id, Some(2)).await,
+            newest_first[..2]
+        );
+        assert_eq!(
+            indexes.replies_to(&replies[0].id, None).await,
+            vec![other.id]
+        );
+        assert!(indexes.replies_to(&replies[2].id, None).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_index_same_id_never_overwrites() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let note = EventBuilder::text_note("Text note", [Tag::hashtag("test")])
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(indexes.index_event(&note).await.status, EventStatus::Saved);
+
+        let stats = indexes.stats().await;
+        assert_eq!(
+            indexes.index_event(&note).await.status,
+            EventStatus::Duplicate
+        );
+        assert_eq!(indexes.stats().await, stats);
+        assert_eq!(indexes.check_integrity().await, Ok(()));
+
+        // After clear, the same ID is a new event
+        indexes.clear().await;
+        assert_eq!(indexes.index_event(&note).await.status, EventStatus::Saved);
+        assert_eq!(indexes.stats().await, stats);
+        assert_eq!(indexes.check_integrity().await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_event_matches() {
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: Vec<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events.iter().cloned().collect()).await;
+
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+        let ids: Vec<EventId> = indexes.all_event_ids().await;
+
+        let filters = [
+            Filter::new().author(keys_a.public_key()),
+            Filter::new().kinds([Kind::TextNote, Kind::EventDeletion]),
+            Filter::new().since(Timestamp::from(1704644600)),
+            Filter::new().identifier("id-1"),
+            Filter::new().search("empty"),
+            Filter::new().search("note text"),
+        ];
+
+        // Same results of the queries, for the indexed events
+        for filter in filters.into_iter() {
+            let expected: Vec<Event> = indexes.query(vec![filter.clone()], Order::Desc).await;
+            let mut matching: Vec<Event> = events
+                .iter()
+                .filter(|e| ids.contains(&e.id))
+                .filter(|e| event_matches(e, &filter))
+                .cloned()
+                .collect();
+            matching.sort_by(|a, b| b.cmp(a));
+            assert_eq!(matching, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tag_index_cleanup_on_discard() {
+        let keys = Keys::generate();
+        let other = Keys::generate().public_key();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let root = EventBuilder::text_note("Root", []).to_event(&keys).unwrap();
+        indexes.index_event(&root).await;
+
+        // Helper to check if any tag index entry references an event
+        async fn is_tag_indexed(indexes: &DatabaseHelper, id: &EventId) -> bool {
+            let inner = indexes.read().await;
+            inner
+                .tag_index
+                .values()
+                .flat_map(|map| map.values())
+                .flatten()
+                .any(|ev| ev.id == *id)
+        }
+
+        // Removed
+        let reply = EventBuilder::text_note("Reply", [Tag::event(root.id), Tag::public_key(other)])
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&reply).await;
+        assert!(is_tag_indexed(&indexes, &reply.id).await);
+        indexes.remove_event(&reply.id).await;
+        assert!(!is_tag_indexed(&indexes, &reply.id).await);
+
+        // Deleted (NIP-09)
+        indexes.index_event(&reply).await;
+        let deletion = EventBuilder::delete([reply.id]).to_event(&keys).unwrap();
+        indexes.index_event(&deletion).await;
+        assert!(!is_tag_indexed(&indexes, &reply.id).await);
+
+        // Replaced
+        let first = EventBuilder::new(
+            Kind::ParameterizedReplaceable(32121),
+            "",
+            [
+                Tag::identifier("id"),
+                Tag::event(root.id),
+                Tag::public_key(other),
+            ],
+        )
+        .custom_created_at(Timestamp::from(1))
+        .to_event(&keys)
+        .unwrap();
+        let second = EventBuilder::new(
+            Kind::ParameterizedReplaceable(32121),
+            "",
+            [Tag::identifier("id")],
+        )
+        .custom_created_at(Timestamp::from(2))
+        .to_event(&keys)
+        .unwrap();
+        indexes.index_event(&first).await;
+        assert!(is_tag_indexed(&indexes, &first.id).await);
+        indexes.index_event(&second).await;
+        assert!(!is_tag_indexed(&indexes, &first.id).await);
+
+        // The `p` tag is still indexed only if some event references it
+        let inner = indexes.read().await;
+        let p = SingleLetterTag::lowercase(Alphabet::P);
+        assert!(inner.tag_index.get(&p).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_query_recent() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let notes: Vec<Event> = (1..=10)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"), [])
+                    .custom_created_at(Timestamp::from(i * 10))
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+        indexes.bulk_index(notes.clone()).await;
+
+        let now = Timestamp::from(100);
+        let within = Duration::from_secs(30);
+
+        // Last 30 secs
+        let res = indexes
+            .query_recent(Filter::new(), within, now, Order::Asc)
+            .await;
+        assert_eq!(res, notes[6..]);
+
+        // `since` is overridden
+        let filter = Filter::new().since(Timestamp::from(0));
+        let res = indexes.query_recent(filter, within, now, Order::Asc).await;
+        assert_eq!(res, notes[6..]);
+
+        // `until` is kept
+        let filter = Filter::new().until(Timestamp::from(80));
+        let res = indexes.query_recent(filter, within, now, Order::Asc).await;
+        assert_eq!(res, notes[6..8]);
+
+        // Relative to `now`
+        let res = indexes
+            .query_recent(Filter::new(), within, Timestamp::from(50), Order::Asc)
+            .await;
+        assert_eq!(res, notes[1..]);
+    }
+
+    #[tokio::test]
+    async fn test_query_independent_of_insertion_order() {
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+        let keys_b = Keys::new(SecretKey::from_bech32(SECRET_KEY_B).unwrap());
+
+        // Many events with the same timestamps
+        let mut events: Vec<Event> = Vec::new();
+        for i in 0..20u64 {
+            let keys = if i % 2 == 0 { &keys_a } else { &keys_b };
+            let event = EventBuilder::text_note(format!("Note {i}"), [Tag::hashtag("test")])
+                .custom_created_at(Timestamp::from(i % 3))
+                .to_event(keys)
+                .unwrap();
+            events.push(event);
+        }
+
+        let orders: [Vec<Event>; 3] = [
+            events.clone(),
+            events.iter().rev().cloned().collect(),
+            events
+                .iter()
+                .step_by(2)
+                .chain(events.iter().skip(1).step_by(2))
+                .cloned()
+                .collect(),
+        ];
+
+        let filters = [
+            Filter::new(),
+            Filter::new().author(keys_a.public_key()),
+            Filter::new().kind(Kind::TextNote).limit(7),
+            Filter::new().hashtag("test").limit(5),
+            Filter::new().search("note"),
+        ];
+
+        let mut results: Vec<Vec<Vec<Event>>> = Vec::new();
+        for events in orders.into_iter() {
+            let indexes = DatabaseHelper::unbounded();
+            indexes.bulk_index(events).await;
+
+            let mut res = Vec::new();
+            for filter in filters.iter() {
+                res.push(indexes.query(vec![filter.clone()], Order::Desc).await);
+            }
+            results.push(res);
+        }
+
+        assert_eq!(results[0], results[1]);
+        assert_eq!(results[0], results[2]);
+    }
+
+    #[tokio::test]
+    async fn test_observer() {
+        use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+        use std::sync::Mutex;
+
+        #[derive(Debug, Default)]
+        struct Counter {
+            indexed: Arc<AtomicUsize>,
+            stored: Arc<AtomicUsize>,
+            matched: Arc<AtomicUsize>,
+            queries: Arc<Mutex<Vec<Vec<Filter>>>>,
+        }
+
+        impl DatabaseHelperObserver for Counter {
+            fn on_index(&self, result: &DatabaseEventResult) {
+                self.indexed.fetch_add(1, AtomicOrdering::SeqCst);
+                if result.to_store {
+                    self.stored.fetch_add(1, AtomicOrdering::SeqCst);
+                }
+            }
+
+            fn on_query(&self, filters: &[Filter], matched: usize, _elapsed: Duration) {
+                self.matched.fetch_add(matched, AtomicOrdering::SeqCst);
+                self.queries.lock().unwrap().push(filters.to_vec());
+            }
+        }
+
+        let counter = Counter::default();
+        let indexed = counter.indexed.clone();
+        let stored = counter.stored.clone();
+        let matched = counter.matched.clone();
+        let queries = counter.queries.clone();
+
+        let indexes = DatabaseHelper::unbounded().with_observer(counter);
+
+        let keys = Keys::generate();
+        let note = EventBuilder::text_note("Text note", [])
+            .to_event(&keys)
+            .unwrap();
+        let ephemeral = EventBuilder::new(Kind::Custom(20001), "", [])
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&note).await;
+        indexes.bulk_index([note.clone(), ephemeral]).await;
+        assert_eq!(indexed.load(AtomicOrdering::SeqCst), 3);
+        assert_eq!(stored.load(AtomicOrdering::SeqCst), 1);
+
+        indexes.query(vec![Filter::new()], Order::Desc).await;
+        indexes
+            .query(vec![Filter::new().kind(Kind::Metadata)], Order::Desc)
+            .await;
+        assert_eq!(matched.load(AtomicOrdering::SeqCst), 1);
+
+        // Every query by filters is reported, with its filters
+        let filter = Filter::new().kind(Kind::TextNote);
+        indexes.count(vec![filter.clone()]).await;
+        indexes.query_page(filter.clone(), None, 10).await;
+        indexes.query_oldest(vec![filter.clone()]).await;
+        indexes
+            .query_with_deadline(&filter, Instant::now() + Duration::from_secs(60))
+            .await
+            .unwrap();
+        let mut iter = indexes.query_iter(vec![filter.clone()], Order::Desc).await;
+        assert_eq!(queries.lock().unwrap().len(), 6);
+        assert!(iter.next().is_some());
+        assert!(iter.next().is_none());
+        assert_eq!(matched.load(AtomicOrdering::SeqCst), 6);
+
+        let queries = queries.lock().unwrap();
+        assert_eq!(queries.len(), 7);
+        assert!(queries[2..]
+            .iter()
+            .all(|filters| filters == &[filter.clone()]));
+    }
+
+    #[tokio::test]
+    async fn test_normalize_tags() {
+        let keys = Keys::generate();
+        let pubkey = Keys::generate().public_key();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let note =
+            EventBuilder::text_note("Text note", [Tag::public_key(pubkey), Tag::hashtag("test")])
+                .to_event(&keys)
+                .unwrap();
+        indexes.index_event(&note).await;
+
+        let p = SingleLetterTag::lowercase(Alphabet::P);
+        let t = SingleLetterTag::lowercase(Alphabet::T);
+        let upper_p = Filter::new().custom_tag(p, [pubkey.to_hex().to_uppercase()]);
+        let upper_t = Filter::new().custom_tag(t, ["TEST"]);
+
+        // Disabled by default
+        assert!(indexes
+            .query(vec![upper_p.clone()], Order::Desc)
+            .await
+            .is_empty());
+
+        indexes.set_normalize_tags(true).await;
+        assert_eq!(
+            indexes.query(vec![upper_p.clone()], Order::Desc).await,
+            vec![note.clone()]
+        );
+        assert_eq!(indexes.count(vec![upper_p.clone()]).await, 1);
+
+        // Other tags aren't normalized
+        assert!(indexes.query(vec![upper_t], Order::Desc).await.is_empty());
+
+        // Kept after clear
+        indexes.clear().await;
+        indexes.index_event(&note).await;
+        assert_eq!(
+            indexes.query(vec![upper_p.clone()], Order::Desc).await,
+            vec![note.clone()]
+        );
+
+        // Uppercase values of the events are normalized too
+        let upper = EventBuilder::text_note(
+            "Uppercase",
+            [Tag::parse(&[String::from("p"), pubkey.to_hex().to_uppercase()]).unwrap()],
+        )
+        .to_event(&keys)
+        .unwrap();
+        indexes.index_event(&upper).await;
+        let lower_p = Filter::new().custom_tag(p, [pubkey.to_hex()]);
+        for filter in [
+            lower_p.clone(),
+            upper_p.clone(),
+            upper_p.clone().author(keys.public_key()),
+        ] {
+            assert_eq!(indexes.query(vec![filter], Order::Desc).await.len(), 2);
+        }
+
+        // Removed with the normalized value
+        indexes.remove_event(&upper.id).await;
+        let inner = indexes.read().await;
+        let map = inner.tag_index.get(&p).unwrap();
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&pubkey.to_hex()).map(|set| set.len()), Some(1));
+        drop(inner);
+
+        // Disabling it builds the index with the raw values
+        indexes.index_event(&upper).await;
+        indexes.set_normalize_tags(false).await;
+        assert_eq!(indexes.query(vec![lower_p], Order::Desc).await, vec![note]);
+        assert_eq!(indexes.query(vec![upper_p], Order::Desc).await, vec![upper]);
+    }
+
+    #[tokio::test]
+    async fn test_query_page() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // Many events with the same timestamps
+        let events: Vec<Event> = (0..10)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"), [])
+                    .custom_created_at(Timestamp::from(i / 4))
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+        indexes.bulk_index(events).await;
+
+        let filter = Filter::new().author(keys.public_key());
+        let expected: Vec<Event> = indexes.query(vec![filter.clone()], Order::Desc).await;
+
+        // Pages of 3 events
+        let mut pages: Vec<Vec<Event>> = Vec::new();
+        let mut cursor: Option<Cursor> = None;
+        loop {
+            let (page, next) = indexes.query_page(filter.clone(), cursor, 3).await;
+            pages.push(page);
+
+            match next {
+                // Persist and restore the cursor
+                Some(next) => cursor = Some(Cursor::from_str(&next.to_string()).unwrap()),
+                None => break,
+            }
+        }
+
+        assert_eq!(
+            pages.iter().map(|page| page.len()).collect::<Vec<_>>(),
+            vec![3, 3, 3, 1]
+        );
+        assert_eq!(pages.concat(), expected);
+
+        // Exact number of events
+        let (page, next) = indexes.query_page(filter.clone(), None, 10).await;
+        assert_eq!(page, expected);
+        assert_eq!(next, None);
+
+        // All the events, with the `limit` capping every page
+        let mut pages: Vec<Event> = Vec::new();
+        let mut cursor: Option<Cursor> = None;
+        loop {
+            let (page, next) = indexes.query_page(Filter::new().limit(4), cursor, 5).await;
+            assert!(page.len() <= 4);
+            pages.extend(page);
+
+            match next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(pages, expected);
+
+        // `until` before the cursor
+        let filter = Filter::new().until(Timestamp::from(1));
+        let (page, next) = indexes.query_page(filter.clone(), None, 2).await;
+        let (rest, _) = indexes.query_page(filter.clone(), next, 10).await;
+        assert_eq!(
+            [page, rest].concat(),
+            indexes.query(vec![filter], Order::Desc).await
+        );
+
+        assert_eq!(Cursor::from_str("abc"), Err(Error::InvalidCursor));
+        assert_eq!(Cursor::from_str("1:abc"), Err(Error::InvalidCursor));
+    }
+
+    #[tokio::test]
+    async fn test_replaced_coordinate() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // Replaceable
+        let metadata_1 = EventBuilder::metadata(&Metadata::new().name("account"))
+            .custom_created_at(Timestamp::from(1))
+            .to_event(&keys)
+            .unwrap();
+        let metadata_2 = EventBuilder::metadata(&Metadata::new().name("account-2"))
+            .custom_created_at(Timestamp::from(2))
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(indexes.index_event(&metadata_1).await.replaced, None);
+        let res = indexes.index_event(&metadata_2).await;
+        assert_eq!(
+            res.replaced,
+            Some(Coordinate::new(Kind::Metadata, keys.public_key()))
+        );
+        assert_eq!(res.to_discard, HashSet::from([metadata_1.id]));
+
+        // Parameterized replaceable
+        let kind = Kind::ParameterizedReplaceable(32121);
+        let first = EventBuilder::new(kind, "", [Tag::identifier("id")])
+            .custom_created_at(Timestamp::from(1))
+            .to_event(&keys)
+            .unwrap();
+        let second = EventBuilder::new(kind, "", [Tag::identifier("id")])
+            .custom_created_at(Timestamp::from(2))
+            .to_event(&keys)
+            .unwrap();
+        let other = EventBuilder::new(kind, "", [Tag::identifier("other")])
+            .custom_created_at(Timestamp::from(3))
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(indexes.index_event(&first).await.replaced, None);
+        assert_eq!(
+            indexes.index_event(&second).await.replaced,
+            Some(Coordinate::new(kind, keys.public_key()).identifier("id"))
+        );
+        assert_eq!(indexes.index_event(&other).await.replaced, None);
+
+        // Not replaced
+        assert_eq!(indexes.index_event(&first).await.replaced, None);
+    }
+
+    #[tokio::test]
+    async fn test_verifying() {
+        let keys = Keys::generate();
+
+        let note = EventBuilder::text_note("Text note", [])
+            .to_event(&keys)
+            .unwrap();
+        let forged = Event::new(
+            note.id,
+            note.pubkey,
+            note.created_at,
+            note.kind,
+            note.tags.clone(),
+            "Forged",
+            note.signature(),
+        );
+        let other = EventBuilder::text_note("Other", [])
+            .to_event(&keys)
+            .unwrap();
+
+        // Not verifying by default
+        let indexes = DatabaseHelper::unbounded();
+        assert_eq!(
+            indexes.index_event(&forged).await.status,
+            EventStatus::Saved
+        );
+
+        let indexes = DatabaseHelper::unbounded().verifying();
+        let res = indexes.index_event(&forged).await;
+        assert_eq!(res.status, EventStatus::Invalid);
+        assert!(!res.to_store);
+        assert!(!indexes.has_event(&forged.id).await);
+
+        let results = indexes.bulk_index([forged, note.clone(), other]).await;
+        assert_eq!(
+            results.iter().map(|res| res.status).collect::<Vec<_>>(),
+            vec![EventStatus::Invalid, EventStatus::Saved, EventStatus::Saved]
+        );
+        assert!(indexes.has_event(&note.id).await);
+    }
+
+    #[tokio::test]
+    async fn test_query_by_many_authors() {
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+        let keys_b = Keys::new(SecretKey::from_bech32(SECRET_KEY_B).unwrap());
+        let authors = [keys_a.public_key(), keys_b.public_key()];
+
+        let filters = [
+            Filter::new().authors(authors),
+            Filter::new().authors(authors).limit(3),
+            Filter::new()
+                .authors(authors)
+                .since(Timestamp::from(1704644596))
+                .until(Timestamp::from(1704645586)),
+        ];
+
+        let inner = indexes.read().await;
+        for filter in filters {
+            assert!(matches!(
+                QueryPattern::from(filter.clone()),
+                QueryPattern::Authors(..)
+            ));
+            let limit = filter.limit.unwrap_or(usize::MAX);
+            let expected: Vec<EventId> = inner
+                .internal_generic_query(filter.clone(), &visit_all)
+                .take(limit)
+                .map(|e| e.id)
+                .collect();
+            let found: Vec<EventId> = inner
+                .query(vec![filter], Order::Desc)
+                .into_iter()
+                .map(|e| e.id)
+                .collect();
+            assert!(!found.is_empty());
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_events_of_kind() {
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        // Only the current versions of the parameterized replaceable events
+        let expected: Vec<EventId> = [EVENTS[6], EVENTS[5], EVENTS[4]]
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap().id)
+            .collect();
+        assert_eq!(
+            indexes.events_of_kind(Kind::from(32122), None).await,
+            expected
+        );
+        assert_eq!(
+            indexes.events_of_kind(Kind::from(32122), Some(1)).await,
+            expected[..1]
+        );
+        assert!(indexes
+            .events_of_kind(Kind::TextNote, Some(0))
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_query_empty_filter() {
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        // An empty filter matches everything
+        let all = indexes.query([Filter::new()], Order::Desc).await;
+        assert_eq!(all.len(), 10);
+        assert!(all.windows(2).all(|w| w[0].created_at >= w[1].created_at));
+
+        // A filter with only the limit returns the newest events
+        assert_eq!(
+            indexes.query([Filter::new().limit(3)], Order::Desc).await,
+            vec![
+                Event::from_json(EVENTS[13]).unwrap(),
+                Event::from_json(EVENTS[12]).unwrap(),
+                Event::from_json(EVENTS[11]).unwrap(),
+            ]
+        );
+        assert_eq!(
+            indexes.query([Filter::new().limit(3)], Order::Desc).await,
+            all[..3]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serialize_roundtrip() {
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+
+        let indexes = DatabaseHelper::bounded(100);
+        indexes.set_normalize_tags(true).await;
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let bytes = indexes.serialize().await;
+        let restored = DatabaseHelper::deserialize(&bytes).unwrap();
+        assert_eq!(restored.stats().await, indexes.stats().await);
+        assert_eq!(restored.serialize().await.len(), bytes.len());
+
+        let filters = [
+            Filter::new(),
+            Filter::new().author(keys_a.public_key()).limit(2),
+            Filter::new().kind(Kind::from(32122)),
+            Filter::new().identifier("id-1"),
+            Filter::new().search("note"),
+        ];
+        for filter in filters {
+            assert_eq!(
+                restored.query([filter.clone()], Order::Desc).await,
+                indexes.query([filter], Order::Desc).await
+            );
+        }
+
+        // Deletions are kept: a deleted event is still rejected
+        let deleted = Event::from_json(EVENTS[3]).unwrap();
+        assert_eq!(
+            restored.index_event(&deleted).await.status,
+            EventStatus::Rejected
+        );
+
+        // Invalid snapshots
+        assert_eq!(
+            DatabaseHelper::deserialize(&bytes[..bytes.len() - 1]).unwrap_err(),
+            Error::InvalidSnapshot
+        );
+        // More events than the capacity
+        let small = DatabaseHelper::bounded(1);
+        small.set_normalize_tags(true).await;
+        let small_bytes = small.serialize().await;
+        let pos = bytes
+            .iter()
+            .zip(small_bytes.iter())
+            .position(|(a, b)| a != b)
+            .unwrap();
+        let mut over_capacity = bytes.clone();
+        over_capacity[pos] = small_bytes[pos];
+        assert_eq!(
+            DatabaseHelper::deserialize(&over_capacity).unwrap_err(),
+            Error::InvalidSnapshot
+        );
+        let mut unsupported = bytes.clone();
+        unsupported[0] = u8::MAX;
+        assert_eq!(
+            DatabaseHelper::deserialize(&unsupported).unwrap_err(),
+            Error::UnsupportedSnapshotVersion(u8::MAX)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_replaceable_updates() {
+        const TASKS: usize = 8;
+        const UPDATES_PER_TASK: usize = 25;
+
+        let keys = Keys::generate();
+        let indexes = DatabaseHelper::unbounded();
+
+        // Interleave the timestamps across the tasks, so the newest event isn't the last indexed
+        let mut tasks = Vec::with_capacity(TASKS);
+        for t in 0..TASKS {
+            let events: Vec<Event> = (0..UPDATES_PER_TASK)
+                .rev()
+                .flat_map(|i| {
+                    let created_at = Timestamp::from((1_000 + i * TASKS + t) as u64);
+                    [
+                        EventBuilder::metadata(&Metadata::new().name(format!("{t}-{i}")))
+                            .custom_created_at(created_at)
+                            .to_event(&keys)
+                            .unwrap(),
+                        EventBuilder::new(Kind::from(30001), "", [Tag::identifier("list")])
+                            .custom_created_at(created_at)
+                            .to_event(&keys)
+                            .unwrap(),
+                    ]
+                })
+                .collect();
+            let indexes = indexes.clone();
+            tasks.push(tokio::spawn(async move {
+                for event in events.iter() {
+                    indexes.index_event(event).await;
+                    tokio::task::yield_now().await;
+                }
+            }));
+        }
+
+        for handle in tasks.into_iter() {
+            handle.await.unwrap();
+        }
+
+        let newest = Timestamp::from((1_000 + UPDATES_PER_TASK * TASKS - 1) as u64);
+        for filter in [
+            Filter::new().kind(Kind::Metadata).author(keys.public_key()),
+            Filter::new()
+                .kind(Kind::from(30001))
+                .author(keys.public_key())
+                .identifier("list"),
+        ] {
+            let events = indexes.query([filter], Order::Desc).await;
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].created_at, newest);
+        }
+        assert_eq!(indexes.count([Filter::new()]).await, 2);
+        assert_eq!(indexes.check_integrity().await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_query_kind_range() {
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let ids = |positions: &[usize]| -> Vec<EventId> {
+            positions
+                .iter()
+                .map(|i| Event::from_json(EVENTS[*i]).unwrap().id)
+                .collect()
+        };
+
+        // Addressable events
+        assert_eq!(
+            indexes
+                .query_kind_range(30000..=39999, &Filter::new())
+                .await,
+            ids(&[6, 5, 4, 1])
+        );
+        assert_eq!(
+            indexes
+                .query_kind_range(30000..=39999, &Filter::new().author(keys_a.public_key()))
+                .await,
+            ids(&[6, 1])
+        );
+        assert_eq!(
+            indexes
+                .query_kind_range(30000..=39999, &Filter::new().identifier("id-1").limit(2))
+                .await,
+            ids(&[6, 5])
+        );
+        assert_eq!(
+            indexes
+                .query_kind_range(
+                    30000..=39999,
+                    &Filter::new().until(Timestamp::from(1704644601))
+                )
+                .await,
+            ids(&[4, 1])
+        );
+
+        // Match the kind too, if set in the filter
+        assert!(indexes
+            .query_kind_range(0..=10, &Filter::new().kind(Kind::from(32122)))
+            .await
+            .is_empty());
+
+        // Same search of the queries: words are matched entirely
+        let keys = Keys::generate();
+        let note = EventBuilder::text_note("Hello, Nostr!", [])
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&note).await;
+        let author = Filter::new().author(keys.public_key());
+        assert_eq!(
+            indexes
+                .query_kind_range(0..=10, &author.clone().search("nostr"))
+                .await,
+            vec![note.id]
+        );
+        assert!(indexes
+            .query_kind_range(0..=10, &author.search("nost"))
+            .await
+            .is_empty());
+
+        // The kinds without events are dropped
+        indexes.remove_event(&note.id).await;
+        let inner = indexes.read().await;
+        assert!(inner
+            .kind_authors
+            .get(&Kind::TextNote)
+            .map_or(true, |authors| !authors.contains(&keys.public_key())));
+    }
+
+    #[tokio::test]
+    async fn test_evaluate() {
+        let indexes = DatabaseHelper::bounded(10);
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let keys = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+        let stats = indexes.stats().await;
+
+        let metadata = EventBuilder::metadata(&Metadata::new().name("Key A"))
+            .custom_created_at(Timestamp::from(1704646000))
+            .to_event(&keys)
+            .unwrap();
+        let old = EventBuilder::text_note("Old", [])
+            .custom_created_at(Timestamp::from(1704600000))
+            .to_event(&keys)
+            .unwrap();
+        let new = EventBuilder::text_note("New", [])
+            .custom_created_at(Timestamp::from(1704700000))
+            .to_event(&keys)
+            .unwrap();
+        let candidates = [
+            metadata,
+            old,
+            new,
+            Event::from_json(EVENTS[0]).unwrap(),
+            Event::from_json(EVENTS[3]).unwrap(),
+        ];
+
+        for event in candidates.iter() {
+            let evaluation = indexes.evaluate(event).await;
+            assert_eq!(indexes.stats().await, stats);
+
+            // Same result of the real indexing
+            let copy = DatabaseHelper::deserialize(&indexes.serialize().await).unwrap();
+            assert_eq!(copy.index_event(event).await, evaluation);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tag_value_counts() {
+        let me = Keys::generate();
+        let other = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+        for i in 0..5 {
+            let tags = if i % 2 == 0 {
+                vec![
+                    Tag::public_key(me.public_key()),
+                    Tag::public_key(other.public_key()),
+                ]
+            } else {
+                vec![Tag::public_key(me.public_key())]
+            };
+            let note = EventBuilder::text_note(format!("Note {i}"), tags)
+                .to_event(&other)
+                .unwrap();
+            indexes.index_event(&note).await;
+        }
+
+        assert_eq!(
+            indexes.tag_value_counts(Alphabet::P, 10).await,
+            vec![
+                (me.public_key().to_hex(), 5),
+                (other.public_key().to_hex(), 3)
+            ]
+        );
+        assert_eq!(
+            indexes.tag_value_counts(Alphabet::P, 1).await,
+            vec![(me.public_key().to_hex(), 5)]
+        );
+        assert!(indexes.tag_value_counts(Alphabet::T, 10).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_query_from_sources() {
+        let keys = Keys::generate();
+        let trusted = Url::parse("wss://trusted.example.com").unwrap();
+        let other = Url::parse("wss://other.example.com").unwrap();
+
+        let notes: Vec<Event> = (0..4)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"), [])
+                    .custom_created_at(Timestamp::from(1000 + i))
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+
+        let indexes = DatabaseHelper::unbounded();
+        indexes
+            .index_event_with_source(&notes[0], trusted.clone())
+            .await;
+        indexes
+            .index_event_with_source(&notes[1], other.clone())
+            .await;
+        indexes.index_event(&notes[2]).await;
+        indexes
+            .index_event_with_source(&notes[3], other.clone())
+            .await;
+
+        // Seen later on the trusted relay too
+        let res = indexes
+            .index_event_with_source(&notes[3], trusted.clone())
+            .await;
+        assert_eq!(res.status, EventStatus::Duplicate);
+
+        let sources: HashSet<Url> = HashSet::from([trusted]);
+        assert_eq!(
+            indexes
+                .query_from_sources([Filter::new()], Order::Desc, &sources)
+                .await,
+            vec![notes[3].clone(), notes[0].clone()]
+        );
+        assert!(indexes
+            .query_from_sources([Filter::new()], Order::Desc, &HashSet::new())
+            .await
+            .is_empty());
+
+        // Sources are dropped with the event
+        indexes.remove_event(&notes[0].id).await;
+        assert_eq!(
+            indexes
+                .query_from_sources([Filter::new()], Order::Desc, &sources)
+                .await,
+            vec![notes[3].clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_from_sources_with_limit() {
+        let keys = Keys::generate();
+        let trusted = Url::parse("wss://trusted.example.com").unwrap();
+        let untrusted = Url::parse("wss://untrusted.example.com").unwrap();
+
+        let indexes = DatabaseHelper::unbounded();
+        let mut notes: Vec<Event> = Vec::new();
+        for i in 0..3 {
+            let note = EventBuilder::text_note(format!("Note {i}"), [])
+                .custom_created_at(Timestamp::from(1000 + i))
+                .to_event(&keys)
+                .unwrap();
+            indexes
+                .index_event_with_source(&note, trusted.clone())
+                .await;
+            notes.push(note);
+        }
+
+        // The untrusted relay has the newest events
+        for i in 0..3 {
+            let note = EventBuilder::text_note(format!("Untrusted {i}"), [])
+                .custom_created_at(Timestamp::from(2000 + i))
+                .to_event(&keys)
+                .unwrap();
+            indexes
+                .index_event_with_source(&note, untrusted.clone())
+                .await;
+        }
+
+        let sources: HashSet<Url> = HashSet::from([trusted]);
+        for filter in [
+            Filter::new().limit(2),
+            Filter::new().author(keys.public_key()).limit(2),
+        ] {
+            let res = indexes
+                .query_from_sources([filter], Order::Desc, &sources)
+                .await;
+            assert_eq!(res, vec![notes[2].clone(), notes[1].clone()]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_histogram() {
+        let keys = Keys::generate();
+        let indexes = DatabaseHelper::unbounded();
+        for created_at in [100, 101, 130, 165, 200, 250] {
+            let note = EventBuilder::text_note(format!("Note {created_at}"), [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap();
+            indexes.index_event(&note).await;
+        }
+
+        assert_eq!(
+            indexes
+                .histogram(Timestamp::from(100), Timestamp::from(200), 30)
+                .await
+                .unwrap(),
+            vec![
+                (Timestamp::from(100), 2),
+                (Timestamp::from(130), 1),
+                (Timestamp::from(160), 1),
+                (Timestamp::from(190), 1),
+            ]
+        );
+
+        // Contiguous series also without events
+        assert_eq!(
+            indexes
+                .histogram(Timestamp::from(0), Timestamp::from(20), 10)
+                .await
+                .unwrap(),
+            vec![
+                (Timestamp::from(0), 0),
+                (Timestamp::from(10), 0),
+                (Timestamp::from(20), 0),
+            ]
+        );
+
+        assert!(indexes
+            .histogram(Timestamp::from(100), Timestamp::from(200), 0)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(indexes
+            .histogram(Timestamp::from(200), Timestamp::from(100), 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Huge ranges are rejected instead of allocated
+        assert_eq!(
+            indexes
+                .histogram(Timestamp::from(0), Timestamp::from(u64::MAX), 1)
+                .await,
+            Err(Error::TooManyBuckets)
+        );
+        let max = MAX_HISTOGRAM_BUCKETS as u64;
+        assert_eq!(
+            indexes
+                .histogram(Timestamp::from(0), Timestamp::from(max), 1)
+                .await,
+            Err(Error::TooManyBuckets)
+        );
+        let series = indexes
+            .histogram(Timestamp::from(0), Timestamp::from(max - 1), 1)
+            .await
+            .unwrap();
+        assert_eq!(series.len(), MAX_HISTOGRAM_BUCKETS);
+        assert_eq!(series.iter().map(|(_, count)| count).sum::<usize>(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_ephemeral_status() {
+        let keys = Keys::generate();
+        let ephemeral = EventBuilder::new(Kind::from(20001), "Ephemeral", [])
+            .to_event(&keys)
+            .unwrap();
+
+        let indexes = DatabaseHelper::unbounded();
+        let res = indexes.index_event(&ephemeral).await;
+        assert_eq!(res.status, EventStatus::Ephemeral);
+        assert!(!res.to_store);
+        assert!(res.to_discard.is_empty());
+        assert!(!indexes.has_event(&ephemeral.id).await);
+        assert_eq!(indexes.evaluate(&ephemeral).await, res);
+
+        // Verified before being reported as ephemeral
+        let forged = Event::new(
+            ephemeral.id,
+            ephemeral.pubkey,
+            ephemeral.created_at,
+            ephemeral.kind,
+            ephemeral.tags.clone(),
+            "Forged",
+            ephemeral.signature(),
+        );
+        let indexes = DatabaseHelper::unbounded().verifying();
+        assert_eq!(
+            indexes.index_event(&forged).await.status,
+            EventStatus::Invalid
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_within_ids() {
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let id = |i: usize| Event::from_json(EVENTS[i]).unwrap().id;
+
+        // 3 deleted, 4 and 5 by another author, 6 and 12 not text notes
+        let candidates: HashSet<EventId> = [0, 3, 4, 5, 6, 12].into_iter().map(id).collect();
+
+        let filter = Filter::new().author(keys_a.public_key());
+        assert_eq!(
+            indexes.query_within_ids(&candidates, &filter).await,
+            vec![id(12), id(6), id(0)]
+        );
+
+        let filter = Filter::new()
+            .author(keys_a.public_key())
+            .kind(Kind::TextNote);
+        assert_eq!(
+            indexes.query_within_ids(&candidates, &filter).await,
+            vec![id(0)]
+        );
+
+        // Intersection with the IDs of the filter
+        let filter = Filter::new().ids([id(4), id(13)]);
+        assert_eq!(
+            indexes.query_within_ids(&candidates, &filter).await,
+            vec![id(4)]
+        );
+
+        assert!(indexes
+            .query_within_ids(&HashSet::new(), &Filter::new())
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_query_tag_values_union() {
+        let keys = Keys::generate();
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+
+        let indexes = DatabaseHelper::unbounded();
+        let mut notes: Vec<Event> = Vec::new();
+        for (i, tags) in [
+            vec![Tag::public_key(alice)],
+            vec![Tag::public_key(alice), Tag::public_key(bob)],
+            vec![Tag::public_key(bob)],
+            vec![],
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let note = EventBuilder::text_note(format!("Note {i}"), tags)
+                .custom_created_at(Timestamp::from(i as u64))
+                .to_event(&keys)
+                .unwrap();
+            indexes.index_event(&note).await;
+            notes.push(note);
+        }
+
+        // The note mentioning both is returned once
+        let filter = Filter::new().pubkeys([alice, bob]);
+        assert_eq!(
+            indexes.query([filter.clone()], Order::Desc).await,
+            vec![notes[2].clone(), notes[1].clone(), notes[0].clone()]
+        );
+        assert_eq!(indexes.count([filter.clone()]).await, 3);
+
+        // Overlapping filters
+        let filters = [filter, Filter::new().pubkey(alice).limit(1)];
+        assert_eq!(
+            indexes.query(filters.clone(), Order::Asc).await,
+            vec![notes[0].clone(), notes[1].clone(), notes[2].clone()]
+        );
+        assert_eq!(indexes.count(filters).await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_since_until_boundaries() {
+        let keys = Keys::generate();
+        let other = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+        let mut notes: Vec<Event> = Vec::new();
+        for created_at in [100, 101, 102] {
+            let note = EventBuilder::text_note(
+                format!("Boundary note {created_at}"),
+                [Tag::hashtag("boundary")],
+            )
+            .custom_created_at(Timestamp::from(created_at))
+            .to_event(&keys)
+            .unwrap();
+            indexes.index_event(&note).await;
+            notes.push(note);
+        }
+
+        // One filter for every query pattern
+        let patterns = [
+            Filter::new(),
+            Filter::new().kind(Kind::TextNote),
+            Filter::new().author(keys.public_key()),
+            Filter::new().authors([keys.public_key(), other.public_key()]),
+            Filter::new().author(keys.public_key()).kind(Kind::TextNote),
+            Filter::new().hashtag("boundary"),
+            Filter::new().search("boundary"),
+            Filter::new().ids(notes.iter().map(|e| e.id)),
+        ];
+
+        // (since, until, indexes of the expected notes)
+        let matrix: [(u64, u64, &[usize]); 9] = [
+            (100, 100, &[0]),
+            (101, 101, &[1]),
+            (102, 102, &[2]),
+            (100, 101, &[1, 0]),
+            (101, 102, &[2, 1]),
+            (100, 102, &[2, 1, 0]),
+            (99, 99, &[]),
+            (103, 103, &[]),
+            (102, 101, &[]),
+        ];
+
+        for pattern in patterns.iter() {
+            for (since, until, expected) in matrix.iter() {
+                let filter = pattern
+                    .clone()
+                    .since(Timestamp::from(*since))
+                    .until(Timestamp::from(*until));
+                let expected: Vec<Event> = expected.iter().map(|i| notes[*i].clone()).collect();
+                assert_eq!(
+                    indexes.query([filter.clone()], Order::Desc).await,
+                    expected,
+                    "{filter:?}"
+                );
+                assert_eq!(indexes.count([filter]).await, expected.len());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_addressable() {
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+        let keys_b = Keys::new(SecretKey::from_bech32(SECRET_KEY_B).unwrap());
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let id = |i: usize| Event::from_json(EVENTS[i]).unwrap().id;
+        let kind = Kind::from(32122);
+
+        // Event 2 replaced by Event 6
+        assert_eq!(
+            indexes
+                .get_addressable(kind, &keys_a.public_key(), "id-1")
+                .await,
+            Some(id(6))
+        );
+        assert_eq!(
+            indexes
+                .get_addressable(kind, &keys_b.public_key(), "id-1")
+                .await,
+            Some(id(5))
+        );
+
+        // Event 3 deleted by Event 8
+        assert_eq!(
+            indexes
+                .get_addressable(kind, &keys_a.public_key(), "id-2")
+                .await,
+            None
+        );
+
+        // Not parameterized replaceable
+        assert_eq!(
+            indexes
+                .get_addressable(Kind::TextNote, &keys_a.public_key(), "")
+                .await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clear_author() {
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+        let keys_b = Keys::new(SecretKey::from_bech32(SECRET_KEY_B).unwrap());
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let others = indexes
+            .query([Filter::new().author(keys_b.public_key())], Order::Desc)
+            .await;
+
+        assert_eq!(indexes.clear_author(&keys_a.public_key()).await, 5);
+        assert_eq!(indexes.clear_author(&keys_a.public_key()).await, 0);
+        assert_eq!(indexes.check_integrity().await, Ok(()));
+
+        // Nothing of the author left in any index
+        assert_eq!(indexes.query([Filter::new()], Order::Desc).await, others);
+        assert!(indexes
+            .query([Filter::new().search("note")], Order::Desc)
+            .await
+            .is_empty());
+        assert!(indexes
+            .query(
+                [Filter::new()
+                    .identifier("id-1")
+                    .author(keys_a.public_key())
+                    .kind(Kind::from(32122))],
+                Order::Desc
+            )
+            .await
+            .is_empty());
+        assert_eq!(indexes.count_by_author(&keys_a.public_key()).await, 0);
+        assert!(!indexes.count_by_kind().await.contains_key(&Kind::TextNote));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_consistent_reads_during_writes() {
+        const WRITERS: usize = 4;
+        const UPDATES_PER_WRITER: usize = 50;
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let mut writers = Vec::with_capacity(WRITERS);
+        for w in 0..WRITERS {
+            let indexes = indexes.clone();
+            writers.push(tokio::spawn(async move {
+                let keys = Keys::generate();
+                for i in 0..UPDATES_PER_WRITER {
+                    // Index in more maps (tags, words, coordinates) and replace the previous one
+                    let event = EventBuilder::new(
+                        Kind::from(30000),
+                        format!("Writer {w} update {i}"),
+                        [Tag::identifier("list"), Tag::hashtag("update")],
+                    )
+                    .custom_created_at(Timestamp::from(i as u64))
+                    .to_event(&keys)
+                    .unwrap();
+                    indexes.index_event(&event).await;
+                }
+            }));
+        }
+
+        let mut readers = Vec::with_capacity(WRITERS);
+        for _ in 0..WRITERS {
+            let indexes = indexes.clone();
+            readers.push(tokio::spawn(async move {
+                for _ in 0..100 {
+                    // Never a torn state: an event is in all its indexes or in none
+                    assert_eq!(indexes.check_integrity().await, Ok(()));
+                    let tagged = indexes.count([Filter::new().hashtag("update")]).await;
+                    assert!(tagged <= WRITERS);
+                    tokio::task::yield_now().await;
+                }
+            }));
+        }
+
+        for handle in writers.into_iter().chain(readers) {
+            handle.await.unwrap();
+        }
+
+        assert_eq!(
+            indexes.count([Filter::new().hashtag("update")]).await,
+            WRITERS
+        );
+        assert_eq!(indexes.check_integrity().await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_event_sort_key() {
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let keys = Keys::generate();
+        let same_timestamp: Vec<Event> = (0..3)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"), [])
+                    .custom_created_at(Timestamp::from(1704644581))
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+        indexes.bulk_index(same_timestamp).await;
+
+        let results = indexes.query([Filter::new()], Order::Desc).await;
+        let mut sorted = results.clone();
+        sorted.reverse();
+        sorted.sort_by_key(event_sort_key);
+        assert_eq!(sorted, results);
+    }
+
+    #[tokio::test]
+    async fn test_max_future_skew() {
+        let keys = Keys::generate();
+        let now = Timestamp::from(1_000_000);
+        let note = |created_at: u64| {
+            EventBuilder::text_note(format!("Note {created_at}"), [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap()
+        };
+
+        let indexes = DatabaseHelper::unbounded();
+        let mut inner = indexes.write().await;
+
+        // Disabled by default
+        let res = inner.internal_index_event(&note(2_000_000), &now);
+        assert_eq!(res.status, EventStatus::Saved);
+
+        inner.max_future_skew = Some(Duration::from_secs(60));
+        let res = inner.internal_index_event(&note(1_000_060), &now);
+        assert_eq!(res.status, EventStatus::Saved);
+        let future = note(1_000_061);
+        let res = inner.internal_index_event(&future, &now);
+        assert_eq!(res.status, EventStatus::FutureTimestamp);
+        assert!(!res.to_store);
+        assert!(res.to_discard.is_empty());
+        assert!(!inner.has_event(&future.id));
+
+        // Accepted later
+        let later = now + Duration::from_secs(1);
+        let res = inner.internal_index_event(&future, &later);
+        assert_eq!(res.status, EventStatus::Saved);
+        drop(inner);
+
+        indexes.set_max_future_skew(None).await;
+        let far = EventBuilder::text_note("Far", [])
+            .custom_created_at(Timestamp::now() + Duration::from_secs(3600))
+            .to_event(&keys)
+            .unwrap();
+        assert!(indexes.index_event(&far).await.to_store);
+    }
+
+    #[tokio::test]
+    async fn test_query_missing_tag() {
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let id = |i: usize| Event::from_json(EVENTS[i]).unwrap().id;
+
+        // 6 and 1 have a `d` tag
+        let filter = Filter::new().author(keys_a.public_key());
+        assert_eq!(
+            indexes.query_missing_tag(&filter, Alphabet::D).await,
+            vec![id(12), id(8), id(0)]
+        );
+
+        // Limit applied after the exclusion
+        let filter = Filter::new().author(keys_a.public_key()).limit(2);
+        assert_eq!(
+            indexes.query_missing_tag(&filter, Alphabet::D).await,
+            vec![id(12), id(8)]
+        );
+
+        // No text note has a `client` tag
+        let filter = Filter::new().kind(Kind::TextNote);
+        assert_eq!(
+            indexes.query_missing_tag(&filter, Alphabet::C).await,
+            vec![id(0)]
+        );
+
+        let filter = Filter::new().kind(Kind::Custom(32122));
+        assert!(indexes
+            .query_missing_tag(&filter, Alphabet::D)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_index_same_event_twice() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let note = EventBuilder::text_note("Delivered by many relays", [Tag::hashtag("nostr")])
+            .to_event(&keys)
+            .unwrap();
+        assert!(indexes.index_event(&note).await.to_store);
+        let stats = indexes.stats().await;
+
+        let res = indexes.index_event(&note).await;
+        assert_eq!(res.status, EventStatus::Duplicate);
+        assert!(!res.to_store);
+
+        // No new entries
+        assert_eq!(indexes.stats().await, stats);
+        assert_eq!(indexes.count([Filter::new()]).await, 1);
+        assert_eq!(indexes.count_by_author(&keys.public_key()).await, 1);
+        assert_eq!(indexes.count([Filter::new().hashtag("nostr")]).await, 1);
+        assert_eq!(
+            indexes
+                .query([Filter::new().author(keys.public_key())], Order::Desc)
+                .await,
+            vec![note]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_replaceable() {
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+        let keys_b = Keys::new(SecretKey::from_bech32(SECRET_KEY_B).unwrap());
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        // Event 9 and 10 deleted
+        assert_eq!(
+            indexes
+                .get_replaceable(&keys_a.public_key(), Kind::Metadata)
+                .await,
+            None
+        );
+
+        let first = Event::from_json(REPLACEABLE_EVENT_1).unwrap();
+        let second = Event::from_json(REPLACEABLE_EVENT_2).unwrap();
+        assert!(indexes.index_event(&first).await.to_store);
+        assert_eq!(
+            indexes
+                .get_replaceable(&keys_a.public_key(), Kind::Metadata)
+                .await,
+            Some(first.id)
+        );
+
+        assert!(indexes.index_event(&second).await.to_store);
+        assert_eq!(
+            indexes
+                .get_replaceable(&keys_a.public_key(), Kind::Metadata)
+                .await,
+            Some(second.id)
+        );
+        assert_eq!(
+            indexes
+                .get_replaceable(&keys_b.public_key(), Kind::Metadata)
+                .await,
+            None
+        );
+
+        // Not replaceable
+        assert_eq!(
+            indexes
+                .get_replaceable(&keys_a.public_key(), Kind::TextNote)
+                .await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_tags_within_window() {
+        let me = Keys::generate();
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let mut notes: Vec<Event> = Vec::new();
+        for i in 0..10 {
+            let tags = [
+                Tag::public_key(me.public_key()),
+                Tag::hashtag(format!("tag{}", i % 2)),
+            ];
+            let note = EventBuilder::text_note(format!("Note {i}"), tags)
+                .custom_created_at(Timestamp::from(i * 10))
+                .to_event(&keys)
+                .unwrap();
+            assert!(indexes.index_event(&note).await.to_store);
+            notes.push(note);
+        }
+
+        // Bounds included
+        let filter = Filter::new()
+            .pubkey(me.public_key())
+            .since(Timestamp::from(30))
+            .until(Timestamp::from(60));
+        assert_eq!(
+            indexes.query([filter], Order::Desc).await,
+            vec![
+                notes[6].clone(),
+                notes[5].clone(),
+                notes[4].clone(),
+                notes[3].clone()
+            ]
+        );
+
+        // Values of the same tag merged within the window
+        let filter = Filter::new()
+            .hashtags(["tag0", "tag1"])
+            .since(Timestamp::from(75))
+            .until(Timestamp::from(85));
+        assert_eq!(
+            indexes.query([filter], Order::Desc).await,
+            vec![notes[8].clone()]
+        );
+
+        let filter = Filter::new()
+            .pubkey(me.public_key())
+            .since(Timestamp::from(91))
+            .until(Timestamp::from(99));
+        assert!(indexes.query([filter], Order::Desc).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_query_tag_not_indexed() {
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        let z = SingleLetterTag::lowercase(Alphabet::Z);
+        let missing = Filter::new().custom_tag(z, ["value"]);
+
+        // Whatever the other constraints, a tag that no event has matches nothing
+        for filter in [
+            missing.clone(),
+            missing.clone().author(keys_a.public_key()),
+            missing.clone().kind(Kind::TextNote),
+            missing
+                .clone()
+                .authors([keys_a.public_key()])
+                .kinds([Kind::TextNote, Kind::EventDeletion]),
+            missing.clone().pubkey(keys_a.public_key()),
+            missing.clone().identifier("multi-id"),
+            missing
+                .clone()
+                .since(Timestamp::from(0))
+                .until(Timestamp::now()),
+        ] {
+            assert!(indexes
+                .query([filter.clone()], Order::Desc)
+                .await
+                .is_empty());
+            assert_eq!(indexes.count([filter]).await, 0);
+        }
+
+        // Existing tag, but no event with the value
+        let filter = Filter::new()
+            .author(keys_a.public_key())
+            .identifier("unknown");
+        assert!(indexes.query([filter], Order::Desc).await.is_empty());
+
+        // Other filters still match
+        let text_note = Filter::new().kind(Kind::TextNote);
+        assert_eq!(
+            indexes.query([missing, text_note], Order::Desc).await,
+            vec![Event::from_json(EVENTS[0]).unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_with_deadline() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let mut ids: Vec<EventId> = Vec::new();
+        for i in 0..1000 {
+            let note = EventBuilder::text_note(format!("Note {i}"), [])
+                .custom_created_at(Timestamp::from(i))
+                .to_event(&keys)
+                .unwrap();
+            assert!(indexes.index_event(&note).await.to_store);
+            ids.push(note.id);
+        }
+        ids.reverse();
+
+        let deadline = Instant::now() + Duration::from_secs(60);
+        assert_eq!(
+            indexes.query_with_deadline(&Filter::new(), deadline).await,
+            Ok(ids)
+        );
+
+        // Deadline already reached
+        let deadline = Instant::now();
+        assert_eq!(
+            indexes.query_with_deadline(&Filter::new(), deadline).await,
+            Err(Error::QueryTimeout)
+        );
+
+        // Low selectivity: every event is visited, but none matches
+        let filter = Filter::new().kind(Kind::Metadata);
+        assert_eq!(
+            indexes.query_with_deadline(&filter, deadline).await,
+            Err(Error::QueryTimeout)
+        );
+        let deadline = Instant::now() + Duration::from_secs(60);
+        assert_eq!(
+            indexes.query_with_deadline(&filter, deadline).await,
+            Ok(Vec::new())
+        );
+
+        // No candidates to visit
+        let deadline = Instant::now();
+        let filter = Filter::new().author(Keys::generate().public_key());
+        assert_eq!(
+            indexes.query_with_deadline(&filter, deadline).await,
+            Ok(Vec::new())
+        );
+        assert_eq!(indexes.stats().await.events, 1000);
+    }
+
+    #[tokio::test]
+    async fn test_events_with_hashtag() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let note = |created_at: u64, tags: Vec<Tag>| {
+            EventBuilder::text_note(format!("Note {created_at}"), tags)
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap()
+        };
+        let first = note(1, vec![Tag::hashtag("nostr")]);
+        let second = note(2, vec![Tag::hashtag("nostr"), Tag::hashtag("nostr")]);
+        let third = note(3, vec![Tag::hashtag("rust")]);
+        let fourth = note(4, vec![Tag::hashtag("rust"), Tag::hashtag("nostr")]);
+        for ev in [&first, &second, &third, &fourth] {
+            assert!(indexes.index_event(ev).await.to_store);
+        }
+
+        assert_eq!(
+            indexes.events_with_hashtag("nostr", None).await,
+            vec![fourth.id, second.id, first.id]
+        );
+        assert_eq!(
+            indexes.events_with_hashtag("nostr", Some(2)).await,
+            vec![fourth.id, second.id]
+        );
+        assert_eq!(
+            indexes.events_with_hashtag("rust", None).await,
+            vec![fourth.id, third.id]
+        );
+        assert!(indexes.events_with_hashtag("Nostr", None).await.is_empty());
+
+        // Deleted
+        let deletion = EventBuilder::delete([fourth.id]).to_event(&keys).unwrap();
+        assert!(indexes.index_event(&deletion).await.to_store);
+        assert_eq!(
+            indexes.events_with_hashtag("nostr", None).await,
+            vec![second.id, first.id]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_ordered_by_received_at() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // Received out of order, the last one with a spoofed old timestamp
+        let mut received: Vec<Event> = Vec::new();
+        for created_at in [20, 30, 10, 1] {
+            let note = EventBuilder::text_note(format!("Note {created_at}"), [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap();
+            assert!(indexes.index_event(&note).await.to_store);
+            received.push(note);
+        }
+
+        let filter = Filter::new().author(keys.public_key());
+        let mut expected: Vec<Event> = received.iter().rev().cloned().collect();
+        assert_eq!(
+            indexes
+                .query_ordered_by([filter.clone()], Order::Desc, OrderBy::ReceivedAt)
+                .await,
+            expected
+        );
+        assert_eq!(
+            indexes
+                .query_ordered_by([Filter::new()], Order::Asc, OrderBy::ReceivedAt)
+                .await,
+            received
+        );
+        assert_eq!(
+            indexes
+                .query_ordered_by([filter.clone()], Order::Desc, OrderBy::CreatedAt)
+                .await,
+            indexes.query([filter.clone()], Order::Desc).await
+        );
+
+        // Kept by the snapshots
+        let restored = DatabaseHelper::deserialize(&indexes.serialize().await).unwrap();
+        assert_eq!(
+            restored
+                .query_ordered_by([filter.clone()], Order::Desc, OrderBy::ReceivedAt)
+                .await,
+            expected
+        );
+
+        // New events go to the top, whatever their timestamp
+        let old = EventBuilder::text_note("Old", [])
+            .custom_created_at(Timestamp::from(0))
+            .to_event(&keys)
+            .unwrap();
+        assert!(indexes.index_event(&old).await.to_store);
+        expected.insert(0, old);
+        assert_eq!(
+            indexes
+                .query_ordered_by([filter.clone()], Order::Desc, OrderBy::ReceivedAt)
+                .await,
+            expected
+        );
+
+        // A future-dated event doesn't take the slots of the `limit`
+        let future = EventBuilder::text_note("Future", [])
+            .custom_created_at(Timestamp::from(u32::MAX as u64))
+            .to_event(&keys)
+            .unwrap();
+        let indexes = DatabaseHelper::unbounded();
+        indexes.index_event(&future).await;
+        for ev in received.iter() {
+            indexes.index_event(ev).await;
+        }
+        assert_eq!(
+            indexes
+                .query_ordered_by([filter.limit(2)], Order::Desc, OrderBy::ReceivedAt)
+                .await,
+            vec![received[3].clone(), received[2].clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_min_created_at() {
+        let keys = Keys::generate();
+        let note = |created_at: u64| {
+            EventBuilder::text_note(format!("Note {created_at}"), [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap()
+        };
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // No floor by default
+        assert!(indexes.index_event(&note(1)).await.to_store);
+
+        indexes.set_min_created_at(Some(Timestamp::from(100))).await;
+        let old = note(99);
+        let res = indexes.index_event(&old).await;
+        assert_eq!(res.status, EventStatus::Rejected);
+        assert!(!res.to_store);
+        assert!(res.to_discard.is_empty());
+        assert!(!indexes.has_event(&old.id).await);
+        assert_eq!(indexes.evaluate(&old).await.status, EventStatus::Rejected);
+
+        // Floor included
+        assert!(indexes.index_event(&note(100)).await.to_store);
+
+        // Kept by the snapshots
+        let restored = DatabaseHelper::deserialize(&indexes.serialize().await).unwrap();
+        assert!(!restored.index_event(&old).await.to_store);
+
+        indexes.set_min_created_at(None).await;
+        assert!(indexes.index_event(&old).await.to_store);
+        assert_eq!(indexes.stats().await.events, 3);
+    }
+
+    #[tokio::test]
+    async fn test_index_event_detailed() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::bounded(2);
+
+        let metadata_1 = EventBuilder::metadata(&Metadata::new().name("account"))
+            .custom_created_at(Timestamp::from(10))
+            .to_event(&keys)
+            .unwrap();
+        let res = indexes.index_event_detailed(&metadata_1).await;
+        assert_eq!(res.result.status, EventStatus::Saved);
+        assert!(res.discarded.is_empty());
+
+        // Replaced
+        let metadata_2 = EventBuilder::metadata(&Metadata::new().name("account-2"))
+            .custom_created_at(Timestamp::from(20))
+            .to_event(&keys)
+            .unwrap();
+        let res = indexes.index_event_detailed(&metadata_2).await;
+        assert_eq!(res.result.status, EventStatus::Replaced);
+        assert_eq!(
+            res.discarded,
+            vec![DiscardedEvent {
+                id: metadata_1.id,
+                kind: Kind::Metadata,
+                author: keys.public_key(),
+                created_at: Timestamp::from(10),
+            }]
+        );
+        assert!(res.result.to_discard.contains(&metadata_1.id));
+
+        // Deleted
+        let note = EventBuilder::text_note("Note", [])
+            .custom_created_at(Timestamp::from(15))
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&note).await;
+        let deletion = EventBuilder::delete([note.id])
+            .custom_created_at(Timestamp::from(30))
+            .to_event(&keys)
+            .unwrap();
+        let res = indexes.index_event_detailed(&deletion).await;
+        assert_eq!(res.discarded, vec![DiscardedEvent::from(&note)]);
+
+        // Oldest event evicted
+        let newest = EventBuilder::text_note("Newest", [])
+            .custom_created_at(Timestamp::from(40))
+            .to_event(&keys)
+            .unwrap();
+        let res = indexes.index_event_detailed(&newest).await;
+        assert!(res.result.to_store);
+        assert_eq!(res.discarded, vec![DiscardedEvent::from(&metadata_2)]);
+
+        // Not stored
+        let res = indexes.index_event_detailed(&newest).await;
+        assert_eq!(res.result.status, EventStatus::Duplicate);
+        assert!(res.discarded.is_empty());
+    }
+
+    #[test]
+    fn test_into_local() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded().verifying();
+
+        // Shared
+        let shared = indexes.clone();
+        let indexes = indexes.into_local().unwrap_err();
+        drop(shared);
+
+        let local = indexes.into_local().unwrap();
+
+        let note = EventBuilder::text_note("Text note", [])
+            .to_event(&keys)
+            .unwrap();
+        let res = local.index_event(&note);
+        assert_eq!(res.status, EventStatus::Saved);
+        assert_eq!(local.index_event(&note).status, EventStatus::Duplicate);
+        assert!(local.has_event(&note.id));
+        assert_eq!(local.event_by_id(&note.id), Some(note.clone()));
+        assert_eq!(
+            local.query([Filter::new()], Order::Desc),
+            vec![note.clone()]
+        );
+        assert_eq!(local.count([Filter::new().author(keys.public_key())]), 1);
+
+        // Still verifying
+        let forged = Event::new(
+            note.id,
+            note.pubkey,
+            note.created_at,
+            note.kind,
+            note.tags.clone(),
+            "Forged",
+            note.signature(),
+        );
+        assert_eq!(local.index_event(&forged).status, EventStatus::Invalid);
+
+        assert!(local.remove_event(&note.id));
+        assert_eq!(local.stats().events, 0);
+    }
+
+    #[tokio::test]
+    async fn test_deleted_ids_tombstones() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let notes: Vec<Event> = (0..3)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"), [])
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+        for note in notes.iter() {
+            assert!(indexes.index_event(note).await.to_store);
+            let deletion = EventBuilder::delete([note.id]).to_event(&keys).unwrap();
+            assert!(indexes.index_event(&deletion).await.to_store);
+        }
+
+        // Delivered again after the deletion
+        for note in notes.iter() {
+            let res = indexes.index_event(note).await;
+            assert_eq!(res.status, EventStatus::Rejected);
+            assert!(!res.to_store);
+            assert!(indexes.has_event_id_been_deleted(&note.id).await);
+        }
+
+        // Oldest tombstones dropped, and their events accepted again
+        indexes.set_max_deleted_ids(Some(2)).await;
+        assert_eq!(indexes.stats().await.deleted_ids, 2);
+        assert!(!indexes.has_event_id_been_deleted(&notes[0].id).await);
+        assert!(indexes.has_event_id_been_deleted(&notes[1].id).await);
+        assert!(indexes.index_event(&notes[0]).await.to_store);
+
+        let other = EventBuilder::text_note("Other", [])
+            .to_event(&keys)
+            .unwrap();
+        let deletion = EventBuilder::delete([other.id]).to_event(&keys).unwrap();
+        indexes.index_event(&other).await;
+        indexes.index_event(&deletion).await;
+        assert_eq!(indexes.stats().await.deleted_ids, 2);
+        assert!(!indexes.has_event_id_been_deleted(&notes[1].id).await);
+
+        // Kept by the snapshots, in deletion order
+        let restored = DatabaseHelper::deserialize(&indexes.serialize().await).unwrap();
+        assert!(!restored.index_event(&notes[2]).await.to_store);
+        assert!(!restored.index_event(&other).await.to_store);
+        let another = EventBuilder::text_note("Another", [])
+            .to_event(&keys)
+            .unwrap();
+        let deletion = EventBuilder::delete([another.id]).to_event(&keys).unwrap();
+        restored.index_event(&another).await;
+        restored.index_event(&deletion).await;
+        assert!(!restored.has_event_id_been_deleted(&notes[2].id).await);
+    }
+
+    #[tokio::test]
+    async fn test_pending_deletions_limit() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+        indexes.set_max_deleted_ids(Some(2)).await;
+
+        // Deletions received before their events
+        let notes: Vec<Event> = (0..3)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"), [])
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+        for note in notes.iter() {
+            let deletion = EventBuilder::delete([note.id]).to_event(&keys).unwrap();
+            assert!(indexes.index_event(&deletion).await.to_store);
+        }
+
+        // The oldest request was dropped, also by the snapshots
+        let restored = DatabaseHelper::deserialize(&indexes.serialize().await).unwrap();
+        for indexes in [indexes, restored] {
+            assert!(indexes.index_event(&notes[0]).await.to_store);
+            assert_eq!(
+                indexes.index_event(&notes[1]).await.status,
+                EventStatus::Rejected
+            );
+            assert_eq!(
+                indexes.index_event(&notes[2]).await.status,
+                EventStatus::Rejected
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_approx_event_count() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::bounded(3);
+        assert_eq!(indexes.approx_event_count(), 0);
+
+        let notes: Vec<Event> = (0..4)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"), [])
+                    .custom_created_at(Timestamp::from(i))
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+        indexes.index_event(&notes[0]).await;
+        indexes.bulk_index(notes[1..3].to_vec()).await;
+        assert_eq!(indexes.approx_event_count(), 3);
+
+        // Evicted
+        indexes.index_event(&notes[3]).await;
+        assert_eq!(indexes.approx_event_count(), 3);
+
+        // Removed
+        indexes.remove_event(&notes[3].id).await;
+        assert_eq!(indexes.approx_event_count(), 2);
+        let deletion = EventBuilder::delete([notes[2].id]).to_event(&keys).unwrap();
+        indexes.index_event(&deletion).await;
+        assert_eq!(indexes.approx_event_count(), 2);
+
+        let restored = DatabaseHelper::deserialize(&indexes.serialize().await).unwrap();
+        assert_eq!(restored.approx_event_count(), 2);
+
+        indexes.clear().await;
+        assert_eq!(indexes.approx_event_count(), 0);
+        assert_eq!(restored.approx_event_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_events_with_same_content() {
+        let keys_a = Keys::generate();
+        let keys_b = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let note = |keys: &Keys, content: &str, created_at: u64| {
+            EventBuilder::text_note(content, [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(keys)
+                .unwrap()
+        };
+        let spam_a = note(&keys_a, "Buy now!", 1);
+        let spam_b = note(&keys_b, "Buy now!", 2);
+        let other = note(&keys_b, "Buy now", 3);
+        indexes
+            .bulk_index([spam_a.clone(), spam_b.clone(), other.clone()])
+            .await;
+
+        // Disabled by default
+        assert!(indexes.events_with_same_content(&spam_a).await.is_empty());
+
+        // Already indexed events added
+        indexes.set_content_hash_index(true).await;
+        assert_eq!(
+            indexes.events_with_same_content(&spam_a).await,
+            vec![spam_b.id]
+        );
+
+        let spam_c = note(&keys_a, "Buy now!", 4);
+        indexes.index_event(&spam_c).await;
+        assert_eq!(
+            indexes.events_with_same_content(&spam_c).await,
+            vec![spam_b.id, spam_a.id]
+        );
+        assert!(indexes.events_with_same_content(&other).await.is_empty());
+
+        // Not indexed event
+        let unknown = note(&keys_b, "Buy now!", 5);
+        assert_eq!(
+            indexes.events_with_same_content(&unknown).await,
+            vec![spam_c.id, spam_b.id, spam_a.id]
+        );
+
+        indexes.remove_event(&spam_b.id).await;
+        assert_eq!(
+            indexes.events_with_same_content(&spam_c).await,
+            vec![spam_a.id]
+        );
+
+        indexes.set_content_hash_index(false).await;
+        assert!(indexes.events_with_same_content(&spam_c).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_query_oldest() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // Two events per timestamp
+        let notes: Vec<Event> = (0..6)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"), [])
+                    .custom_created_at(Timestamp::from(i / 2))
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+        indexes.bulk_index(notes.clone()).await;
+
+        let filter = Filter::new().author(keys.public_key());
+        let asc: Vec<Event> = indexes.query([filter.clone()], Order::Asc).await;
+        let desc: Vec<Event> = indexes.query([filter.clone()], Order::Desc).await;
+        assert!(asc.windows(2).all(|w| w[0].created_at <= w[1].created_at));
+        assert_eq!(indexes.query_oldest([filter.clone()]).await, asc);
+        assert_eq!(indexes.query_oldest([Filter::new()]).await, asc);
+
+        // Oldest 3, splitting the events with timestamp 1
+        assert_eq!(
+            indexes.query_oldest([filter.clone().limit(3)]).await,
+            asc[..3]
+        );
+        assert_eq!(
+            indexes.query_oldest([Filter::new().limit(3)]).await,
+            asc[..3]
+        );
+
+        // Newest 3, splitting the events with timestamp 1
+        assert_eq!(
+            indexes.query([filter.clone().limit(3)], Order::Desc).await,
+            desc[..3]
+        );
+        assert_eq!(
+            indexes.query([filter.clone().limit(3)], Order::Asc).await,
+            asc[3..]
+        );
+
+        // Union of the oldest events of every filter
+        let until = Filter::new().until(Timestamp::from(1)).limit(1);
+        assert_eq!(
+            indexes.query_oldest([filter.clone().limit(2), until]).await,
+            asc[..2]
+        );
+        let since = Filter::new().since(Timestamp::from(2)).limit(1);
+        assert_eq!(
+            indexes.query_oldest([filter.limit(1), since]).await,
+            vec![asc[0].clone(), asc[4].clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_has_kind() {
+        let keys = Keys::generate();
 
-    const EVENTS: [&str; 14] = [
-        r#"{"id":"b7b1fb52ad8461a03e949820ae29a9ea07e35bcd79c95c4b59b0254944f62805","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704644581,"kind":1,"tags":[],"content":"Text note","sig":"ed73a8a4e7c26cd797a7b875c634d9ecb6958c57733305fed23b978109d0411d21b3e182cb67c8ad750884e30ca383b509382ae6187b36e76ee76e6a142c4284"}"#,
-        r#"{"id":"7296747d91c53f1d71778ef3e12d18b66d494a41f688ef244d518abf37c959b6","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704644586,"kind":32121,"tags":[["d","id-1"]],"content":"Empty 1","sig":"8848989a8e808f7315e950f871b231c1dff7752048f8957d4a541881d2005506c30e85c7dd74dab022b3e01329c88e69c9d5d55d961759272a738d150b7dbefc"}"#,
-        r#"{"id":"ec6ea04ba483871062d79f78927df7979f67545b53f552e47626cb1105590442","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704644591,"kind":32122,"tags":[["d","id-1"]],"content":"Empty 2","sig":"89946113a97484850fe35fefdb9120df847b305de1216dae566616fe453565e8707a4da7e68843b560fa22a932f81fc8db2b5a2acb4dcfd3caba9a91320aac92"}"#,
-        r#"{"id":"63b8b829aa31a2de870c3a713541658fcc0187be93af2032ec2ca039befd3f70","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704644596,"kind":32122,"tags":[["d","id-2"]],"content":"","sig":"607b1a67bef57e48d17df4e145718d10b9df51831d1272c149f2ab5ad4993ae723f10a81be2403ae21b2793c8ed4c129e8b031e8b240c6c90c9e6d32f62d26ff"}"#,
-        r#"{"id":"6fe9119c7db13ae13e8ecfcdd2e5bf98e2940ba56a2ce0c3e8fba3d88cd8e69d","pubkey":"79dff8f82963424e0bb02708a22e44b4980893e3a4be0fa3cb60a43b946764e3","created_at":1704644601,"kind":32122,"tags":[["d","id-3"]],"content":"","sig":"d07146547a726fc9b4ec8d67bbbe690347d43dadfe5d9890a428626d38c617c52e6945f2b7144c4e0c51d1e2b0be020614a5cadc9c0256b2e28069b70d9fc26e"}"#,
-        r#"{"id":"a82f6ebfc709f4e7c7971e6bf738e30a3bc112cfdb21336054711e6779fd49ef","pubkey":"79dff8f82963424e0bb02708a22e44b4980893e3a4be0fa3cb60a43b946764e3","created_at":1704644606,"kind":32122,"tags":[["d","id-1"]],"content":"","sig":"96d3349b42ed637712b4d07f037457ab6e9180d58857df77eb5fa27ff1fd68445c72122ec53870831ada8a4d9a0b484435f80d3ff21a862238da7a723a0d073c"}"#,
-        r#"{"id":"8ab0cb1beceeb68f080ec11a3920b8cc491ecc7ec5250405e88691d733185832","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704644611,"kind":32122,"tags":[["d","id-1"]],"content":"Test","sig":"49153b482d7110e2538eb48005f1149622247479b1c0057d902df931d5cea105869deeae908e4e3b903e3140632dc780b3f10344805eab77bb54fb79c4e4359d"}"#,
-        r#"{"id":"63dc49a8f3278a2de8dc0138939de56d392b8eb7a18c627e4d78789e2b0b09f2","pubkey":"79dff8f82963424e0bb02708a22e44b4980893e3a4be0fa3cb60a43b946764e3","created_at":1704644616,"kind":5,"tags":[["a","32122:aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4:"]],"content":"","sig":"977e54e5d57d1fbb83615d3a870037d9eb5182a679ca8357523bbf032580689cf481f76c88c7027034cfaf567ba9d9fe25fc8cd334139a0117ad5cf9fe325eef"}"#,
-        r#"{"id":"6975ace0f3d66967f330d4758fbbf45517d41130e2639b54ca5142f37757c9eb","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704644621,"kind":5,"tags":[["a","32122:aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4:id-2"]],"content":"","sig":"9bb09e4759899d86e447c3fa1be83905fe2eda74a5068a909965ac14fcdabaed64edaeb732154dab734ca41f2fc4d63687870e6f8e56e3d9e180e4a2dd6fb2d2"}"#,
-        r#"{"id":"33f5b4e6a38e107638c20f4536db35191d4b8651ba5a2cefec983b9ec2d65084","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704645586,"kind":0,"tags":[],"content":"{\"name\":\"Key A\"}","sig":"285d090f45a6adcae717b33771149f7840a8c27fb29025d63f1ab8d95614034a54e9f4f29cee9527c4c93321a7ebff287387b7a19ba8e6f764512a40e7120429"}"#,
-        r#"{"id":"90a761aec9b5b60b399a76826141f529db17466deac85696a17e4a243aa271f9","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704645606,"kind":0,"tags":[],"content":"{\"name\":\"key-a\",\"display_name\":\"Key A\",\"lud16\":\"keya@ln.address\"}","sig":"ec8f49d4c722b7ccae102d49befff08e62db775e5da43ef51b25c47dfdd6a09dc7519310a3a63cbdb6ec6b3250e6f19518eb47be604edeb598d16cdc071d3dbc"}"#,
-        r#"{"id":"a295422c636d3532875b75739e8dae3cdb4dd2679c6e4994c9a39c7ebf8bc620","pubkey":"79dff8f82963424e0bb02708a22e44b4980893e3a4be0fa3cb60a43b946764e3","created_at":1704646569,"kind":5,"tags":[["e","90a761aec9b5b60b399a76826141f529db17466deac85696a17e4a243aa271f9"]],"content":"","sig":"d4dc8368a4ad27eef63cacf667345aadd9617001537497108234fc1686d546c949cbb58e007a4d4b632c65ea135af4fbd7a089cc60ab89b6901f5c3fc6a47b29"}"#,
-        r#"{"id":"999e3e270100d7e1eaa98fcfab4a98274872c1f2dfdab024f32e42a5a12d5b5e","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1704646606,"kind":5,"tags":[["e","90a761aec9b5b60b399a76826141f529db17466deac85696a17e4a243aa271f9"]],"content":"","sig":"4f3a33fd52784cea7ca8428fd35d94d65049712e9aa11a70b1a16a1fcd761c7b7e27afac325728b1c00dfa11e33e78b2efd0430a7e4b28f4ede5b579b3f32614"}"#,
-        r#"{"id":"99a022e6d61c4e39c147d08a2be943b664e8030c0049325555ac1766429c2832","pubkey":"79dff8f82963424e0bb02708a22e44b4980893e3a4be0fa3cb60a43b946764e3","created_at":1705241093,"kind":30333,"tags":[["d","multi-id"],["p","aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4"]],"content":"Multi-tags","sig":"0abfb2b696a7ed7c9e8e3bf7743686190f3f1b3d4045b72833ab6187c254f7ed278d289d52dfac3de28be861c1471421d9b1bfb5877413cbc81c84f63207a826"}"#,
-    ];
+        let indexes = DatabaseHelper::bounded(3);
+        assert!(!indexes.has_kind(Kind::TextNote).await);
+        assert!(indexes.distinct_kinds().await.is_empty());
 
-    const REPLACEABLE_EVENT_1: &str = r#"{"id":"f06d755821e56fe9e25373d6bd142979ebdca0063bb0f10a95a95baf41bb5419","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1707478309,"kind":0,"tags":[],"content":"{\"name\":\"Test 1\"}","sig":"a095d9cf4f26794e6421445c0d1c4ada8273ad79a9809aaa20c566fc8d679b57f09889121050853c47be9222106abad0215705a80723f002fd47616ff6ba7bb9"}"#;
-    const REPLACEABLE_EVENT_2: &str = r#"{"id":"e0899bedc802a836c331282eddf712600fea8e00123b541e25a81aa6a4669b4a","pubkey":"aa4fc8665f5696e33db7e1a572e3b0f5b3d615837b0f362dcb1c8068b098c7b4","created_at":1707478348,"kind":0,"tags":[],"content":"{\"name\":\"Test 2\"}","sig":"ca1192ac72530010a895b4d76943bf373696a6969911c486c835995122cd59a46988026e8c0ad8322bc3f5942ecd633fc903e93c0460c9a186243ab1f1597a9c"}"#;
+        let note = EventBuilder::text_note("Note", [])
+            .custom_created_at(Timestamp::from(1))
+            .to_event(&keys)
+            .unwrap();
+        let metadata_1 = EventBuilder::metadata(&Metadata::new().name("account"))
+            .custom_created_at(Timestamp::from(2))
+            .to_event(&keys)
+            .unwrap();
+        indexes.bulk_index([note.clone(), metadata_1]).await;
+        assert!(indexes.has_kind(Kind::TextNote).await);
+        assert!(indexes.has_kind(Kind::Metadata).await);
+        assert_eq!(
+            indexes.distinct_kinds().await,
+            vec![Kind::Metadata, Kind::TextNote]
+        );
+
+        // Replaced
+        let metadata_2 = EventBuilder::metadata(&Metadata::new().name("account-2"))
+            .custom_created_at(Timestamp::from(3))
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&metadata_2).await;
+        assert_eq!(indexes.count_by_kind().await.get(&Kind::Metadata), Some(&1));
+
+        // Deleted
+        let deletion = EventBuilder::delete([note.id])
+            .custom_created_at(Timestamp::from(4))
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&deletion).await;
+        assert!(!indexes.has_kind(Kind::TextNote).await);
+        assert_eq!(
+            indexes.distinct_kinds().await,
+            vec![Kind::Metadata, Kind::EventDeletion]
+        );
+
+        // Evicted
+        for i in 0..2 {
+            let reaction = EventBuilder::new(Kind::Reaction, "+", [])
+                .custom_created_at(Timestamp::from(5 + i))
+                .to_event(&keys)
+                .unwrap();
+            indexes.index_event(&reaction).await;
+        }
+        assert!(!indexes.has_kind(Kind::Metadata).await);
+        assert_eq!(
+            indexes.distinct_kinds().await,
+            vec![Kind::EventDeletion, Kind::Reaction]
+        );
+        assert_eq!(indexes.stats().await.kinds, 2);
+    }
 
     #[tokio::test]
-    async fn test_database_indexes() {
-        // Keys
-        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
-        let keys_b = Keys::new(SecretKey::from_bech32(SECRET_KEY_B).unwrap());
+    async fn test_tag_index_empty_after_removal() {
+        let keys = Keys::generate();
+        let other = Keys::generate().public_key();
 
         let indexes = DatabaseHelper::unbounded();
 
-        // Build indexes
-        let mut events: BTreeSet<Event> = BTreeSet::new();
-        for event in EVENTS.into_iter() {
-            let event = Event::from_json(event).unwrap();
-            events.insert(event);
-        }
-        indexes.bulk_load(events).await;
+        let tags = [
+            Tag::public_key(other),
+            Tag::hashtag("nostr"),
+            Tag::hashtag("rust"),
+            Tag::identifier("id"),
+        ];
+        let note = EventBuilder::text_note("Text note", tags.clone())
+            .to_event(&keys)
+            .unwrap();
 
-        // Test expected output
-        let expected_output = vec![
-            Event::from_json(EVENTS[13]).unwrap(),
-            Event::from_json(EVENTS[12]).unwrap(),
-            Event::from_json(EVENTS[11]).unwrap(),
-            // Event 10 deleted by event 12
-            // Event 9 replaced by event 10
-            Event::from_json(EVENTS[8]).unwrap(),
-            Event::from_json(EVENTS[7]).unwrap(),
-            Event::from_json(EVENTS[6]).unwrap(),
-            Event::from_json(EVENTS[5]).unwrap(),
-            Event::from_json(EVENTS[4]).unwrap(),
-            // Event 3 deleted by Event 8
-            // Event 2 replaced by Event 6
-            Event::from_json(EVENTS[1]).unwrap(),
-            Event::from_json(EVENTS[0]).unwrap(),
+        // Removed
+        indexes.index_event(&note).await;
+        assert!(!indexes.read().await.tag_index.is_empty());
+        assert!(indexes.remove_event(&note.id).await);
+        assert!(indexes.read().await.tag_index.is_empty());
+
+        // Deleted
+        let note = EventBuilder::text_note("Other note", tags.clone())
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&note).await;
+        let deletion = EventBuilder::delete([note.id]).to_event(&keys).unwrap();
+        indexes.index_event(&deletion).await;
+        indexes.remove_event(&deletion.id).await;
+        assert!(indexes.read().await.tag_index.is_empty());
+
+        // Author cleared
+        let note = EventBuilder::text_note("Last note", tags)
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&note).await;
+        indexes.clear_author(&keys.public_key()).await;
+        let inner = indexes.read().await;
+        assert!(inner.tag_index.is_empty());
+        assert!(inner.search_index.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_explain() {
+        let keys_a = Keys::generate();
+        let keys_b = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let events = [
+            EventBuilder::text_note("A 1", [Tag::hashtag("nostr")]).to_event(&keys_a),
+            EventBuilder::text_note("A 2", [Tag::hashtag("nostr")]).to_event(&keys_a),
+            EventBuilder::text_note("A 3", []).to_event(&keys_a),
+            EventBuilder::text_note("B 1", [Tag::hashtag("nostr")]).to_event(&keys_b),
+            EventBuilder::metadata(&Metadata::new().name("B")).to_event(&keys_b),
         ];
+        for event in events {
+            indexes.index_event(&event.unwrap()).await;
+        }
+
+        let filter = Filter::new()
+            .author(keys_a.public_key())
+            .kind(Kind::TextNote)
+            .hashtag("nostr")
+            .limit(1);
+        let explain = indexes.explain(&filter).await;
+        assert_eq!(explain.index, QueryIndex::Tags);
         assert_eq!(
-            indexes.query([Filter::new()], Order::Desc).await,
-            expected_output
+            explain.constraints,
+            vec![
+                (String::from("authors"), 3),
+                (String::from("kinds"), 4),
+                (String::from("#t"), 3),
+            ]
         );
-        assert_eq!(indexes.count([Filter::new()]).await, 10);
+        assert_eq!(explain.candidates, vec![3, 3, 2]);
+        assert_eq!(explain.matched, 2);
+        assert_eq!(explain.returned, 1);
+        assert_eq!(explain.returned, indexes.count([filter]).await);
 
-        // Test get previously deleted replaceable event (check if was deleted by indexes)
+        let filter = Filter::new().search("a");
+        let explain = indexes.explain(&filter).await;
+        assert_eq!(explain.index, QueryIndex::Search);
+        assert_eq!(explain.constraints, vec![(String::from("search"), 3)]);
+        assert_eq!(explain.candidates, vec![3]);
+        assert_eq!(explain.matched, 3);
+
+        let explain = indexes.explain(&Filter::new()).await;
+        assert_eq!(explain.index, QueryIndex::Scan);
+        assert!(explain.constraints.is_empty());
+        assert!(explain.candidates.is_empty());
+        assert_eq!(explain.matched, 5);
+        assert_eq!(explain.returned, 5);
+    }
+
+    #[tokio::test]
+    async fn test_query_with_preferred_authors() {
+        let keys_a = Keys::generate();
+        let keys_b = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let now = Timestamp::now();
+        let older = now - Duration::from_secs(60);
+        let build = |keys: &Keys, content: &str, created_at: Timestamp| {
+            EventBuilder::text_note(content, [])
+                .custom_created_at(created_at)
+                .to_event(keys)
+                .unwrap()
+        };
+        let a_now = build(&keys_a, "A now", now);
+        let b_now = build(&keys_b, "B now", now);
+        let a_older = build(&keys_a, "A older", older);
+        let b_older = build(&keys_b, "B older", older);
+        for event in [&a_now, &b_now, &a_older, &b_older] {
+            indexes.index_event(event).await;
+        }
+
+        let ids = |events: Vec<Event>| events.into_iter().map(|e| e.id).collect::<Vec<_>>();
+        let preferred: HashSet<PublicKey> = HashSet::from([keys_b.public_key()]);
+
+        // Timestamp first, then preference
+        let events = indexes
+            .query_with_preferred_authors([Filter::new()], Order::Desc, &preferred)
+            .await;
+        assert_eq!(
+            ids(events),
+            vec![b_now.id, a_now.id, b_older.id, a_older.id]
+        );
+
+        let events = indexes
+            .query_with_preferred_authors([Filter::new()], Order::Asc, &preferred)
+            .await;
+        assert_eq!(
+            ids(events),
+            vec![b_older.id, a_older.id, b_now.id, a_now.id]
+        );
+
+        // Membership is unchanged
+        let filter = Filter::new().author(keys_a.public_key());
+        let events = indexes
+            .query_with_preferred_authors([filter.clone()], Order::Desc, &preferred)
+            .await;
+        assert_eq!(events, indexes.query([filter], Order::Desc).await);
+
+        // No preferred authors: same order of the query
+        let events = indexes
+            .query_with_preferred_authors([Filter::new()], Order::Desc, &HashSet::new())
+            .await;
+        assert_eq!(events, indexes.query([Filter::new()], Order::Desc).await);
+    }
+
+    #[tokio::test]
+    async fn test_reset_soft() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let note = |content: &str| {
+            EventBuilder::text_note(content, [])
+                .to_event(&keys)
+                .unwrap()
+        };
+        indexes.index_event(&note("1")).await;
+        indexes.index_event(&note("2")).await;
+
+        // The received order goes on after a soft reset
+        indexes.reset_soft().await;
+        assert_eq!(indexes.count([Filter::new()]).await, 0);
+        let event = note("3");
+        indexes.index_event(&event).await;
+        assert_eq!(indexes.read().await.received_index.get(&event.id), Some(&2));
+
+        // Restarted after a full clear
+        indexes.clear().await;
+        let event = note("4");
+        indexes.index_event(&event).await;
+        assert_eq!(indexes.read().await.received_index.get(&event.id), Some(&0));
+    }
+
+    #[tokio::test]
+    async fn test_query_diversified() {
+        let spammer = Keys::generate();
+        let authors: Vec<Keys> = (0..3).map(|_| Keys::generate()).collect();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let now = Timestamp::now();
+        let note = |keys: &Keys, secs: u64| {
+            EventBuilder::text_note(format!("{secs}"), [])
+                .custom_created_at(now - Duration::from_secs(secs))
+                .to_event(keys)
+                .unwrap()
+        };
+
+        // The spammer posted the newest 10 notes
+        let spam: Vec<Event> = (0..10).map(|secs| note(&spammer, secs)).collect();
+        let others: Vec<Event> = authors
+            .iter()
+            .enumerate()
+            .map(|(i, keys)| note(keys, 100 + i as u64))
+            .collect();
+        for event in spam.iter().chain(others.iter()) {
+            indexes.index_event(event).await;
+        }
+
+        let filter = Filter::new().kind(Kind::TextNote);
+        let ids = indexes.query_diversified(&filter, 5, 2).await;
+        assert_eq!(
+            ids,
+            vec![
+                spam[0].id,
+                spam[1].id,
+                others[0].id,
+                others[1].id,
+                others[2].id
+            ]
+        );
+
+        // Overall limit reached before the other authors
+        let ids = indexes.query_diversified(&filter, 2, 3).await;
+        assert_eq!(ids, vec![spam[0].id, spam[1].id]);
+
+        // The limit of the filter is applied after the cap
+        let ids = indexes
+            .query_diversified(&filter.clone().limit(3), 4, 1)
+            .await;
+        assert_eq!(ids, vec![spam[0].id, others[0].id, others[1].id]);
+        let ids = indexes
+            .query_diversified(&filter.clone().limit(0), 4, 1)
+            .await;
+        assert!(ids.is_empty());
+
+        let ids = indexes.query_diversified(&Filter::new(), 10, 0).await;
+        assert!(ids.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_policy() {
+        let banned = Keys::generate();
+        let keys = Keys::generate();
+
+        let banned_pubkey = banned.public_key();
+        let indexes = DatabaseHelper::unbounded().with_policy(move |event: &Event| {
+            if event.author() == banned_pubkey {
+                PolicyDecision::Reject(String::from("banned"))
+            } else {
+                PolicyDecision::Accept
+            }
+        });
+
+        let event = EventBuilder::text_note("Spam", [])
+            .to_event(&banned)
+            .unwrap();
+        let expected = DatabaseEventResult {
+            status: EventStatus::Blocked,
+            reason: Some(String::from("banned")),
+            ..Default::default()
+        };
+        assert_eq!(indexes.evaluate(&event).await, expected);
+        assert_eq!(indexes.index_event(&event).await, expected);
+        assert_eq!(indexes.bulk_index([event.clone()]).await, vec![expected]);
+        assert!(!indexes.has_event(&event.id).await);
+
+        let event = EventBuilder::text_note("Text note", [])
+            .to_event(&keys)
+            .unwrap();
+        let res = indexes.index_event(&event).await;
+        assert!(res.to_store);
+        assert_eq!(res.status, EventStatus::Saved);
+        assert!(res.reason.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let first = EventBuilder::text_note("First", [])
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&first).await;
+
+        let snapshot = indexes.snapshot().await;
+
+        // Writes aren't blocked by the snapshot and aren't visible in it
+        let second = EventBuilder::text_note("Second", [])
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&second).await;
+        indexes.remove_event(&first.id).await;
+
+        assert_eq!(snapshot.count([Filter::new()]), 1);
+        assert!(snapshot.has_event(&first.id));
+        assert!(!snapshot.has_event(&second.id));
+        assert_eq!(
+            snapshot.query([Filter::new()], Order::Desc),
+            vec![first.clone()]
+        );
+
+        assert_eq!(indexes.count([Filter::new()]).await, 1);
+        assert!(indexes.has_event(&second.id).await);
+        assert!(!indexes.has_event(&first.id).await);
+
+        // The snapshot doesn't keep the helper shared
+        drop(snapshot);
+        let local = indexes.into_local().unwrap();
+        assert!(local.has_event(&second.id));
+    }
+
+    #[tokio::test]
+    async fn test_references_coordinate() {
+        let author = Keys::generate();
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let article =
+            Coordinate::new(Kind::LongFormTextNote, author.public_key()).identifier("article");
+        let profile = Coordinate::new(Kind::Metadata, author.public_key());
+
+        let comment = EventBuilder::text_note("Comment", [Tag::coordinate(article.clone())])
+            .to_event(&keys)
+            .unwrap();
+        let reply = EventBuilder::text_note("Reply", [Tag::coordinate(article.clone())])
+            .custom_created_at(comment.created_at + Duration::from_secs(1))
+            .to_event(&keys)
+            .unwrap();
+        let mention = EventBuilder::text_note("Mention", [Tag::coordinate(profile.clone())])
+            .to_event(&keys)
+            .unwrap();
+        // Coordinate without the trailing `:`
+        let short = Tag::parse(&[String::from("a"), format!("0:{}", author.public_key())]).unwrap();
+        let short_mention = EventBuilder::text_note("Short mention", [short])
+            .custom_created_at(mention.created_at + Duration::from_secs(1))
+            .to_event(&keys)
+            .unwrap();
+        for event in [&comment, &reply, &mention, &short_mention] {
+            indexes.index_event(event).await;
+        }
+
+        assert_eq!(
+            indexes
+                .references_coordinate(
+                    Kind::LongFormTextNote,
+                    &author.public_key(),
+                    "article",
+                    None
+                )
+                .await,
+            vec![reply.id, comment.id]
+        );
+        assert_eq!(
+            indexes
+                .references_coordinate(
+                    Kind::LongFormTextNote,
+                    &author.public_key(),
+                    "article",
+                    Some(1)
+                )
+                .await,
+            vec![reply.id]
+        );
+        assert_eq!(
+            indexes
+                .references_coordinate(Kind::Metadata, &author.public_key(), "", None)
+                .await,
+            vec![short_mention.id, mention.id]
+        );
         assert!(indexes
-            .query(
-                [Filter::new()
-                    .kind(Kind::Metadata)
-                    .author(keys_a.public_key())],
-                Order::Desc
-            )
+            .references_coordinate(Kind::LongFormTextNote, &author.public_key(), "other", None)
             .await
             .is_empty());
+    }
+
+    #[cfg(feature = "index-timing")]
+    #[tokio::test]
+    async fn test_index_timings() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+        assert_eq!(indexes.stats().await.index_timings, IndexTimings::default());
+
+        for i in 0..100 {
+            let note = EventBuilder::text_note(format!("Note {i}"), [Tag::hashtag("nostr")])
+                .to_event(&keys)
+                .unwrap();
+            indexes.index_event(&note).await;
+        }
+
+        let timings: IndexTimings = indexes.stats().await.index_timings;
+        let total: Duration =
+            timings.created_at + timings.author + timings.kind + timings.tags + timings.content;
+        assert!(total > Duration::ZERO);
+
+        indexes.clear().await;
+        assert_eq!(indexes.stats().await.index_timings, IndexTimings::default());
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_tag_values() {
+        let keys = Keys::generate();
+        let other = Keys::generate().public_key();
+        let hex: String = other.to_hex();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        // Same `p` twice, many `t` values and the same value in a `t` tag
+        let event = EventBuilder::text_note(
+            "Text note",
+            [
+                Tag::public_key(other),
+                Tag::public_key(other),
+                Tag::hashtag("nostr"),
+                Tag::hashtag("rust"),
+                Tag::hashtag(hex.clone()),
+            ],
+        )
+        .to_event(&keys)
+        .unwrap();
+        indexes.index_event(&event).await;
+
+        {
+            let inner = indexes.read().await;
+            let p = inner
+                .tag_index
+                .get(&SingleLetterTag::lowercase(Alphabet::P))
+                .unwrap();
+            assert_eq!(p.len(), 1);
+            assert_eq!(p.get(&hex).unwrap().len(), 1);
+            let t = inner
+                .tag_index
+                .get(&SingleLetterTag::lowercase(Alphabet::T))
+                .unwrap();
+            assert_eq!(t.len(), 3);
+        }
+
+        // Returned once, also if more values match
+        let filter = Filter::new().pubkey(other);
+        assert_eq!(
+            indexes.query([filter], Order::Desc).await,
+            vec![event.clone()]
+        );
+        let filter = Filter::new().hashtags(["nostr", "rust"]);
+        assert_eq!(
+            indexes.query([filter], Order::Desc).await,
+            vec![event.clone()]
+        );
+
+        // Different tags are AND-ed
+        let filter = Filter::new().pubkey(other).hashtag("rust");
+        assert_eq!(indexes.count([filter]).await, 1);
+        let filter = Filter::new().pubkey(other).hashtag("other");
+        assert_eq!(indexes.count([filter]).await, 0);
+
+        // The value of a tag doesn't match other tags
+        let filter = Filter::new().hashtag(hex.clone());
+        assert_eq!(indexes.count([filter]).await, 1);
+        let filter = Filter::new().custom_tag(SingleLetterTag::lowercase(Alphabet::E), [hex]);
+        assert_eq!(indexes.count([filter]).await, 0);
+
+        assert!(indexes.remove_event(&event.id).await);
+        assert!(indexes.read().await.tag_index.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_query_recent_global() {
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: BTreeSet<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events).await;
+
+        for n in [0, 1, 5, 100] {
+            assert_eq!(
+                indexes.query_recent_global(n).await,
+                indexes.query([Filter::new().limit(n)], Order::Desc).await
+            );
+        }
+
+        // Replaced events aren't returned
+        let keys = Keys::generate();
+        let metadata = EventBuilder::metadata(&Metadata::new().name("old"))
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&metadata).await;
+        let new_metadata = EventBuilder::metadata(&Metadata::new().name("new"))
+            .custom_created_at(metadata.created_at + Duration::from_secs(1))
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&new_metadata).await;
+
+        let recent = indexes.query_recent_global(2).await;
+        assert_eq!(recent[0], new_metadata);
+        assert_ne!(recent[1], metadata);
+    }
+
+    #[tokio::test]
+    async fn test_query_recent_global_with_capacity() {
+        async fn assert_recent(indexes: &DatabaseHelper) {
+            for n in 0..=6 {
+                assert_eq!(
+                    indexes.query_recent_global(n).await,
+                    indexes.query([Filter::new().limit(n)], Order::Desc).await
+                );
+            }
+        }
+
+        let keys = Keys::generate();
+        let indexes = DatabaseHelper::unbounded();
+
+        let notes: Vec<Event> = (1..=4)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"), [])
+                    .custom_created_at(Timestamp::from(i))
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+        for note in notes.iter() {
+            indexes.index_event(note).await;
+        }
+
+        // Filled with the events already indexed
+        indexes.set_recent_capacity(Some(3)).await;
+        assert_recent(&indexes).await;
+
+        // Replaced inside the window
+        let metadata = EventBuilder::metadata(&Metadata::new().name("old"))
+            .custom_created_at(Timestamp::from(5))
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&metadata).await;
+        assert_recent(&indexes).await;
+        let new_metadata = EventBuilder::metadata(&Metadata::new().name("new"))
+            .custom_created_at(Timestamp::from(6))
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&new_metadata).await;
+        assert_recent(&indexes).await;
+
+        // Deleted and removed inside the window
+        let deletion = EventBuilder::delete([new_metadata.id])
+            .custom_created_at(Timestamp::from(3))
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&deletion).await;
+        assert_recent(&indexes).await;
+        assert!(indexes.remove_event(&notes[3].id).await);
+        assert_recent(&indexes).await;
+
+        // Kept by the snapshots
+        let indexes = DatabaseHelper::deserialize(&indexes.serialize().await).unwrap();
+        assert_recent(&indexes).await;
+
+        indexes.set_recent_capacity(None).await;
+        assert_recent(&indexes).await;
+    }
+
+    #[test]
+    fn test_validate_filter() {
+        let keys = Keys::generate();
+        let p = SingleLetterTag::lowercase(Alphabet::P);
+
+        assert_eq!(validate_filter(&Filter::new()), Ok(()));
+        let filter = Filter::new()
+            .author(keys.public_key())
+            .kind(Kind::TextNote)
+            .since(Timestamp::from(10))
+            .until(Timestamp::from(10))
+            .limit(10);
+        assert_eq!(validate_filter(&filter), Ok(()));
+
+        let filter = Filter::new()
+            .since(Timestamp::from(11))
+            .until(Timestamp::from(10));
+        assert_eq!(
+            validate_filter(&filter),
+            Err(FilterError::InvalidTimeWindow)
+        );
+        assert_eq!(
+            validate_filter(&Filter::new().limit(0)),
+            Err(FilterError::ZeroLimit)
+        );
+        assert_eq!(
+            validate_filter(&Filter::new().ids(Vec::<EventId>::new())),
+            Err(FilterError::EmptyIds)
+        );
+        assert_eq!(
+            validate_filter(&Filter::new().authors(Vec::<PublicKey>::new())),
+            Err(FilterError::EmptyAuthors)
+        );
+        assert_eq!(
+            validate_filter(&Filter::new().kinds(Vec::<Kind>::new())),
+            Err(FilterError::EmptyKinds)
+        );
+        assert_eq!(
+            validate_filter(&Filter::new().custom_tag(p, Vec::<String>::new())),
+            Err(FilterError::EmptyTagValues(p))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_empty_sets() {
+        let indexes = DatabaseHelper::unbounded();
+
+        // Build indexes
+        let events: Vec<Event> = EVENTS
+            .into_iter()
+            .map(|e| Event::from_json(e).unwrap())
+            .collect();
+        indexes.bulk_load(events.iter().cloned().collect()).await;
+
+        let keys_a = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+        let p = SingleLetterTag::lowercase(Alphabet::P);
+
+        let filters = [
+            Filter::new().ids(Vec::<EventId>::new()),
+            Filter::new().authors(Vec::<PublicKey>::new()),
+            Filter::new()
+                .authors(Vec::<PublicKey>::new())
+                .kind(Kind::Metadata),
+            Filter::new().kinds(Vec::<Kind>::new()),
+            Filter::new()
+                .author(keys_a.public_key())
+                .kinds(Vec::<Kind>::new()),
+            Filter::new().custom_tag(p, Vec::<String>::new()),
+            Filter::new().kinds(Vec::<Kind>::new()).search("note"),
+        ];
+
+        // The filters rejected by `validate_filter` match nothing
+        for filter in filters.into_iter() {
+            assert!(validate_filter(&filter).is_err());
+            assert!(indexes
+                .query(vec![filter.clone()], Order::Desc)
+                .await
+                .is_empty());
+            assert_eq!(indexes.count(vec![filter.clone()]).await, 0);
+            assert!(events.iter().all(|e| !event_matches(e, &filter)));
+        }
+    }
 
-        // Test get previously deleted param. replaceable event (check if was deleted by indexes)
-        assert!(indexes
-            .query(
-                [Filter::new()
-                    .kind(Kind::ParameterizedReplaceable(32122))
-                    .author(keys_a.public_key())
-                    .identifier("id-2")],
-                Order::Desc
-            )
-            .await
-            .is_empty());
+    #[tokio::test]
+    async fn test_merge() {
+        let keys_a = Keys::generate();
+        let keys_b = Keys::generate();
 
-        // Test get param replaceable events WITHOUT using indexes (identifier not passed)
-        // Test ascending order
+        let shard_1 = DatabaseHelper::unbounded();
+        let shard_2 = DatabaseHelper::unbounded();
+
+        let now = Timestamp::now();
+        let metadata = |keys: &Keys, name: &str, created_at: Timestamp| {
+            EventBuilder::metadata(&Metadata::new().name(name))
+                .custom_created_at(created_at)
+                .to_event(keys)
+                .unwrap()
+        };
+
+        // A replaceable event in each shard, newer in one or the other
+        let a_old = metadata(&keys_a, "a old", now - Duration::from_secs(10));
+        let a_new = metadata(&keys_a, "a new", now);
+        let b_new = metadata(&keys_b, "b new", now);
+        let b_old = metadata(&keys_b, "b old", now - Duration::from_secs(10));
+        shard_1.index_event(&a_old).await;
+        shard_1.index_event(&b_new).await;
+        shard_2.index_event(&a_new).await;
+        shard_2.index_event(&b_old).await;
+
+        // A note in one shard, deleted in the other
+        let note = EventBuilder::text_note("Text note", [])
+            .to_event(&keys_a)
+            .unwrap();
+        let deletion = EventBuilder::delete([note.id]).to_event(&keys_a).unwrap();
+        let url = Url::parse("wss://relay.example.com").unwrap();
+        shard_1.index_event(&note).await;
+        shard_2
+            .index_event_with_source(&deletion, url.clone())
+            .await;
+
+        let results = shard_1.merge(&shard_2).await;
+        assert_eq!(results.len(), 3);
+
+        assert!(shard_1.has_event(&a_new.id).await);
+        assert!(!shard_1.has_event(&a_old.id).await);
+        assert!(shard_1.has_event(&b_new.id).await);
+        assert!(!shard_1.has_event(&b_old.id).await);
+        assert!(!shard_1.has_event(&note.id).await);
+        assert!(shard_1.has_event_id_been_deleted(&note.id).await);
+        assert!(shard_1.has_event(&deletion.id).await);
         assert_eq!(
-            indexes
-                .query(
-                    [Filter::new()
-                        .kind(Kind::ParameterizedReplaceable(32122))
-                        .author(keys_b.public_key())],
-                    Order::Asc
-                )
+            shard_1
+                .query_from_sources([Filter::new()], Order::Desc, &HashSet::from([url]))
                 .await,
-            vec![
-                Event::from_json(EVENTS[4]).unwrap(),
-                Event::from_json(EVENTS[5]).unwrap(),
-            ]
+            vec![deletion]
         );
 
-        // Test get param replaceable events using indexes
+        // Merging again changes nothing
+        let count = shard_1.count([Filter::new()]).await;
+        shard_1.merge(&shard_2).await;
+        shard_1.merge(&shard_1.clone()).await;
+        assert_eq!(shard_1.count([Filter::new()]).await, count);
+
+        // The tombstones are kept also without the deletion event
+        let deleted_1 = EventBuilder::text_note("Deleted 1", [])
+            .to_event(&keys_a)
+            .unwrap();
+        let deleted_2 = EventBuilder::text_note("Deleted 2", [])
+            .to_event(&keys_a)
+            .unwrap();
+        let pending = EventBuilder::text_note("Pending", [])
+            .to_event(&keys_a)
+            .unwrap();
+        let deletion = EventBuilder::delete([deleted_1.id, deleted_2.id, pending.id])
+            .to_event(&keys_a)
+            .unwrap();
+        let shard_3 = DatabaseHelper::unbounded();
+        shard_3.index_event(&deleted_1).await;
+        shard_3.index_event(&deleted_2).await;
+        shard_3.index_event(&deletion).await;
+        shard_3.remove_event(&deletion.id).await;
+
+        let shard_4 = DatabaseHelper::unbounded();
+        shard_4.merge(&shard_3).await;
+        assert!(shard_4.has_event_id_been_deleted(&deleted_1.id).await);
+        assert!(shard_4.has_event_id_been_deleted(&deleted_2.id).await);
         assert_eq!(
-            indexes
-                .query(
-                    [Filter::new()
-                        .kind(Kind::ParameterizedReplaceable(32122))
-                        .author(keys_b.public_key())
-                        .identifier("id-3")],
-                    Order::Desc
-                )
-                .await,
-            vec![Event::from_json(EVENTS[4]).unwrap()]
+            shard_4.index_event(&pending).await.status,
+            EventStatus::Rejected
         );
 
-        assert_eq!(
-            indexes
-                .query([Filter::new().author(keys_a.public_key())], Order::Desc)
-                .await,
+        // Within `max_deleted_ids`
+        let shard_5 = DatabaseHelper::unbounded();
+        shard_5.set_max_deleted_ids(Some(1)).await;
+        shard_5.merge(&shard_3).await;
+        assert_eq!(shard_5.stats().await.deleted_ids, 1);
+    }
+
+    #[tokio::test]
+    async fn test_query_without_duplicates() {
+        let keys = Keys::generate();
+        let other = Keys::generate().public_key();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let event = EventBuilder::text_note(
+            "Text note",
+            [
+                Tag::public_key(other),
+                Tag::hashtag("nostr"),
+                Tag::hashtag("rust"),
+            ],
+        )
+        .to_event(&keys)
+        .unwrap();
+        let other_event = EventBuilder::text_note("Other note", [Tag::hashtag("nostr")])
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&event).await;
+        indexes.index_event(&other_event).await;
+
+        let filters: Vec<Vec<Filter>> = vec![
+            // Author also in ids
+            vec![Filter::new().id(event.id).author(keys.public_key())],
+            // Tag value matched two ways
+            vec![Filter::new().hashtags(["nostr", "rust"])],
+            vec![Filter::new()
+                .author(keys.public_key())
+                .hashtags(["nostr", "rust"])],
+            // Overlapping filters
             vec![
-                Event::from_json(EVENTS[12]).unwrap(),
-                Event::from_json(EVENTS[8]).unwrap(),
-                Event::from_json(EVENTS[6]).unwrap(),
-                Event::from_json(EVENTS[1]).unwrap(),
-                Event::from_json(EVENTS[0]).unwrap(),
-            ]
+                Filter::new().author(keys.public_key()),
+                Filter::new().pubkey(other),
+                Filter::new().hashtag("nostr"),
+                Filter::new().id(event.id),
+            ],
+            vec![Filter::new().search("note"), Filter::new().hashtag("rust")],
+        ];
+        for filters in filters.into_iter() {
+            let ids: Vec<EventId> = indexes
+                .query(filters, Order::Desc)
+                .await
+                .into_iter()
+                .map(|e| e.id)
+                .collect();
+            let unique: HashSet<EventId> = ids.iter().copied().collect();
+            assert_eq!(ids.len(), unique.len());
+            assert!(ids.contains(&event.id));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_indexed_tags() {
+        let keys = Keys::generate();
+        let other = Keys::generate().public_key();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let event = EventBuilder::text_note(
+            "Text note",
+            [
+                Tag::public_key(other),
+                Tag::hashtag("nostr"),
+                Tag::parse(&["R", "wss://relay.example.com"]).unwrap(),
+            ],
+        )
+        .to_event(&keys)
+        .unwrap();
+        indexes.index_event(&event).await;
+
+        // Tags index built again
+        indexes
+            .set_indexed_tags(Some(HashSet::from([Alphabet::P, Alphabet::R])))
+            .await;
+        let p = Filter::new().pubkey(other);
+        let t = Filter::new().hashtag("nostr");
+        let r = Filter::new().custom_tag(
+            SingleLetterTag::uppercase(Alphabet::R),
+            ["wss://relay.example.com"],
         );
+        assert_eq!(indexes.count([p.clone()]).await, 1);
+        assert_eq!(indexes.count([r.clone()]).await, 1);
+        assert_eq!(indexes.count([t.clone()]).await, 0);
+        assert!(indexes
+            .read()
+            .await
+            .tag_index
+            .get(&SingleLetterTag::lowercase(Alphabet::T))
+            .is_none());
 
         assert_eq!(
-            indexes
-                .query(
-                    [Filter::new()
-                        .author(keys_a.public_key())
-                        .kinds([Kind::TextNote, Kind::Custom(32121)])],
-                    Order::Desc
-                )
-                .await,
-            vec![
-                Event::from_json(EVENTS[1]).unwrap(),
-                Event::from_json(EVENTS[0]).unwrap(),
-            ]
+            indexes.try_query([p.clone()], Order::Desc).await,
+            Ok(vec![event.clone()])
         );
+        assert_eq!(
+            indexes.try_query([p.clone(), t.clone()], Order::Desc).await,
+            Err(Error::TagNotIndexed(SingleLetterTag::lowercase(
+                Alphabet::T
+            )))
+        );
+
+        // New events, snapshots and clear keep the letters
+        let other_event = EventBuilder::text_note("Other note", [Tag::hashtag("nostr")])
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&other_event).await;
+        assert_eq!(indexes.count([t.clone()]).await, 0);
+        let restored = DatabaseHelper::deserialize(&indexes.serialize().await).unwrap();
+        assert_eq!(restored.count([p.clone()]).await, 1);
+        assert_eq!(restored.count([t.clone()]).await, 0);
+        indexes.clear().await;
+        assert!(indexes.try_query([t.clone()], Order::Desc).await.is_err());
+
+        // All the tags indexed again
+        restored.set_indexed_tags(None).await;
+        assert_eq!(restored.count([t.clone()]).await, 2);
+        assert!(restored.try_query([t], Order::Desc).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_events_received_since() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let now = Timestamp::now();
+        let note = |content: &str, created_at: Timestamp| {
+            EventBuilder::text_note(content, [])
+                .custom_created_at(created_at)
+                .to_event(&keys)
+                .unwrap()
+        };
+        let first = note("First", now);
+        let second = note("Second", now);
+        indexes.index_event(&first).await;
+        indexes.index_event(&second).await;
+
+        let (ids, cursor) = indexes.events_received_since(0, 10).await;
+        assert_eq!(ids, vec![first.id, second.id]);
+        assert_eq!(cursor, 2);
 
+        // Nothing new
         assert_eq!(
-            indexes
-                .query(
-                    [Filter::new()
-                        .authors([keys_a.public_key(), keys_b.public_key()])
-                        .kinds([Kind::TextNote, Kind::Custom(32121)])],
-                    Order::Desc
-                )
-                .await,
-            vec![
-                Event::from_json(EVENTS[1]).unwrap(),
-                Event::from_json(EVENTS[0]).unwrap(),
-            ]
+            indexes.events_received_since(cursor, 10).await,
+            (vec![], cursor)
         );
 
-        // Test get param replaceable events using identifier
+        // Old event delivered late, and a deleted one
+        let late = note("Late", now - Duration::from_secs(3600));
+        indexes.index_event(&late).await;
+        let deleted = note("Deleted", now);
+        indexes.index_event(&deleted).await;
+        let deletion = EventBuilder::delete([deleted.id]).to_event(&keys).unwrap();
+        indexes.index_event(&deletion).await;
+
+        let (ids, next) = indexes.events_received_since(cursor, 1).await;
+        assert_eq!(ids, vec![late.id]);
+        let (ids, next) = indexes.events_received_since(next, 10).await;
+        assert_eq!(ids, vec![deletion.id]);
+
+        // Kept by the repair
+        indexes.repair().await;
         assert_eq!(
-            indexes
-                .query([Filter::new().identifier("id-1")], Order::Desc)
-                .await,
-            vec![
-                Event::from_json(EVENTS[6]).unwrap(),
-                Event::from_json(EVENTS[5]).unwrap(),
-                Event::from_json(EVENTS[1]).unwrap(),
-            ]
+            indexes.events_received_since(0, 10).await,
+            (vec![first.id, second.id, late.id, deletion.id], next)
         );
 
-        // Test get param replaceable events with multiple tags using identifier
+        // Kept by the snapshots: the cursors still work and the positions aren't reused
+        let restored = DatabaseHelper::deserialize(&indexes.serialize().await).unwrap();
         assert_eq!(
-            indexes
-                .query([Filter::new().identifier("multi-id")], Order::Desc)
-                .await,
-            vec![Event::from_json(EVENTS[13]).unwrap()]
+            restored.events_received_since(cursor, 10).await,
+            (vec![late.id, deletion.id], next)
         );
-        // As above but by using kind and pubkey
+        let after = note("After", now);
+        restored.index_event(&after).await;
         assert_eq!(
-            indexes
-                .query(
-                    [Filter::new()
-                        .pubkey(keys_a.public_key())
-                        .kind(Kind::Custom(30333))
-                        .limit(1)],
-                    Order::Desc
-                )
-                .await,
-            vec![Event::from_json(EVENTS[13]).unwrap()]
+            restored.events_received_since(next, 10).await,
+            (vec![after.id], next + 1)
         );
+    }
 
-        // Test add new replaceable event (metadata)
-        let first_ev_metadata = Event::from_json(REPLACEABLE_EVENT_1).unwrap();
-        let res = indexes.index_event(&first_ev_metadata).await;
-        assert!(res.to_store);
-        assert!(res.to_discard.is_empty());
+    #[tokio::test]
+    async fn test_deletion_by_coordinate() {
+        let keys = Keys::generate();
+        let other = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+
+        let kind = Kind::LongFormTextNote;
+        let now = Timestamp::now();
+        let article = |keys: &Keys, content: &str, created_at: Timestamp| {
+            EventBuilder::new(kind, content, [Tag::identifier("article")])
+                .custom_created_at(created_at)
+                .to_event(keys)
+                .unwrap()
+        };
+        let first = article(&keys, "First version", now - Duration::from_secs(20));
+        let second = article(&keys, "Second version", now - Duration::from_secs(10));
+        let others = article(&other, "Same identifier", now - Duration::from_secs(10));
+        indexes.index_event(&first).await;
+        indexes.index_event(&second).await;
+        indexes.index_event(&others).await;
+
+        // Delete all the versions at the coordinate
+        let coordinate = Coordinate::new(kind, keys.public_key()).identifier("article");
+        let deletion = EventBuilder::new(
+            Kind::EventDeletion,
+            "",
+            [Tag::coordinate(coordinate.clone())],
+        )
+        .custom_created_at(now)
+        .to_event(&keys)
+        .unwrap();
+        let res = indexes.index_event(&deletion).await;
+        assert_eq!(res.to_discard, HashSet::from([second.id]));
+        assert!(!indexes.has_event(&second.id).await);
+        assert!(indexes.has_coordinate_been_deleted(&coordinate, now).await);
+
+        // The events of other authors aren't deleted
+        assert!(indexes.has_event(&others.id).await);
+        let forged = EventBuilder::new(
+            Kind::EventDeletion,
+            "",
+            [Tag::coordinate(
+                Coordinate::new(kind, other.public_key()).identifier("article"),
+            )],
+        )
+        .to_event(&keys)
+        .unwrap();
+        indexes.index_event(&forged).await;
+        assert!(indexes.has_event(&others.id).await);
+
+        // An empty identifier deletes only the event without identifier
+        let named = EventBuilder::new(kind, "Named", [Tag::identifier("foo")])
+            .custom_created_at(now - Duration::from_secs(10))
+            .to_event(&other)
+            .unwrap();
+        let unnamed = EventBuilder::new(kind, "Unnamed", [])
+            .custom_created_at(now - Duration::from_secs(10))
+            .to_event(&other)
+            .unwrap();
+        indexes.index_event(&named).await;
+        indexes.index_event(&unnamed).await;
+        let empty = Coordinate::new(kind, other.public_key());
+        let deletion = EventBuilder::new(Kind::EventDeletion, "", [Tag::coordinate(empty)])
+            .custom_created_at(now)
+            .to_event(&other)
+            .unwrap();
+        let res = indexes.index_event(&deletion).await;
+        assert_eq!(res.to_discard, HashSet::from([unnamed.id]));
+        assert!(indexes.has_event(&named.id).await);
+        assert!(indexes.has_event(&others.id).await);
+        assert!(!indexes.has_event(&unnamed.id).await);
+        assert!(!indexes.index_event(&unnamed).await.to_store);
+
+        // Old versions are rejected, also if never seen before, newer ones are indexed
+        assert!(!indexes.index_event(&first).await.to_store);
+        assert!(!indexes.index_event(&second).await.to_store);
+        let unseen = article(&keys, "Unseen version", now - Duration::from_secs(5));
+        assert!(!indexes.index_event(&unseen).await.to_store);
+        let third = article(&keys, "Third version", now + Duration::from_secs(10));
+        assert!(indexes.index_event(&third).await.to_store);
+
+        // Replaceable events too
+        let metadata = EventBuilder::metadata(&Metadata::new().name("name"))
+            .custom_created_at(now - Duration::from_secs(10))
+            .to_event(&keys)
+            .unwrap();
+        indexes.index_event(&metadata).await;
+        let deletion = EventBuilder::new(
+            Kind::EventDeletion,
+            "",
+            [Tag::coordinate(Coordinate::new(
+                Kind::Metadata,
+                keys.public_key(),
+            ))],
+        )
+        .custom_created_at(now)
+        .to_event(&keys)
+        .unwrap();
         assert_eq!(
-            indexes
-                .query(
-                    [Filter::new()
-                        .kind(Kind::Metadata)
-                        .author(keys_a.public_key())],
-                    Order::Desc
-                )
-                .await,
-            vec![first_ev_metadata.clone()]
+            indexes.index_event(&deletion).await.to_discard,
+            HashSet::from([metadata.id])
         );
+        assert!(!indexes.index_event(&metadata).await.to_store);
+        let unseen = EventBuilder::metadata(&Metadata::new().name("unseen"))
+            .custom_created_at(now - Duration::from_secs(5))
+            .to_event(&keys)
+            .unwrap();
+        assert!(!indexes.index_event(&unseen).await.to_store);
+        let newer = EventBuilder::metadata(&Metadata::new().name("newer"))
+            .custom_created_at(now + Duration::from_secs(5))
+            .to_event(&keys)
+            .unwrap();
+        assert!(indexes.index_event(&newer).await.to_store);
+    }
+
+    #[tokio::test]
+    async fn test_prune_expired_before() {
+        let keys = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+        assert_eq!(indexes.next_expiration().await, None);
+
+        let now = Timestamp::now();
+        let expiring = |content: &str, secs: u64| {
+            EventBuilder::text_note(content, [Tag::expiration(now + Duration::from_secs(secs))])
+                .to_event(&keys)
+                .unwrap()
+        };
+        let later = expiring("Later", 200);
+        let sooner = expiring("Sooner", 100);
+        let removed = expiring("Removed", 50);
+        let note = EventBuilder::text_note("Text note", [])
+            .to_event(&keys)
+            .unwrap();
+        for event in [&later, &sooner, &removed, &note] {
+            indexes.index_event(event).await;
+        }
 
-        // Test add replace metadata
-        let ev = Event::from_json(REPLACEABLE_EVENT_2).unwrap();
-        let res = indexes.index_event(&ev).await;
-        assert!(res.to_store);
-        assert!(res.to_discard.contains(&first_ev_metadata.id));
         assert_eq!(
-            indexes
-                .query(
-                    [Filter::new()
-                        .kind(Kind::Metadata)
-                        .author(keys_a.public_key())],
-                    Order::Desc
-                )
-                .await,
-            vec![ev]
+            indexes.next_expiration().await,
+            Some(now + Duration::from_secs(50))
+        );
+        indexes.remove_event(&removed.id).await;
+        assert_eq!(
+            indexes.next_expiration().await,
+            Some(now + Duration::from_secs(100))
         );
+
+        // Nothing expired yet
+        assert!(indexes.prune_expired_before(now).await.is_empty());
+        assert!(indexes
+            .prune_expired_before(now + Duration::from_secs(100))
+            .await
+            .is_empty());
+
+        // Sorted by expiration
+        let ids = indexes
+            .prune_expired_before(now + Duration::from_secs(300))
+            .await;
+        assert_eq!(ids, vec![sooner.id, later.id]);
+        assert_eq!(indexes.next_expiration().await, None);
+        assert!(!indexes.has_event(&sooner.id).await);
+        assert!(!indexes.has_event(&later.id).await);
+        assert!(indexes.has_event(&note.id).await);
+
+        // Expired, not deleted
+        assert!(!indexes.has_event_id_been_deleted(&sooner.id).await);
+        assert_eq!(indexes.stats().await.deleted_ids, 0);
+    }
+
+    #[tokio::test]
+    async fn test_tags_query_window() {
+        let keys = Keys::generate();
+        let me = Keys::generate();
+
+        let indexes = DatabaseHelper::unbounded();
+        let mut in_window: Vec<Event> = Vec::new();
+        for created_at in [10, 20, 20, 20, 30] {
+            let note = EventBuilder::text_note(
+                format!("Note {created_at} {}", in_window.len()),
+                [Tag::public_key(me.public_key())],
+            )
+            .custom_created_at(Timestamp::from(created_at))
+            .to_event(&keys)
+            .unwrap();
+            indexes.index_event(&note).await;
+            if created_at == 20 {
+                in_window.push(note);
+            }
+        }
+        in_window.sort_by_key(event_sort_key);
+
+        // All the events of the boundary second, whatever their ID
+        let filter = Filter::new()
+            .pubkey(me.public_key())
+            .since(Timestamp::from(20))
+            .until(Timestamp::from(20));
+        assert_eq!(indexes.query(vec![filter], Order::Desc).await, in_window);
+
+        // Empty windows
+        let filter = Filter::new()
+            .pubkey(me.public_key())
+            .since(Timestamp::from(21))
+            .until(Timestamp::from(29));
+        assert!(indexes.query(vec![filter], Order::Desc).await.is_empty());
+        let filter = Filter::new()
+            .pubkey(me.public_key())
+            .since(Timestamp::from(30))
+            .until(Timestamp::from(10));
+        assert!(indexes.query(vec![filter], Order::Desc).await.is_empty());
+    }
+}
+
+#[cfg(bench)]
+mod benches {
+    use nostr::{EventBuilder, Keys, Tag};
+    use test::{black_box, Bencher};
+
+    use super::*;
+
+    const AUTHORS: usize = 100;
+    const NOTES_PER_AUTHOR: usize = 100;
+
+    fn mentions_helper() -> (InternalDatabaseHelper, Filter) {
+        let me = Keys::generate();
+        let authors: Vec<Keys> = (0..AUTHORS).map(|_| Keys::generate()).collect();
+
+        let mut helper = InternalDatabaseHelper::default();
+        for keys in authors.iter() {
+            for i in 0..NOTES_PER_AUTHOR {
+                // Mention me only in some notes
+                let tags = if i % 10 == 0 {
+                    vec![Tag::public_key(me.public_key())]
+                } else {
+                    vec![Tag::public_key(Keys::generate().public_key())]
+                };
+                let note = EventBuilder::text_note("Note", tags)
+                    .custom_created_at(Timestamp::from(i as u64))
+                    .to_event(keys)
+                    .unwrap();
+                helper.index_event(&note);
+            }
+        }
+
+        let filter = Filter::new()
+            .authors(authors.iter().take(10).map(|k| k.public_key()))
+            .kind(Kind::TextNote)
+            .pubkey(me.public_key());
+
+        (helper, filter)
+    }
+
+    fn following_helper() -> (InternalDatabaseHelper, Filter) {
+        let authors: Vec<Keys> = (0..5000).map(|_| Keys::generate()).collect();
+
+        let mut helper = InternalDatabaseHelper::default();
+        for (n, keys) in authors.iter().enumerate() {
+            for i in 0..10 {
+                let note = EventBuilder::text_note("Note", [])
+                    .custom_created_at(Timestamp::from((n * 10 + i) as u64))
+                    .to_event(keys)
+                    .unwrap();
+                helper.index_event(&note);
+            }
+        }
+
+        let filter = Filter::new()
+            .authors(authors.iter().map(|k| k.public_key()))
+            .limit(50);
+
+        (helper, filter)
+    }
+
+    #[bench]
+    pub fn query_p_tag_one_hour_window(bh: &mut Bencher) {
+        let me = Keys::generate();
+        let keys = Keys::generate();
+
+        // A mention every minute for a week
+        let mut helper = InternalDatabaseHelper::default();
+        for i in 0..7 * 24 * 60 {
+            let note = EventBuilder::text_note("Note", [Tag::public_key(me.public_key())])
+                .custom_created_at(Timestamp::from(i * 60))
+                .to_event(&keys)
+                .unwrap();
+            helper.index_event(&note);
+        }
+
+        let filter = Filter::new()
+            .pubkey(me.public_key())
+            .since(Timestamp::from(3 * 24 * 60 * 60))
+            .until(Timestamp::from(3 * 24 * 60 * 60 + 60 * 60));
+        bh.iter(|| {
+            black_box(helper.query(vec![filter.clone()], Order::Desc));
+        });
+    }
+
+    #[bench]
+    pub fn query_authors_and_p_tag_locked(bh: &mut Bencher) {
+        let (helper, filter) = mentions_helper();
+        let helper = DatabaseHelper {
+            inner: Arc::new(RwLock::new(Arc::new(helper))),
+            event_count: Arc::default(),
+            observer: None,
+            policy: None,
+            verify: false,
+        };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        bh.iter(|| {
+            black_box(rt.block_on(helper.query(vec![filter.clone()], Order::Desc)));
+        });
+    }
+
+    #[bench]
+    pub fn index_event_while_querying_snapshot(bh: &mut Bencher) {
+        let (helper, filter) = mentions_helper();
+        let helper = DatabaseHelper {
+            inner: Arc::new(RwLock::new(Arc::new(helper))),
+            event_count: Arc::default(),
+            observer: None,
+            policy: None,
+            verify: false,
+        };
+        let keys = Keys::generate();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        // A long query holding a snapshot (a read lock, before): the writes aren't blocked
+        let snapshot = rt.block_on(helper.snapshot());
+        let query = std::thread::spawn(move || {
+            for _ in 0..1000 {
+                black_box(snapshot.query(vec![filter.clone()], Order::Desc));
+            }
+        });
+
+        bh.iter(|| {
+            let note = EventBuilder::text_note("Note", []).to_event(&keys).unwrap();
+            black_box(rt.block_on(helper.index_event(&note)));
+        });
+
+        query.join().unwrap();
+    }
+
+    #[bench]
+    pub fn index_event_after_every_snapshot(bh: &mut Bencher) {
+        let (helper, _) = mentions_helper();
+        let helper = DatabaseHelper {
+            inner: Arc::new(RwLock::new(Arc::new(helper))),
+            event_count: Arc::default(),
+            observer: None,
+            policy: None,
+            verify: false,
+        };
+        let keys = Keys::generate();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        // Worst case: every write clones the state referenced by a snapshot
+        bh.iter(|| {
+            let snapshot = rt.block_on(helper.snapshot());
+            let note = EventBuilder::text_note("Note", []).to_event(&keys).unwrap();
+            black_box(rt.block_on(helper.index_event(&note)));
+            black_box(snapshot);
+        });
+    }
+
+    #[bench]
+    pub fn query_many_authors(bh: &mut Bencher) {
+        let (helper, filter) = following_helper();
+        bh.iter(|| {
+            black_box(helper.query(vec![filter.clone()], Order::Desc));
+        });
+    }
+
+    #[bench]
+    pub fn query_many_authors_generic(bh: &mut Bencher) {
+        let (helper, filter) = following_helper();
+        bh.iter(|| {
+            black_box(
+                helper
+                    .internal_generic_query(filter.clone(), &visit_all)
+                    .take(50)
+                    .count(),
+            );
+        });
+    }
+
+    #[bench]
+    pub fn query_authors_and_p_tag(bh: &mut Bencher) {
+        let (helper, filter) = mentions_helper();
+        bh.iter(|| {
+            black_box(helper.query(vec![filter.clone()], Order::Desc));
+        });
+    }
+
+    #[bench]
+    pub fn query_mentions_and_feed(bh: &mut Bencher) {
+        let (helper, mentions) = mentions_helper();
+        let feed = Filter::new().kind(Kind::TextNote).limit(500);
+        bh.iter(|| {
+            black_box(helper.query(vec![mentions.clone(), feed.clone()], Order::Desc));
+        });
+    }
+
+    #[bench]
+    pub fn query_authors_and_p_tag_generic(bh: &mut Bencher) {
+        let (helper, filter) = mentions_helper();
+        bh.iter(|| {
+            black_box(
+                helper
+                    .internal_generic_query(filter.clone(), &visit_all)
+                    .count(),
+            );
+        });
     }
 }
@@ -7,6 +7,10 @@
 #![warn(missing_docs)]
 #![warn(rustdoc::bare_urls)]
 #![allow(clippy::mutable_key_type)] // TODO: remove when possible. Needed to suppress false positive for `BTreeSet<Event>`
+#![cfg_attr(bench, feature(test))]
+
+#[cfg(bench)]
+extern crate test;
 
 use core::fmt;
 use std::collections::{BTreeSet, HashSet};
@@ -29,7 +33,13 @@ mod util;
 pub use self::error::DatabaseError;
 #[cfg(feature = "flatbuf")]
 pub use self::flatbuffers::{FlatBufferBuilder, FlatBufferDecode, FlatBufferEncode};
-pub use self::helper::{DatabaseEventResult, DatabaseHelper};
+pub use self::helper::{
+    Cursor, DatabaseEventResult, DatabaseHelper, DatabaseHelperObserver, DatabaseHelperSnapshot,
+    DatabaseHelperStats, DetailedEventResult, DiscardedEvent, EventStatus, LocalDatabaseHelper,
+    OrderBy, PolicyDecision, QueryExplain, QueryIndex, QueryIter,
+};
+#[cfg(feature = "index-timing")]
+pub use self::helper::IndexTimings;
 pub use self::memory::{MemoryDatabase, MemoryDatabaseOptions};
 pub use self::profile::Profile;
 
@@ -5,8 +5,12 @@
 #![allow(dead_code)]
 
 use std::borrow::Borrow;
-use std::collections::btree_set::Iter;
 use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::RangeBounds;
+
+use im::ordset::{Iter, RangedIter};
+use im::OrdSet;
 
 /// Represents the possible options for removing a value.
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -45,17 +49,45 @@ pub struct InsertResult<T> {
     pub pop: Option<T>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct BTreeCappedSet<T> {
-    set: BTreeSet<T>,
+    /// Persistent set: cloning it is `O(1)`, the nodes are shared until modified
+    set: OrdSet<T>,
     capacity: Capacity,
 }
 
-impl<T> Default for BTreeCappedSet<T> {
+impl<T> fmt::Debug for BTreeCappedSet<T>
+where
+    T: Ord + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BTreeCappedSet")
+            .field("set", &self.set)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl<T> Clone for BTreeCappedSet<T>
+where
+    T: Ord + Clone,
+{
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            set: self.set.clone(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl<T> Default for BTreeCappedSet<T>
+where
+    T: Ord,
+{
     #[inline]
     fn default() -> Self {
         Self {
-            set: BTreeSet::new(),
+            set: OrdSet::new(),
             capacity: Capacity::default(),
         }
     }
@@ -63,12 +95,12 @@ impl<T> Default for BTreeCappedSet<T> {
 
 impl<T> BTreeCappedSet<T>
 where
-    T: Ord,
+    T: Ord + Clone,
 {
     #[inline]
     pub fn bounded(max: usize) -> Self {
         Self {
-            set: BTreeSet::new(),
+            set: OrdSet::new(),
             capacity: Capacity::bounded(max),
         }
     }
@@ -76,7 +108,7 @@ where
     #[inline]
     pub fn bounded_with_policy(max: usize, policy: OverCapacityPolicy) -> Self {
         Self {
-            set: BTreeSet::new(),
+            set: OrdSet::new(),
             capacity: Capacity::Bounded { max, policy },
         }
     }
@@ -84,7 +116,7 @@ where
     #[inline]
     pub fn unbounded() -> Self {
         Self {
-            set: BTreeSet::new(),
+            set: OrdSet::new(),
             capacity: Capacity::Unbounded,
         }
     }
@@ -102,8 +134,8 @@ where
             Capacity::Bounded { max, policy } if self.set.len() > max => {
                 while self.set.len() != max {
                     match policy {
-                        OverCapacityPolicy::First => self.set.pop_first(),
-                        OverCapacityPolicy::Last => self.set.pop_last(),
+                        OverCapacityPolicy::First => self.set.remove_min(),
+                        OverCapacityPolicy::Last => self.set.remove_max(),
                     };
                 }
             }
@@ -136,11 +168,11 @@ where
             Capacity::Bounded { max, policy } if self.set.len() >= max => {
                 // Get the last value and compare it to the new value without popping
                 let should_insert: bool = match policy {
-                    OverCapacityPolicy::First => match self.set.first() {
+                    OverCapacityPolicy::First => match self.set.get_min() {
                         Some(first) => &value > first,
                         None => true,
                     },
-                    OverCapacityPolicy::Last => match self.set.last() {
+                    OverCapacityPolicy::Last => match self.set.get_max() {
                         Some(last) => &value < last,
                         None => true,
                     },
@@ -149,10 +181,10 @@ where
                 if should_insert {
                     // Pop the value if the new value should be inserted
                     InsertResult {
-                        inserted: self.set.insert(value),
+                        inserted: self.set.insert(value).is_none(),
                         pop: match policy {
-                            OverCapacityPolicy::First => self.set.pop_first(),
-                            OverCapacityPolicy::Last => self.set.pop_last(),
+                            OverCapacityPolicy::First => self.set.remove_min(),
+                            OverCapacityPolicy::Last => self.set.remove_max(),
                         },
                     }
                 } else {
@@ -166,26 +198,85 @@ where
             _ => {
                 // Insert value
                 InsertResult {
-                    inserted: self.set.insert(value),
+                    inserted: self.set.insert(value).is_none(),
                     pop: None,
                 }
             }
         }
     }
 
+    /// Get what [`BTreeCappedSet::insert`] would do, without modifying the set
+    ///
+    /// The `removed` values are considered as already removed from the set.
+    pub fn insert_outcome<'a>(&'a self, value: &T, removed: &BTreeSet<&T>) -> InsertResult<&'a T> {
+        let len: usize = self.set.len().saturating_sub(removed.len());
+        let present: bool = self.set.contains(value) && !removed.contains(value);
+        match self.capacity {
+            // Bounded capacity and limit reached
+            Capacity::Bounded { max, policy } if len >= max => {
+                let mut remaining = self.set.iter().filter(|v| !removed.contains(v));
+                match policy {
+                    OverCapacityPolicy::First => match remaining.next() {
+                        Some(first) if value > first => InsertResult {
+                            inserted: !present,
+                            pop: Some(first),
+                        },
+                        Some(_) => InsertResult {
+                            inserted: false,
+                            pop: None,
+                        },
+                        None => InsertResult {
+                            inserted: true,
+                            pop: None,
+                        },
+                    },
+                    OverCapacityPolicy::Last => match remaining.next_back() {
+                        Some(last) if value < last => InsertResult {
+                            inserted: !present,
+                            pop: Some(last),
+                        },
+                        Some(_) => InsertResult {
+                            inserted: false,
+                            pop: None,
+                        },
+                        None => InsertResult {
+                            inserted: true,
+                            pop: None,
+                        },
+                    },
+                }
+            }
+            // Unbounded capacity or bounded capacity not reached
+            _ => InsertResult {
+                inserted: !present,
+                pop: None,
+            },
+        }
+    }
+
     #[inline]
     pub fn remove<Q>(&mut self, value: &Q) -> bool
     where
         T: Borrow<Q> + Ord,
         Q: Ord,
     {
-        self.set.remove(value)
+        self.set.remove(value).is_some()
     }
 
     #[inline]
     pub fn iter(&self) -> Iter<'_, T> {
         self.set.iter()
     }
+
+    #[inline]
+    pub fn range<K, R>(&self, range: R) -> RangedIter<'_, T>
+    where
+        T: Borrow<K>,
+        K: Ord + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.set.range(range)
+    }
 }
 
 #[cfg(test)]
@@ -246,6 +337,30 @@ mod tests {
         assert_eq!(set.len(), 2);
     }
 
+    #[test]
+    fn test_insert_outcome() {
+        let mut set = BTreeCappedSet::bounded_with_policy(2, OverCapacityPolicy::Last);
+        set.insert(1);
+        set.insert(2);
+
+        let res = set.insert_outcome(&0, &BTreeSet::new());
+        assert!(res.inserted);
+        assert_eq!(res.pop, Some(&2));
+
+        let res = set.insert_outcome(&3, &BTreeSet::new());
+        assert!(!res.inserted);
+        assert_eq!(res.pop, None);
+
+        // 2 will be removed before the insertion
+        let removed = BTreeSet::from([&2]);
+        let res = set.insert_outcome(&3, &removed);
+        assert!(res.inserted);
+        assert_eq!(res.pop, None);
+
+        // Not modified
+        assert_eq!(set.len(), 2);
+    }
+
     #[test]
     fn test_remove() {
         let mut set = BTreeCappedSet::bounded(3);
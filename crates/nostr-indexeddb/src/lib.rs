@@ -275,6 +275,7 @@ impl_nostr_database!({
         let DatabaseEventResult {
             to_store,
             to_discard,
+            ..
         } = self.helper.index_event(event).await;
 
         if to_store {
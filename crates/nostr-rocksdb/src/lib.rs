@@ -175,6 +175,7 @@ impl NostrDatabase for RocksDatabase {
         let DatabaseEventResult {
             to_store,
             to_discard,
+            ..
         } = self.helper.index_event(event).await;
 
         if to_store {
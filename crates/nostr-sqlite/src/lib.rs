@@ -136,6 +136,7 @@ impl NostrDatabase for SQLiteDatabase {
         let DatabaseEventResult {
             to_store,
             to_discard,
+            ..
         } = self.helper.index_event(event).await;
 
         if !to_discard.is_empty() {